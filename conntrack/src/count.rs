@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+use crate::Table;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Count {
+    // The table is omitted when the count is not bound to a specific table in the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<Table>,
+    pub count: u32,
+    // The change from the previous count when the count is watched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<i64>,
+}
+
+impl Count {
+    pub fn new(count: u32) -> Count {
+        Count {
+            table: None,
+            count,
+            delta: None,
+        }
+    }
+
+    pub fn with_table(table: Table, count: u32) -> Count {
+        Count {
+            table: Some(table),
+            count,
+            delta: None,
+        }
+    }
+
+    // Set the change from the previous count. The first count has no change.
+    pub fn delta(mut self, prev: Option<u32>) -> Count {
+        self.delta = prev.map(|prev| self.count as i64 - prev as i64);
+        self
+    }
+}
@@ -9,6 +9,10 @@ use crate::flow::FlowError;
 pub enum Error {
     #[error("failed to create socket: {0}")]
     Socket(io::Error),
+    #[error("failed to switch network namespace: {0}")]
+    Netns(io::Error),
+    #[error("netlink portid {0} is already used by another socket in this process")]
+    PortIdInUse(u32),
     #[error("invalid family: {0}")]
     InvalidFamily(String),
     #[error("invalid l4 protocol: {0}")]
@@ -31,12 +35,77 @@ pub enum Error {
     NetlinkMessage(NetlinkError),
     #[error("flow error: {0}")]
     Flow(FlowError),
+    #[error("failed to read the conntrack table from procfs: {0}")]
+    Proc(io::Error),
     #[error("message error: {0}")]
     Message(String),
+    // Some minimal kernels reply statistics messages without counters.
+    #[error("conntrack counters are not available")]
+    CounterNotAvailable,
+    #[error("timed out while discarding the rest of a dump")]
+    DrainTimeout,
     #[error("dummy")]
     Dummy,
 }
 
+// A category of errors for callers which branch on the cause without matching every variant.
+// New kinds may be added, so a wildcard arm is required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Permission,
+    NotFound,
+    AlreadyExists,
+    InvalidInput,
+    Io,
+    Decode,
+    Timeout,
+    Unsupported,
+    Other,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Socket(e)
+            | Error::Netns(e)
+            | Error::Send(e)
+            | Error::Recv(e)
+            | Error::Poll(e)
+            | Error::Proc(e) => io_kind(e),
+            Error::InvalidFamily(_)
+            | Error::InvalidL4Protocol(_)
+            | Error::InvalidTable(_)
+            | Error::InvalidTcpState(_)
+            | Error::InvalidCtState(_) => ErrorKind::InvalidInput,
+            Error::Netfilter(_) | Error::Flow(_) | Error::Message(_) => ErrorKind::Decode,
+            Error::NetlinkMessage(e) => match e {
+                NetlinkError::OperationNotPermitted => ErrorKind::Permission,
+                NetlinkError::NoEntry => ErrorKind::NotFound,
+                NetlinkError::IO => ErrorKind::Io,
+                NetlinkError::AlreadyExists => ErrorKind::AlreadyExists,
+                NetlinkError::InvalidArgument => ErrorKind::InvalidInput,
+                NetlinkError::Other(_) => ErrorKind::Other,
+            },
+            Error::CounterNotAvailable => ErrorKind::Unsupported,
+            Error::PortIdInUse(_) => ErrorKind::AlreadyExists,
+            Error::DrainTimeout => ErrorKind::Timeout,
+            Error::Dummy => ErrorKind::Other,
+        }
+    }
+}
+
+fn io_kind(e: &io::Error) -> ErrorKind {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => ErrorKind::Permission,
+        io::ErrorKind::NotFound => ErrorKind::NotFound,
+        io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+        io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+        _ => ErrorKind::Io,
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum NetlinkError {
     #[error("operation not permitted")]
@@ -65,3 +134,30 @@ impl From<i32> for NetlinkError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use rstest::rstest;
+
+    use super::{Error, ErrorKind, NetlinkError};
+
+    #[rstest(
+        err,
+        expected,
+        case(Error::Socket(io::Error::from_raw_os_error(1)), ErrorKind::Permission),
+        case(Error::Recv(io::Error::from(io::ErrorKind::TimedOut)), ErrorKind::Timeout),
+        case(Error::Send(io::Error::from(io::ErrorKind::BrokenPipe)), ErrorKind::Io),
+        case(Error::NetlinkMessage(NetlinkError::from(-2)), ErrorKind::NotFound),
+        case(Error::NetlinkMessage(NetlinkError::from(-1)), ErrorKind::Permission),
+        case(Error::InvalidTable(String::from("foo")), ErrorKind::InvalidInput),
+        case(Error::Message(String::from("foo")), ErrorKind::Decode),
+        case(Error::CounterNotAvailable, ErrorKind::Unsupported),
+        case(Error::PortIdInUse(100), ErrorKind::AlreadyExists),
+        case(Error::DrainTimeout, ErrorKind::Timeout),
+    )]
+    fn test_error_kind(err: Error, expected: ErrorKind) {
+        assert_eq!(err.kind(), expected);
+    }
+}
@@ -1,12 +1,9 @@
 use netlink_packet_netfilter::ctnetlink::{message::CtNetlinkMessage, nlas::stat::nla::StatNla};
 
-use crate::{
-    error::Error,
-    flow::{Flow, FlowBuilder},
-    message::{Message, MessageType},
-    stats::Stats,
-};
+use crate::{error::Error, flow::Flow, message::Message, stats::Stats};
 
+// Most events are flows. Boxing them only adds an allocation for each flow.
+#[allow(clippy::large_enum_variant)]
 pub enum Event {
     Flow(Flow),
     Count(u32),
@@ -17,27 +14,16 @@ impl TryFrom<&Message> for Event {
     type Error = Error;
 
     fn try_from(msg: &Message) -> Result<Self, Self::Error> {
-        // This constant is defined in Linux kernel (linux/netlink.h)
-        const NLM_F_CREATE: u16 = 0x400;
+        Event::from_message(msg, false)
+    }
+}
+
+impl Event {
+    pub(crate) fn from_message(msg: &Message, keep_raw: bool) -> Result<Event, Error> {
         match &msg.msg {
-            CtNetlinkMessage::New(nlas) => {
-                let mut builder = FlowBuilder::try_from(nlas).map_err(Error::Flow)?;
-                builder = if msg.flag & NLM_F_CREATE != 0 {
-                    builder.event_type(MessageType::New)
-                } else {
-                    builder.event_type(MessageType::Update)
-                };
-                let flow = builder.build().map_err(Error::Flow)?;
-                Ok(Event::Flow(flow))
-            }
-            CtNetlinkMessage::Delete(nlas) => {
-                let flow = FlowBuilder::try_from(nlas)
-                    .map_err(Error::Flow)?
-                    .event_type(MessageType::Destroy)
-                    .build()
-                    .map_err(Error::Flow)?;
-                Ok(Event::Flow(flow))
-            }
+            CtNetlinkMessage::New(_) | CtNetlinkMessage::Delete(_) => Ok(Event::Flow(
+                Flow::from_message(msg, keep_raw).map_err(Error::Flow)?,
+            )),
             CtNetlinkMessage::GetStats(Some(nlas)) => {
                 let counter = nlas
                     .iter()
@@ -45,12 +31,15 @@ impl TryFrom<&Message> for Event {
                         StatNla::Searched(c) => Some(*c),
                         _ => None,
                     })
-                    .ok_or(Error::Message("failed to get the counter".to_string()))?;
+                    .ok_or(Error::CounterNotAvailable)?;
                 Ok(Event::Count(counter))
             }
-            CtNetlinkMessage::GetStatsCPU(Some(nlas)) => {
+            CtNetlinkMessage::GetStatsCPU(Some(nlas)) if !nlas.is_empty() => {
                 Ok(Event::Stats(Stats::from_nlas(msg.res_id, nlas)))
             }
+            CtNetlinkMessage::GetStats(_) | CtNetlinkMessage::GetStatsCPU(_) => {
+                Err(Error::CounterNotAvailable)
+            }
             _ => Err(Error::Message(format!(
                 "unknown message type: {}",
                 msg.msg.message_type()
@@ -1,21 +1,39 @@
-use std::{collections::HashSet, net::IpAddr, str::FromStr};
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
-use netlink_packet_netfilter::ctnetlink::{
-    message::CtNetlinkMessage,
-    nlas::{
-        ct_attr::CtAttr,
-        flow::{
-            ip_tuple::{IpTupleBuilder, ProtocolTupleBuilder, TupleNla},
-            nla::FlowNla,
-            protocol_info::{ProtocolInfo, ProtocolInfoTcp},
-            status::{ConnectionStatus, ConnectionStatusFlag},
+use netlink_packet_core::{NetlinkMessage, NetlinkPayload};
+use netlink_packet_netfilter::{
+    ctnetlink::{
+        message::CtNetlinkMessage,
+        nlas::{
+            ct_attr::CtAttr,
+            flow::{
+                ip_tuple::{IpTupleBuilder, ProtocolTupleBuilder, TupleNla},
+                nla::FlowNla,
+                protocol_info::{ProtocolInfo, ProtocolInfoTcp},
+                status::{ConnectionStatus, ConnectionStatusFlag},
+            },
         },
     },
+    NetfilterMessage, NetfilterMessageInner,
+};
+use netlink_packet_utils::{
+    nla::{NlasIterator, NLA_TYPE_MASK},
+    parsers::{parse_ip, parse_string, parse_u16_be, parse_u32_be, parse_u64, parse_u8},
+    DecodeError,
 };
-use netlink_packet_utils::DecodeError;
-use serde::{ser::SerializeSeq, Serialize};
+use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize};
 
-use crate::message::{Message, MessageType};
+use crate::{
+    error::{Error, NetlinkError},
+    message::{Message, MessageType},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum FlowError {
@@ -25,15 +43,24 @@ pub enum FlowError {
     MissingField(String),
     #[error("invalid tcp state: {0}")]
     InvalidTcpState(String),
+    #[error("invalid sctp state: {0}")]
+    InvalidSctpState(String),
+    #[error("invalid dccp state: {0}")]
+    InvalidDccpState(String),
+    #[error("invalid state: {0}")]
+    InvalidProtoState(String),
     #[error("invalid L4 protocol: {0}")]
     InvalidL4Protocol(String),
     #[error("invalid ct state: {0}")]
     InvalidCtState(String),
     #[error("netlink error: {0}")]
     Netlink(DecodeError),
+    #[error("invalid flow format: {0}")]
+    InvalidFormat(String),
 }
 
-#[derive(Debug, Clone, Serialize)]
+// Flows are deserialized from the JSON output, e.g. to replay captured output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Flow {
     pub event_type: MessageType,
     pub original: Tuple,
@@ -42,31 +69,287 @@ pub struct Flow {
     pub mark: Option<u32>,
     pub r#use: Option<u32>,
     pub tcp_state: Option<TcpState>,
+    // Protocol specific state. tcp_state is kept for compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proto_info: Option<ProtoInfo>,
     pub status: Status,
     pub timeout: u32,
+    pub secctx: Option<String>,
+    // The kernel assigns a unique id to each entry.
+    pub id: Option<u32>,
+    // Counters are available only when accounting is enabled (net.netfilter.nf_conntrack_acct=1).
+    pub orig_counter: Option<Counter>,
+    pub reply_counter: Option<Counter>,
+    // The original tuple of the master connection when the flow is created by a helper like FTP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub master: Option<Tuple>,
+    // ICMP type, code and id of the original tuple. The id pairs an echo request with its reply.
+    // netlink_packet_netfilter drops CTA_PROTO_ICMP_* while parsing CTA_TUPLE_PROTO,
+    // so they are read from the attributes kept in Message. See Message::with_bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icmp: Option<Icmp>,
+    // Attributes that are not parsed into the fields above.
+    // This is populated only when ConntrackOption::keep_raw is set.
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_raw",
+        deserialize_with = "deserialize_raw"
+    )]
+    pub raw: Vec<(u16, Vec<u8>)>,
+}
+
+impl Flow {
+    // A related flow is created from an expectation of a helper such as FTP data or SIP RTP.
+    pub fn is_related(&self) -> bool {
+        self.status.contains(ConnectionStatusFlag::Expected)
+    }
+
+    // Some kernels report a half-created entry whose reply tuple is zeroed.
+    // Protocols without ports such as ICMP have zero ports in both tuples, so zero reply ports are orphan only when the original has ports.
+    pub fn has_orphan_reply(&self) -> bool {
+        let (original, reply) = (&self.original, &self.reply);
+        reply.src_addr.is_unspecified()
+            || reply.dst_addr.is_unspecified()
+            || (reply.src_port == 0
+                && reply.dst_port == 0
+                && (original.src_port != 0 || original.dst_port != 0))
+    }
+
+    // The state of a DCCP flow. None for flows of other protocols.
+    pub fn dccp_state(&self) -> Option<DccpState> {
+        match &self.proto_info {
+            Some(ProtoInfo::Dccp { state, .. }) => Some(*state),
+            _ => None,
+        }
+    }
+
+    // A key to order flows deterministically regardless of the order of dump messages.
+    // Flows are ordered by the protocol and then by the original tuple.
+    pub fn sort_key(&self) -> (Protocol, IpAddr, u16, IpAddr, u16) {
+        (
+            self.protocol,
+            self.original.src_addr,
+            self.original.src_port,
+            self.original.dst_addr,
+            self.original.dst_port,
+        )
+    }
+
+    // The absolute time when the entry expires if no more packets are seen.
+    // The timeout is the remaining seconds at the time of the dump or the event, so `now` should be that time.
+    // Entries with FIXED_TIMEOUT don't expire by timeout, so check the status before showing it.
+    pub fn expires_at(&self, now: SystemTime) -> SystemTime {
+        now + Duration::from_secs(self.timeout as u64)
+    }
+}
+
+// A flow with flat keys which line up with the table columns, e.g. `orig_src_addr` instead of `original.src_addr`.
+#[derive(Debug, Serialize)]
+pub struct FlatFlow<'a> {
+    pub event_type: MessageType,
+    pub orig_src_addr: IpAddr,
+    pub orig_dst_addr: IpAddr,
+    pub orig_src_port: u16,
+    pub orig_dst_port: u16,
+    pub reply_src_addr: IpAddr,
+    pub reply_dst_addr: IpAddr,
+    pub reply_src_port: u16,
+    pub reply_dst_port: u16,
+    pub protocol: Protocol,
+    pub mark: Option<u32>,
+    pub r#use: Option<u32>,
+    pub tcp_state: Option<TcpState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proto_info: Option<&'a ProtoInfo>,
+    pub status: &'a Status,
+    pub timeout: u32,
+    pub secctx: Option<&'a str>,
+    pub id: Option<u32>,
+    pub orig_packets: Option<u64>,
+    pub orig_bytes: Option<u64>,
+    pub reply_packets: Option<u64>,
+    pub reply_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub master: Option<&'a Tuple>,
+    #[serde(
+        skip_serializing_if = "<[_]>::is_empty",
+        serialize_with = "serialize_raw"
+    )]
+    pub raw: &'a [(u16, Vec<u8>)],
+}
+
+impl<'a> From<&'a Flow> for FlatFlow<'a> {
+    fn from(flow: &'a Flow) -> Self {
+        FlatFlow {
+            event_type: flow.event_type,
+            orig_src_addr: flow.original.src_addr,
+            orig_dst_addr: flow.original.dst_addr,
+            orig_src_port: flow.original.src_port,
+            orig_dst_port: flow.original.dst_port,
+            reply_src_addr: flow.reply.src_addr,
+            reply_dst_addr: flow.reply.dst_addr,
+            reply_src_port: flow.reply.src_port,
+            reply_dst_port: flow.reply.dst_port,
+            protocol: flow.protocol,
+            mark: flow.mark,
+            r#use: flow.r#use,
+            tcp_state: flow.tcp_state,
+            proto_info: flow.proto_info.as_ref(),
+            status: &flow.status,
+            timeout: flow.timeout,
+            secctx: flow.secctx.as_deref(),
+            id: flow.id,
+            orig_packets: flow.orig_counter.map(|c| c.packets),
+            orig_bytes: flow.orig_counter.map(|c| c.bytes),
+            reply_packets: flow.reply_counter.map(|c| c.packets),
+            reply_bytes: flow.reply_counter.map(|c| c.bytes),
+            master: flow.master.as_ref(),
+            raw: &flow.raw,
+        }
+    }
+}
+
+/// The canonical text form of a flow is a space separated list of `key=value` fields.
+///
+/// `event=<event> proto=<protocol> orig=<src>-><dst> reply=<src>-><dst> [state=<tcp state>] status=<flags> timeout=<timeout> [mark=<mark>] [use=<use>]`
+///
+/// Addresses are formatted with ports like `1.1.1.1:1234` or `[fd00::1]:1234`,
+/// and status flags are joined with `,` like `SEEN_REPLY,ASSURED`.
+/// Fields in brackets are omitted when they are not set.
+///
+/// e.g. `event=update proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 state=ESTABLISHED status=ASSURED timeout=1000 mark=0 use=1`
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "event={} proto={} orig={} reply={}",
+            String::from(self.event_type).to_lowercase(),
+            String::from(self.protocol),
+            self.original,
+            self.reply
+        )?;
+        if let Some(state) = self.tcp_state {
+            write!(f, " state={}", String::from(state))?;
+        }
+        write!(f, " status={} timeout={}", self.status, self.timeout)?;
+        if let Some(mark) = self.mark {
+            write!(f, " mark={mark}")?;
+        }
+        if let Some(u) = self.r#use {
+            write!(f, " use={u}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Flow {
+    type Err = FlowError;
+
+    // Parse the canonical text form written by the Display implementation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut builder = FlowBuilder::default();
+        let mut timeout = None;
+        for field in s.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or(FlowError::InvalidFormat(field.to_string()))?;
+            builder = match key {
+                "event" => builder.event_type(parse_message_type(value)?),
+                "proto" => builder.protocol(parse_protocol(value)?),
+                "orig" => builder.original(Tuple::from_str(value)?),
+                "reply" => builder.reply(Tuple::from_str(value)?),
+                "state" => builder.tcp_state(TcpState::try_from(value)?),
+                "status" => builder.status(parse_status(value)?),
+                "timeout" => {
+                    let t = parse_number(field, value)?;
+                    timeout = Some(t);
+                    builder.timeout(t)
+                }
+                "mark" => builder.mark(parse_number(field, value)?),
+                "use" => builder.r#use(parse_number(field, value)?),
+                _ => return Err(FlowError::InvalidFormat(field.to_string())),
+            };
+        }
+        // The builder zeroes the timeout of events other than update, so the parsed one is kept here.
+        let mut flow = builder.build()?;
+        if let Some(timeout) = timeout {
+            flow.timeout = timeout;
+        }
+        Ok(flow)
+    }
+}
+
+fn parse_message_type(s: &str) -> Result<MessageType, FlowError> {
+    match s.to_lowercase().as_str() {
+        "new" => Ok(MessageType::New),
+        "update" => Ok(MessageType::Update),
+        "destroy" => Ok(MessageType::Destroy),
+        _ => Err(FlowError::InvalidFormat(s.to_string())),
+    }
+}
+
+fn parse_protocol(s: &str) -> Result<Protocol, FlowError> {
+    // Protocol::Other(v) is formatted as "other(v)".
+    match s.strip_prefix("other(").and_then(|v| v.strip_suffix(')')) {
+        Some(v) => v
+            .parse::<u8>()
+            .map(Protocol::from)
+            .map_err(|_| FlowError::InvalidL4Protocol(s.to_string())),
+        None => Protocol::try_from(s),
+    }
+}
+
+fn parse_status(s: &str) -> Result<Status, FlowError> {
+    Status::try_from(s)
+}
+
+fn parse_number<T: FromStr>(field: &str, value: &str) -> Result<T, FlowError> {
+    value
+        .parse::<T>()
+        .map_err(|_| FlowError::InvalidFormat(field.to_string()))
 }
 
 impl TryFrom<&Message> for Flow {
     type Error = FlowError;
 
     fn try_from(msg: &Message) -> Result<Self, Self::Error> {
+        Flow::from_message(msg, false)
+    }
+}
+
+impl Flow {
+    // The single path from a message to a flow shared by Event and TryFrom<&Message>.
+    pub(crate) fn from_message(msg: &Message, keep_raw: bool) -> Result<Flow, FlowError> {
         // This constant is defined in Linux kernel (linux/netlink.h)
         const NLM_F_CREATE: u16 = 0x400;
-        match &msg.msg {
-            CtNetlinkMessage::New(nlas) => {
-                let mut builder = FlowBuilder::try_from(nlas)?;
-                builder = if msg.flag & NLM_F_CREATE != 0 {
-                    builder.event_type(MessageType::New)
-                } else {
-                    builder.event_type(MessageType::Update)
-                };
-                builder.build()
-            }
-            CtNetlinkMessage::Delete(nlas) => FlowBuilder::try_from(nlas)?
-                .event_type(MessageType::Destroy)
-                .build(),
-            _ => Err(FlowError::InvalidMessageType(msg.msg.message_type())),
+        let (nlas, event_type) = match &msg.msg {
+            CtNetlinkMessage::New(nlas) if msg.flag & NLM_F_CREATE != 0 => (nlas, MessageType::New),
+            CtNetlinkMessage::New(nlas) => (nlas, MessageType::Update),
+            CtNetlinkMessage::Delete(nlas) => (nlas, MessageType::Destroy),
+            _ => return Err(FlowError::InvalidMessageType(msg.msg.message_type())),
+        };
+        let mut builder = FlowBuilder::from_nlas(nlas, keep_raw)?;
+        if let Some(icmp) = parse_icmp(&msg.attrs)? {
+            builder = builder.icmp(icmp);
         }
+        builder.event_type(event_type).build()
+    }
+}
+
+// Parse a flow from a single ctnetlink message including its netlink header.
+// This allows replaying captured messages and fuzzing the parser without a socket.
+pub fn parse_flow(bytes: &[u8]) -> Result<Flow, Error> {
+    let msg = <NetlinkMessage<NetfilterMessage>>::deserialize(bytes).map_err(Error::Netfilter)?;
+    let flag = msg.header.flags;
+    match msg.payload {
+        NetlinkPayload::InnerMessage(NetfilterMessage {
+            header,
+            inner: NetfilterMessageInner::CtNetlink(ct),
+        }) => Flow::try_from(&Message::new(ct, flag, header.res_id).with_bytes(bytes))
+            .map_err(Error::Flow),
+        NetlinkPayload::Error(e) => Err(Error::NetlinkMessage(NetlinkError::from(e.raw_code()))),
+        _ => Err(Error::Message("not a ctnetlink message".to_string())),
     }
 }
 
@@ -128,13 +411,28 @@ impl TryFrom<&Flow> for CtNetlinkMessage {
         ]));
         // protocol info
         let protocol_info = match flow.protocol {
-            Protocol::Tcp => ProtocolInfo::Tcp(ProtocolInfoTcp {
-                state: flow.tcp_state.unwrap().into(),
-                wscale_original: 0,
-                wscale_reply: 0,
-                flgas_original: 0,
-                flags_reply: 0,
-            }),
+            Protocol::Tcp => match flow.proto_info {
+                Some(ProtoInfo::Tcp {
+                    state,
+                    flags_original,
+                    flags_reply,
+                    wscale_original,
+                    wscale_reply,
+                }) => ProtocolInfo::Tcp(ProtocolInfoTcp {
+                    state: state.into(),
+                    wscale_original,
+                    wscale_reply,
+                    flgas_original: flags_original,
+                    flags_reply,
+                }),
+                _ => ProtocolInfo::Tcp(ProtocolInfoTcp {
+                    state: flow.tcp_state.unwrap().into(),
+                    wscale_original: 0,
+                    wscale_reply: 0,
+                    flgas_original: 0,
+                    flags_reply: 0,
+                }),
+            },
             _ => ProtocolInfo::Other(CtAttr {
                 nested: None,
                 attr_type: 0,
@@ -157,7 +455,7 @@ impl TryFrom<&Flow> for CtNetlinkMessage {
         let v = u16::from(&flow.status) as u32;
         nlas.push(FlowNla::Status(ConnectionStatus::from(v)));
         // id
-        nlas.push(FlowNla::Id(0));
+        nlas.push(FlowNla::Id(flow.id.unwrap_or_default()));
         Ok(CtNetlinkMessage::New(nlas))
     }
 }
@@ -171,8 +469,16 @@ pub(super) struct FlowBuilder {
     mark: Option<u32>,
     r#use: Option<u32>,
     tcp_state: Option<TcpState>,
+    proto_info: Option<ProtoInfo>,
     status: Option<Status>,
     timeout: Option<u32>,
+    secctx: Option<String>,
+    id: Option<u32>,
+    orig_counter: Option<Counter>,
+    reply_counter: Option<Counter>,
+    master: Option<Tuple>,
+    icmp: Option<Icmp>,
+    raw: Vec<(u16, Vec<u8>)>,
 }
 
 impl FlowBuilder {
@@ -210,6 +516,11 @@ impl FlowBuilder {
         self
     }
 
+    pub(super) fn proto_info(mut self, info: ProtoInfo) -> Self {
+        self.proto_info = Some(info);
+        self
+    }
+
     pub(super) fn status(mut self, s: Status) -> Self {
         self.status = Some(s);
         self
@@ -220,6 +531,41 @@ impl FlowBuilder {
         self
     }
 
+    pub(super) fn secctx(mut self, ctx: String) -> Self {
+        self.secctx = Some(ctx);
+        self
+    }
+
+    pub(super) fn id(mut self, id: u32) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub(super) fn orig_counter(mut self, c: Counter) -> Self {
+        self.orig_counter = Some(c);
+        self
+    }
+
+    pub(super) fn reply_counter(mut self, c: Counter) -> Self {
+        self.reply_counter = Some(c);
+        self
+    }
+
+    pub(super) fn master(mut self, tuple: Tuple) -> Self {
+        self.master = Some(tuple);
+        self
+    }
+
+    pub(super) fn icmp(mut self, icmp: Icmp) -> Self {
+        self.icmp = Some(icmp);
+        self
+    }
+
+    pub(super) fn raw(mut self, attr_type: u16, value: Vec<u8>) -> Self {
+        self.raw.push((attr_type, value));
+        self
+    }
+
     pub(super) fn build(&self) -> Result<Flow, FlowError> {
         let event_type = self
             .event_type
@@ -240,6 +586,7 @@ impl FlowBuilder {
             mark: self.mark,
             r#use: self.r#use,
             tcp_state: self.tcp_state,
+            proto_info: self.proto_info.clone(),
             status: self
                 .status
                 .clone()
@@ -250,6 +597,13 @@ impl FlowBuilder {
             } else {
                 0
             },
+            secctx: self.secctx.clone(),
+            id: self.id,
+            orig_counter: self.orig_counter,
+            reply_counter: self.reply_counter,
+            master: self.master.clone(),
+            icmp: self.icmp,
+            raw: self.raw.clone(),
         })
     }
 }
@@ -257,6 +611,13 @@ impl FlowBuilder {
 impl TryFrom<&Vec<FlowNla>> for FlowBuilder {
     type Error = FlowError;
     fn try_from(nlas: &Vec<FlowNla>) -> Result<Self, Self::Error> {
+        FlowBuilder::from_nlas(nlas, false)
+    }
+}
+
+impl FlowBuilder {
+    // When keep_raw is true, unrecognized attributes are kept as raw bytes.
+    pub(super) fn from_nlas(nlas: &[FlowNla], keep_raw: bool) -> Result<Self, FlowError> {
         let mut flow_builder = FlowBuilder::default();
         for nla in nlas.iter() {
             match nla {
@@ -293,164 +654,766 @@ impl TryFrom<&Vec<FlowNla>> for FlowBuilder {
                     }
                     flow_builder = flow_builder.reply(tuple_builder.build()?);
                 }
-                FlowNla::ProtocolInfo(info) => {
-                    if let ProtocolInfo::Tcp(info) = info {
-                        flow_builder = flow_builder.tcp_state(TcpState::try_from(info.state)?);
+                FlowNla::ProtocolInfo(info) => match info {
+                    ProtocolInfo::Tcp(info) => {
+                        let state = TcpState::try_from(info.state)?;
+                        flow_builder = flow_builder.tcp_state(state).proto_info(ProtoInfo::Tcp {
+                            state,
+                            flags_original: info.flgas_original,
+                            flags_reply: info.flags_reply,
+                            wscale_original: info.wscale_original,
+                            wscale_reply: info.wscale_reply,
+                        });
                     }
-                }
+                    ProtocolInfo::Other(attr) => match attr.attr_type & NLA_TYPE_MASK {
+                        CTA_PROTOINFO_SCTP => {
+                            flow_builder = flow_builder.proto_info(parse_sctp_info(attr)?);
+                        }
+                        CTA_PROTOINFO_DCCP => {
+                            flow_builder = flow_builder.proto_info(parse_dccp_info(attr)?);
+                        }
+                        _ => {}
+                    },
+                },
                 FlowNla::Mark(v) => flow_builder = flow_builder.mark(*v),
                 FlowNla::Use(v) => flow_builder = flow_builder.r#use(*v),
                 FlowNla::Timeout(t) => flow_builder = flow_builder.timeout(*t),
                 FlowNla::Status(s) => {
                     flow_builder = flow_builder.status(Status::from(s));
                 }
-                FlowNla::Id(_v) => { /* do nothing */ }
-                FlowNla::Other(_v) => { /* do nothing */ }
+                FlowNla::Id(v) => flow_builder = flow_builder.id(*v),
+                FlowNla::Other(attr) => match attr.attr_type & NLA_TYPE_MASK {
+                    CTA_SECCTX => {
+                        if let Some(ctx) = parse_secctx(attr)? {
+                            flow_builder = flow_builder.secctx(ctx);
+                        }
+                    }
+                    CTA_COUNTERS_ORIG => {
+                        flow_builder = flow_builder.orig_counter(parse_counter(attr)?);
+                    }
+                    CTA_COUNTERS_REPLY => {
+                        flow_builder = flow_builder.reply_counter(parse_counter(attr)?);
+                    }
+                    CTA_TUPLE_MASTER => {
+                        flow_builder = flow_builder.master(parse_master_tuple(attr)?);
+                    }
+                    _ => {
+                        if keep_raw {
+                            flow_builder = flow_builder.raw(attr.attr_type, raw_value(attr));
+                        }
+                    }
+                },
             }
         }
         Ok(flow_builder)
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct Tuple {
-    pub src_addr: IpAddr,
-    pub dst_addr: IpAddr,
-    pub src_port: u16,
-    pub dst_port: u16,
+// Nested attributes are encoded back into the netlink attribute format.
+fn raw_value(attr: &CtAttr) -> Vec<u8> {
+    match &attr.nested {
+        Some(nested) => {
+            let mut buf = Vec::new();
+            for a in nested.iter() {
+                let value = raw_value(a);
+                let len = (4 + value.len()) as u16;
+                buf.extend_from_slice(&len.to_ne_bytes());
+                buf.extend_from_slice(&a.attr_type.to_ne_bytes());
+                buf.extend_from_slice(&value);
+                // Each attribute is aligned to 4 bytes.
+                buf.resize(buf.len().next_multiple_of(4), 0);
+            }
+            buf
+        }
+        None => attr.value.clone().unwrap_or_default(),
+    }
 }
 
-#[derive(Debug, Default)]
-pub(super) struct TupleBuilder {
-    src_addr: Option<IpAddr>,
-    dst_addr: Option<IpAddr>,
-    src_port: Option<u16>,
-    dst_port: Option<u16>,
+#[derive(Serialize, Deserialize)]
+struct RawAttr {
+    #[serde(rename = "type")]
+    attr_type: u16,
+    value: String,
 }
 
-impl TupleBuilder {
-    pub(super) fn src_addr(mut self, addr: IpAddr) -> Self {
-        self.src_addr = Some(addr);
-        self
+fn serialize_raw<S>(raw: &[(u16, Vec<u8>)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(raw.len()))?;
+    for (attr_type, value) in raw.iter() {
+        seq.serialize_element(&RawAttr {
+            attr_type: *attr_type,
+            value: value.iter().map(|b| format!("{b:02x}")).collect(),
+        })?;
     }
+    seq.end()
+}
 
-    pub(super) fn dst_addr(mut self, addr: IpAddr) -> Self {
-        self.dst_addr = Some(addr);
-        self
+fn deserialize_raw<'de, D>(deserializer: D) -> Result<Vec<(u16, Vec<u8>)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<RawAttr>::deserialize(deserializer)?
+        .into_iter()
+        .map(|attr| {
+            let value = (0..attr.value.len())
+                .step_by(2)
+                .map(|i| {
+                    attr.value
+                        .get(i..i + 2)
+                        .and_then(|b| u8::from_str_radix(b, 16).ok())
+                        .ok_or_else(|| serde::de::Error::custom("invalid hex value"))
+                })
+                .collect::<Result<Vec<u8>, D::Error>>()?;
+            Ok((attr.attr_type, value))
+        })
+        .collect()
+}
+
+// These constants are defined in Linux kernel (linux/netfilter/nfnetlink_conntrack.h)
+const CTA_SECCTX: u16 = 19;
+const CTA_SECCTX_NAME: u16 = 1;
+const CTA_COUNTERS_ORIG: u16 = 9;
+const CTA_COUNTERS_REPLY: u16 = 10;
+const CTA_COUNTERS_PACKETS: u16 = 1;
+const CTA_COUNTERS_BYTES: u16 = 2;
+const CTA_COUNTERS32_PACKETS: u16 = 3;
+const CTA_COUNTERS32_BYTES: u16 = 4;
+const CTA_TUPLE_MASTER: u16 = 14;
+const CTA_TUPLE_IP: u16 = 1;
+const CTA_TUPLE_PROTO: u16 = 2;
+const CTA_IP_V4_SRC: u16 = 1;
+const CTA_IP_V4_DST: u16 = 2;
+const CTA_IP_V6_SRC: u16 = 3;
+const CTA_IP_V6_DST: u16 = 4;
+const CTA_TUPLE_ORIG: u16 = 1;
+const CTA_PROTO_SRC_PORT: u16 = 2;
+const CTA_PROTO_DST_PORT: u16 = 3;
+const CTA_PROTO_ICMP_ID: u16 = 4;
+const CTA_PROTO_ICMP_TYPE: u16 = 5;
+const CTA_PROTO_ICMP_CODE: u16 = 6;
+const CTA_PROTO_ICMPV6_ID: u16 = 7;
+const CTA_PROTO_ICMPV6_TYPE: u16 = 8;
+const CTA_PROTO_ICMPV6_CODE: u16 = 9;
+const CTA_PROTOINFO_DCCP: u16 = 2;
+const CTA_PROTOINFO_DCCP_STATE: u16 = 1;
+const CTA_PROTOINFO_DCCP_ROLE: u16 = 2;
+const CTA_PROTOINFO_SCTP: u16 = 3;
+const CTA_PROTOINFO_SCTP_STATE: u16 = 1;
+const CTA_PROTOINFO_SCTP_VTAG_ORIGINAL: u16 = 2;
+const CTA_PROTOINFO_SCTP_VTAG_REPLY: u16 = 3;
+
+// Attributes which are not known by netlink_packet_netfilter are given as CtAttr.
+// Its nested attributes may be parsed already or left in the value.
+fn nested_attrs(attr: &CtAttr) -> Result<Vec<(u16, Vec<u8>)>, FlowError> {
+    if let Some(nested) = &attr.nested {
+        return Ok(nested
+            .iter()
+            .map(|a| (a.attr_type & NLA_TYPE_MASK, raw_value(a)))
+            .collect());
     }
+    let mut attrs = Vec::new();
+    if let Some(value) = &attr.value {
+        for nla in NlasIterator::new(value) {
+            let nla = nla.map_err(FlowError::Netlink)?;
+            attrs.push((nla.kind(), nla.value().to_vec()));
+        }
+    }
+    Ok(attrs)
+}
 
-    pub(super) fn src_port(mut self, port: u16) -> Self {
-        self.src_port = Some(port);
-        self
+// CTA_SECCTX is a nested attribute which has the context string as CTA_SECCTX_NAME.
+fn parse_secctx(attr: &CtAttr) -> Result<Option<String>, FlowError> {
+    for (kind, value) in nested_attrs(attr)? {
+        if kind == CTA_SECCTX_NAME {
+            return parse_string(&value).map(Some).map_err(FlowError::Netlink);
+        }
     }
+    Ok(None)
+}
 
-    pub(super) fn dst_port(mut self, port: u16) -> Self {
-        self.dst_port = Some(port);
-        self
+// CTA_TUPLE_MASTER is a nested tuple which has CTA_TUPLE_IP and CTA_TUPLE_PROTO.
+// Ports are in big endian. They are zero when the protocol has no ports like the main tuples.
+fn parse_master_tuple(attr: &CtAttr) -> Result<Tuple, FlowError> {
+    let mut builder = TupleBuilder::default().src_port(0).dst_port(0);
+    for (kind, value) in nested_attrs(attr)? {
+        for nla in NlasIterator::new(&value) {
+            let nla = nla.map_err(FlowError::Netlink)?;
+            match (kind, nla.kind()) {
+                (CTA_TUPLE_IP, CTA_IP_V4_SRC | CTA_IP_V6_SRC) => {
+                    builder = builder.src_addr(parse_ip(nla.value()).map_err(FlowError::Netlink)?)
+                }
+                (CTA_TUPLE_IP, CTA_IP_V4_DST | CTA_IP_V6_DST) => {
+                    builder = builder.dst_addr(parse_ip(nla.value()).map_err(FlowError::Netlink)?)
+                }
+                (CTA_TUPLE_PROTO, CTA_PROTO_SRC_PORT) => {
+                    builder =
+                        builder.src_port(parse_u16_be(nla.value()).map_err(FlowError::Netlink)?)
+                }
+                (CTA_TUPLE_PROTO, CTA_PROTO_DST_PORT) => {
+                    builder =
+                        builder.dst_port(parse_u16_be(nla.value()).map_err(FlowError::Netlink)?)
+                }
+                _ => {}
+            }
+        }
     }
+    builder.build()
+}
 
-    pub(super) fn build(&self) -> Result<Tuple, FlowError> {
-        Ok(Tuple {
-            src_addr: self
-                .src_addr
-                .ok_or(FlowError::MissingField("src_addr".to_string()))?,
-            dst_addr: self
-                .dst_addr
-                .ok_or(FlowError::MissingField("dst_addr".to_string()))?,
-            src_port: self
-                .src_port
-                .ok_or(FlowError::MissingField("src_port".to_string()))?,
-            dst_port: self
-                .dst_port
-                .ok_or(FlowError::MissingField("dst_port".to_string()))?,
-        })
+// State specific to the L4 protocol given by CTA_PROTOINFO.
+// It is serialized as a tagged union like `{"protocol": "tcp", "state": ...}`.
+// ICMP type and code are not included because they are a part of the tuple.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "protocol", rename_all = "lowercase")]
+pub enum ProtoInfo {
+    Tcp {
+        state: TcpState,
+        flags_original: u16,
+        flags_reply: u16,
+        wscale_original: u8,
+        wscale_reply: u8,
+    },
+    Sctp {
+        state: SctpState,
+        vtag_original: u32,
+        vtag_reply: u32,
+    },
+    Dccp {
+        state: DccpState,
+        // The role of the original direction. None when the kernel doesn't report it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<DccpRole>,
+    },
+}
+
+// CTA_PROTOINFO_SCTP is a nested attribute which has the state and verification tags.
+// The verification tags are in big endian.
+fn parse_sctp_info(attr: &CtAttr) -> Result<ProtoInfo, FlowError> {
+    let mut state = SctpState(0);
+    let mut vtag_original = 0;
+    let mut vtag_reply = 0;
+    for (kind, value) in nested_attrs(attr)? {
+        match kind {
+            CTA_PROTOINFO_SCTP_STATE => {
+                state = SctpState(parse_u8(&value).map_err(FlowError::Netlink)?)
+            }
+            CTA_PROTOINFO_SCTP_VTAG_ORIGINAL => {
+                vtag_original = parse_u32_be(&value).map_err(FlowError::Netlink)?
+            }
+            CTA_PROTOINFO_SCTP_VTAG_REPLY => {
+                vtag_reply = parse_u32_be(&value).map_err(FlowError::Netlink)?
+            }
+            _ => {}
+        }
     }
+    Ok(ProtoInfo::Sctp {
+        state,
+        vtag_original,
+        vtag_reply,
+    })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
-pub enum Protocol {
-    Tcp, // 6
-    Udp, // 17
-    Other(u8),
+// enum sctp_conntrack in the kernel (linux/netfilter/nf_conntrack_sctp.h)
+// It is serialized as the name of the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SctpState(pub u8);
+
+impl From<SctpState> for String {
+    fn from(s: SctpState) -> Self {
+        match s.0 {
+            0 => String::from("NONE"),
+            1 => String::from("CLOSED"),
+            2 => String::from("COOKIE_WAIT"),
+            3 => String::from("COOKIE_ECHOED"),
+            4 => String::from("ESTABLISHED"),
+            5 => String::from("SHUTDOWN_SENT"),
+            6 => String::from("SHUTDOWN_RECD"),
+            7 => String::from("SHUTDOWN_ACK_SENT"),
+            8 => String::from("HEARTBEAT_SENT"),
+            v => format!("UNKNOWN({v})"),
+        }
+    }
 }
 
-impl From<u8> for Protocol {
-    fn from(v: u8) -> Self {
-        match v {
-            6 => Protocol::Tcp,
-            17 => Protocol::Udp,
-            _ => Protocol::Other(v),
+// The name of an unknown state like "UNKNOWN(9)" is parsed back to its value.
+impl<'de> Deserialize<'de> for SctpState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.strip_prefix("UNKNOWN(").and_then(|v| v.strip_suffix(')')) {
+            Some(v) => v
+                .parse::<u8>()
+                .map(SctpState)
+                .map_err(serde::de::Error::custom),
+            None => SctpState::try_from(s.as_str()).map_err(serde::de::Error::custom),
         }
     }
 }
 
-impl TryFrom<&str> for Protocol {
+impl TryFrom<&str> for SctpState {
     type Error = FlowError;
-    // only support tcp and udp
-    fn try_from(p: &str) -> Result<Self, Self::Error> {
-        match p.to_lowercase().as_str() {
-            "tcp" => Ok(Protocol::Tcp),
-            "udp" => Ok(Protocol::Udp),
-            _ => Err(FlowError::InvalidL4Protocol(p.to_string())),
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().replace("_", "").as_str() {
+            "none" => Ok(SctpState(0)),
+            "closed" => Ok(SctpState(1)),
+            "cookiewait" => Ok(SctpState(2)),
+            "cookieechoed" => Ok(SctpState(3)),
+            "established" => Ok(SctpState(4)),
+            "shutdownsent" => Ok(SctpState(5)),
+            "shutdownrecd" => Ok(SctpState(6)),
+            "shutdownacksent" => Ok(SctpState(7)),
+            "heartbeatsent" => Ok(SctpState(8)),
+            _ => Err(FlowError::InvalidSctpState(s.to_string())),
         }
     }
 }
 
-impl From<Protocol> for String {
-    fn from(p: Protocol) -> Self {
-        match p {
-            Protocol::Tcp => String::from("tcp"),
-            Protocol::Udp => String::from("udp"),
-            Protocol::Other(v) => format!("other({v})"),
+impl FromStr for SctpState {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SctpState::try_from(s)
+    }
+}
+
+impl Serialize for SctpState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&String::from(*self))
+    }
+}
+
+// CTA_PROTOINFO_DCCP is a nested attribute which has the state, the role and the handshake sequence.
+// The handshake sequence is not used.
+fn parse_dccp_info(attr: &CtAttr) -> Result<ProtoInfo, FlowError> {
+    let mut state = DccpState(0);
+    let mut role = None;
+    for (kind, value) in nested_attrs(attr)? {
+        match kind {
+            CTA_PROTOINFO_DCCP_STATE => {
+                state = DccpState(parse_u8(&value).map_err(FlowError::Netlink)?)
+            }
+            CTA_PROTOINFO_DCCP_ROLE => {
+                role = DccpRole::from_u8(parse_u8(&value).map_err(FlowError::Netlink)?)
+            }
+            _ => {}
         }
     }
+    Ok(ProtoInfo::Dccp { state, role })
 }
 
-impl From<Protocol> for u8 {
-    fn from(p: Protocol) -> Self {
-        match p {
-            Protocol::Tcp => 6,
-            Protocol::Udp => 17,
-            Protocol::Other(v) => v,
+// enum ct_dccp_states in the kernel (linux/netfilter/nf_conntrack_dccp.h)
+// It is serialized as the name of the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DccpState(pub u8);
+
+impl From<DccpState> for String {
+    fn from(s: DccpState) -> Self {
+        match s.0 {
+            0 => String::from("NONE"),
+            1 => String::from("REQUEST"),
+            2 => String::from("RESPOND"),
+            3 => String::from("PARTOPEN"),
+            4 => String::from("OPEN"),
+            5 => String::from("CLOSEREQ"),
+            6 => String::from("CLOSING"),
+            7 => String::from("TIMEWAIT"),
+            8 => String::from("IGNORE"),
+            9 => String::from("INVALID"),
+            v => format!("UNKNOWN({v})"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
-pub enum TcpState {
-    None,
-    SynSent,
-    SynRecv,
-    Established,
-    FinWait,
-    CloseWait,
-    LastAck,
-    TimeWait,
-    Close,
-    Listen,
+// The name of an unknown state like "UNKNOWN(10)" is parsed back to its value.
+impl<'de> Deserialize<'de> for DccpState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.strip_prefix("UNKNOWN(").and_then(|v| v.strip_suffix(')')) {
+            Some(v) => v
+                .parse::<u8>()
+                .map(DccpState)
+                .map_err(serde::de::Error::custom),
+            None => DccpState::try_from(s.as_str()).map_err(serde::de::Error::custom),
+        }
+    }
 }
 
-impl TryFrom<&str> for TcpState {
+impl TryFrom<&str> for DccpState {
     type Error = FlowError;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         match s.to_lowercase().replace("_", "").as_str() {
-            "none" => Ok(TcpState::None),
-            "synsent" => Ok(TcpState::SynSent),
-            "synrecv" => Ok(TcpState::SynRecv),
-            "established" => Ok(TcpState::Established),
-            "finwait" => Ok(TcpState::FinWait),
-            "closewait" => Ok(TcpState::CloseWait),
-            "lastack" => Ok(TcpState::LastAck),
-            "timewait" => Ok(TcpState::TimeWait),
-            "close" => Ok(TcpState::Close),
-            "listen" => Ok(TcpState::Listen),
-            _ => Err(FlowError::InvalidTcpState(s.to_string())),
+            "none" => Ok(DccpState(0)),
+            "request" => Ok(DccpState(1)),
+            "respond" => Ok(DccpState(2)),
+            "partopen" => Ok(DccpState(3)),
+            "open" => Ok(DccpState(4)),
+            "closereq" => Ok(DccpState(5)),
+            "closing" => Ok(DccpState(6)),
+            "timewait" => Ok(DccpState(7)),
+            "ignore" => Ok(DccpState(8)),
+            "invalid" => Ok(DccpState(9)),
+            _ => Err(FlowError::InvalidDccpState(s.to_string())),
         }
     }
 }
 
-impl TryFrom<u8> for TcpState {
+impl FromStr for DccpState {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DccpState::try_from(s)
+    }
+}
+
+impl Serialize for DccpState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&String::from(*self))
+    }
+}
+
+// enum ct_dccp_roles in the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DccpRole {
+    Client,
+    Server,
+}
+
+impl DccpRole {
+    fn from_u8(v: u8) -> Option<DccpRole> {
+        match v {
+            0 => Some(DccpRole::Client),
+            1 => Some(DccpRole::Server),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+// CTA_COUNTERS_ORIG and CTA_COUNTERS_REPLY are nested attributes which have counters in big endian.
+fn parse_counter(attr: &CtAttr) -> Result<Counter, FlowError> {
+    let mut counter = Counter::default();
+    for (kind, value) in nested_attrs(attr)? {
+        match kind {
+            CTA_COUNTERS_PACKETS => {
+                counter.packets = parse_u64(&value)
+                    .map(u64::from_be)
+                    .map_err(FlowError::Netlink)?
+            }
+            CTA_COUNTERS_BYTES => {
+                counter.bytes = parse_u64(&value)
+                    .map(u64::from_be)
+                    .map_err(FlowError::Netlink)?
+            }
+            CTA_COUNTERS32_PACKETS => {
+                counter.packets = parse_u32_be(&value).map_err(FlowError::Netlink)? as u64
+            }
+            CTA_COUNTERS32_BYTES => {
+                counter.bytes = parse_u32_be(&value).map_err(FlowError::Netlink)? as u64
+            }
+            _ => {}
+        }
+    }
+    Ok(counter)
+}
+
+// Read the ICMP part of CTA_TUPLE_ORIG from attributes of a message. The id is in big endian.
+// None when the attributes are not kept or the tuple has no ICMP attributes.
+fn parse_icmp(attrs: &[u8]) -> Result<Option<Icmp>, FlowError> {
+    let mut icmp = None::<Icmp>;
+    for tuple in NlasIterator::new(attrs) {
+        let tuple = tuple.map_err(FlowError::Netlink)?;
+        if tuple.kind() != CTA_TUPLE_ORIG {
+            continue;
+        }
+        for proto in NlasIterator::new(tuple.value()) {
+            let proto = proto.map_err(FlowError::Netlink)?;
+            if proto.kind() != CTA_TUPLE_PROTO {
+                continue;
+            }
+            for nla in NlasIterator::new(proto.value()) {
+                let nla = nla.map_err(FlowError::Netlink)?;
+                match nla.kind() {
+                    CTA_PROTO_ICMP_ID | CTA_PROTO_ICMPV6_ID => {
+                        icmp.get_or_insert_default().id =
+                            parse_u16_be(nla.value()).map_err(FlowError::Netlink)?
+                    }
+                    CTA_PROTO_ICMP_TYPE | CTA_PROTO_ICMPV6_TYPE => {
+                        icmp.get_or_insert_default().r#type =
+                            parse_u8(nla.value()).map_err(FlowError::Netlink)?
+                    }
+                    CTA_PROTO_ICMP_CODE | CTA_PROTO_ICMPV6_CODE => {
+                        icmp.get_or_insert_default().code =
+                            parse_u8(nla.value()).map_err(FlowError::Netlink)?
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(icmp)
+}
+
+// The ICMP part of a tuple. ICMP has no ports, so ports of the tuple are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Icmp {
+    pub r#type: u8,
+    pub code: u8,
+    pub id: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Tuple {
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+// A tuple is formatted as "<src addr>:<src port>-><dst addr>:<dst port>".
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}->{}",
+            SocketAddr::new(self.src_addr, self.src_port),
+            SocketAddr::new(self.dst_addr, self.dst_port)
+        )
+    }
+}
+
+impl FromStr for Tuple {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (src, dst) = s
+            .split_once("->")
+            .ok_or(FlowError::InvalidFormat(s.to_string()))?;
+        let src = SocketAddr::from_str(src).map_err(|_| FlowError::InvalidFormat(s.to_string()))?;
+        let dst = SocketAddr::from_str(dst).map_err(|_| FlowError::InvalidFormat(s.to_string()))?;
+        Ok(Tuple {
+            src_addr: src.ip(),
+            dst_addr: dst.ip(),
+            src_port: src.port(),
+            dst_port: dst.port(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct TupleBuilder {
+    src_addr: Option<IpAddr>,
+    dst_addr: Option<IpAddr>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+}
+
+impl TupleBuilder {
+    pub(super) fn src_addr(mut self, addr: IpAddr) -> Self {
+        self.src_addr = Some(addr);
+        self
+    }
+
+    pub(super) fn dst_addr(mut self, addr: IpAddr) -> Self {
+        self.dst_addr = Some(addr);
+        self
+    }
+
+    pub(super) fn src_port(mut self, port: u16) -> Self {
+        self.src_port = Some(port);
+        self
+    }
+
+    pub(super) fn dst_port(mut self, port: u16) -> Self {
+        self.dst_port = Some(port);
+        self
+    }
+
+    pub(super) fn build(&self) -> Result<Tuple, FlowError> {
+        Ok(Tuple {
+            src_addr: self
+                .src_addr
+                .ok_or(FlowError::MissingField("src_addr".to_string()))?,
+            dst_addr: self
+                .dst_addr
+                .ok_or(FlowError::MissingField("dst_addr".to_string()))?,
+            src_port: self
+                .src_port
+                .ok_or(FlowError::MissingField("src_port".to_string()))?,
+            dst_port: self
+                .dst_port
+                .ok_or(FlowError::MissingField("dst_port".to_string()))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum Protocol {
+    Tcp, // 6
+    Udp, // 17
+    Other(u8),
+}
+
+impl From<u8> for Protocol {
+    fn from(v: u8) -> Self {
+        match v {
+            6 => Protocol::Tcp,
+            17 => Protocol::Udp,
+            _ => Protocol::Other(v),
+        }
+    }
+}
+
+impl TryFrom<&str> for Protocol {
+    type Error = FlowError;
+    // Names of protocols tracked by conntrack or a protocol number like "47".
+    // Numbers of tcp and udp are converted into Tcp and Udp.
+    fn try_from(p: &str) -> Result<Self, Self::Error> {
+        match p.to_lowercase().as_str() {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            "icmp" => Ok(Protocol::Other(1)),
+            "dccp" => Ok(Protocol::Other(33)),
+            "gre" => Ok(Protocol::Other(47)),
+            "esp" => Ok(Protocol::Other(50)),
+            "icmpv6" => Ok(Protocol::Other(58)),
+            "sctp" => Ok(Protocol::Other(132)),
+            _ => p
+                .parse::<u8>()
+                .map(Protocol::from)
+                .map_err(|_| FlowError::InvalidL4Protocol(p.to_string())),
+        }
+    }
+}
+
+// The serialized form like "Tcp" or {"Other": 47} is accepted
+// as well as a name like "tcp" or a protocol number like 47 for hand-written fixtures.
+impl<'de> Deserialize<'de> for Protocol {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(u8),
+            Name(String),
+            Other {
+                #[serde(rename = "Other")]
+                other: u8,
+            },
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(v) | Repr::Other { other: v } => Ok(Protocol::from(v)),
+            Repr::Name(s) => parse_protocol(&s).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl From<Protocol> for String {
+    fn from(p: Protocol) -> Self {
+        match p {
+            Protocol::Tcp => String::from("tcp"),
+            Protocol::Udp => String::from("udp"),
+            Protocol::Other(v) => format!("other({v})"),
+        }
+    }
+}
+
+impl From<Protocol> for u8 {
+    fn from(p: Protocol) -> Self {
+        match p {
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+            Protocol::Other(v) => v,
+        }
+    }
+}
+
+// Protocols are compared by the protocol number, so Other(6) equals Tcp as well as in Ord.
+impl PartialEq for Protocol {
+    fn eq(&self, other: &Self) -> bool {
+        u8::from(*self) == u8::from(*other)
+    }
+}
+
+impl Eq for Protocol {}
+
+impl std::hash::Hash for Protocol {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        u8::from(*self).hash(state);
+    }
+}
+
+// Protocols are ordered by the protocol number like ICMP(1) < TCP(6) < UDP(17).
+impl Ord for Protocol {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        u8::from(*self).cmp(&u8::from(*other))
+    }
+}
+
+impl PartialOrd for Protocol {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum TcpState {
+    None,
+    SynSent,
+    SynRecv,
+    Established,
+    FinWait,
+    CloseWait,
+    LastAck,
+    TimeWait,
+    Close,
+    Listen,
+}
+
+impl TryFrom<&str> for TcpState {
+    type Error = FlowError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().replace("_", "").as_str() {
+            "none" => Ok(TcpState::None),
+            "synsent" => Ok(TcpState::SynSent),
+            "synrecv" => Ok(TcpState::SynRecv),
+            "established" => Ok(TcpState::Established),
+            "finwait" => Ok(TcpState::FinWait),
+            "closewait" => Ok(TcpState::CloseWait),
+            "lastack" => Ok(TcpState::LastAck),
+            "timewait" => Ok(TcpState::TimeWait),
+            "close" => Ok(TcpState::Close),
+            // The kernel names the state for the value 9 SYN_SENT2 in /proc/net/nf_conntrack.
+            "listen" | "synsent2" => Ok(TcpState::Listen),
+            _ => Err(FlowError::InvalidTcpState(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<u8> for TcpState {
     type Error = FlowError;
     fn try_from(s: u8) -> Result<Self, Self::Error> {
         match s {
@@ -503,6 +1466,17 @@ impl From<TcpState> for String {
     }
 }
 
+// Both the serialized form like "SynSent" and the canonical form like "SYN_SENT" are accepted.
+impl<'de> Deserialize<'de> for TcpState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        TcpState::try_from(s.as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
 impl FromStr for TcpState {
     type Err = FlowError;
 
@@ -531,17 +1505,41 @@ impl Status {
 
         for p in Self::PREFERRED.iter() {
             if self.inner.contains(p) {
-                return ct_status_flag_to_string(p);
+                return ct_status_flag_name(p).to_string();
             }
         }
 
-        ct_status_flag_to_string(
-            self.inner
-                .iter()
-                .collect::<Vec<&ConnectionStatusFlag>>()
-                .first()
-                .unwrap(),
-        )
+        // Fall back to the flag with the lowest value to keep the result stable.
+        self.flags()
+            .next()
+            .map(|flag| ct_status_flag_name(&flag).to_string())
+            .unwrap_or_default()
+    }
+
+    // The name of the flag as shown and serialized like "SEEN_REPLY".
+    pub fn flag_name(flag: ConnectionStatusFlag) -> &'static str {
+        ct_status_flag_name(&flag)
+    }
+
+    // All flags with their names and values in the order of their values.
+    pub fn all_flags() -> &'static [(ConnectionStatusFlag, &'static str, u32)] {
+        &ALL_FLAGS
+    }
+
+    pub fn contains(&self, flag: ConnectionStatusFlag) -> bool {
+        self.inner.contains(&flag)
+    }
+
+    // Flags are yielded in the order of their values.
+    pub fn flags(&self) -> impl Iterator<Item = ConnectionStatusFlag> + '_ {
+        FLAGS
+            .iter()
+            .copied()
+            .filter(|flag| self.inner.contains(flag))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
     }
 
     pub fn assured() -> Status {
@@ -567,6 +1565,92 @@ impl Status {
             inner: HashSet::from([ConnectionStatusFlag::Expected]),
         }
     }
+
+    pub fn confirmed() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::Confirmed]),
+        }
+    }
+
+    pub fn src_nat() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::SourceNAT]),
+        }
+    }
+
+    pub fn dst_nat() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::DestinationNAT]),
+        }
+    }
+
+    // An alias of src_nat() named after the SNAT flag.
+    pub fn snat() -> Status {
+        Status::src_nat()
+    }
+
+    // An alias of dst_nat() named after the DNAT flag.
+    pub fn dnat() -> Status {
+        Status::dst_nat()
+    }
+
+    // Both SNAT and DNAT. This is meant for matching with any of them, e.g. Filter::status().
+    pub fn nat() -> Status {
+        Status {
+            inner: HashSet::from([
+                ConnectionStatusFlag::SourceNAT,
+                ConnectionStatusFlag::DestinationNAT,
+            ]),
+        }
+    }
+
+    pub fn seq_adjust() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::SequenceAdjust]),
+        }
+    }
+
+    pub fn src_nat_done() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::SourceNATDone]),
+        }
+    }
+
+    pub fn dst_nat_done() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::DestinationNATDone]),
+        }
+    }
+
+    pub fn dying() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::Dying]),
+        }
+    }
+
+    pub fn template() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::Template]),
+        }
+    }
+
+    pub fn untracked() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::Untracked]),
+        }
+    }
+
+    pub fn helper() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::Helper]),
+        }
+    }
+
+    pub fn offload() -> Status {
+        Status {
+            inner: HashSet::from([ConnectionStatusFlag::Offload]),
+        }
+    }
 }
 
 const FLAGS: [ConnectionStatusFlag; 15] = [
@@ -587,6 +1671,16 @@ const FLAGS: [ConnectionStatusFlag; 15] = [
     ConnectionStatusFlag::Offload,
 ];
 
+const ALL_FLAGS: [(ConnectionStatusFlag, &str, u32); FLAGS.len()] = {
+    let mut all = [(ConnectionStatusFlag::Expected, "", 0); FLAGS.len()];
+    let mut i = 0;
+    while i < FLAGS.len() {
+        all[i] = (FLAGS[i], ct_status_flag_name(&FLAGS[i]), FLAGS[i] as u32);
+        i += 1;
+    }
+    all
+};
+
 impl From<&ConnectionStatus> for Status {
     fn from(s: &ConnectionStatus) -> Self {
         let mut flags = Vec::new();
@@ -601,6 +1695,13 @@ impl From<&ConnectionStatus> for Status {
     }
 }
 
+// HashSet doesn't implement Hash, so the flags are hashed as their bits.
+impl Hash for Status {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        u16::from(self).hash(state);
+    }
+}
+
 impl From<&Status> for u16 {
     fn from(status: &Status) -> Self {
         let mut v = 0;
@@ -625,47 +1726,108 @@ impl From<u16> for Status {
     }
 }
 
+// Flags are joined with "," in the order of their values.
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags: Vec<&str> = FLAGS
+            .iter()
+            .filter(|flag| self.inner.contains(flag))
+            .map(ct_status_flag_name)
+            .collect();
+        write!(f, "{}", flags.join(","))
+    }
+}
+
 impl Serialize for Status {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
+        // Iterate FLAGS instead of the HashSet to serialize flags in a stable order.
         let mut seq = serializer.serialize_seq(Some(self.inner.len()))?;
-        for s in self.inner.iter() {
-            seq.serialize_element(&ct_status_flag_to_string(s))?;
+        for s in self.flags() {
+            seq.serialize_element(ct_status_flag_name(&s))?;
         }
         seq.end()
     }
 }
 
-fn ct_status_flag_to_string(f: &ConnectionStatusFlag) -> String {
+// A list of flag names as serialized.
+impl<'de> Deserialize<'de> for Status {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let flags = Vec::<String>::deserialize(deserializer)?;
+        Status::try_from(flags.join(",").as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
+// Comma separated flag names like "assured,seen_reply" are combined into a status.
+// Names are the same as the canonical form and compared case-insensitively.
+impl TryFrom<&str> for Status {
+    type Error = FlowError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let mut inner = HashSet::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let flag = FLAGS
+                .iter()
+                .find(|f| ct_status_flag_name(f).eq_ignore_ascii_case(name))
+                .ok_or(FlowError::InvalidCtState(name.to_string()))?;
+            inner.insert(*flag);
+        }
+        Ok(Status { inner })
+    }
+}
+
+const fn ct_status_flag_name(f: &ConnectionStatusFlag) -> &'static str {
     match f {
-        ConnectionStatusFlag::Offload => String::from("OFFLOAD"),
-        ConnectionStatusFlag::Helper => String::from("HELPER"),
-        ConnectionStatusFlag::Untracked => String::from("UNTRACKED"),
-        ConnectionStatusFlag::Template => String::from("TEMPLATE"),
-        ConnectionStatusFlag::FixedTimeout => String::from("FIXED_TIMEOUT"),
-        ConnectionStatusFlag::Dying => String::from("DYING"),
-        ConnectionStatusFlag::DestinationNATDone => String::from("DNAT_DONE"),
-        ConnectionStatusFlag::SourceNATDone => String::from("SNAT_DONE"),
-        ConnectionStatusFlag::SequenceAdjust => String::from("SEQ_ADJ"),
-        ConnectionStatusFlag::DestinationNAT => String::from("DNAT"),
-        ConnectionStatusFlag::SourceNAT => String::from("SNAT"),
-        ConnectionStatusFlag::Confirmed => String::from("CONFIRMED"),
-        ConnectionStatusFlag::Assured => String::from("ASSURED"),
-        ConnectionStatusFlag::SeenReply => String::from("SEEN_REPLY"),
-        ConnectionStatusFlag::Expected => String::from("EXPECTED"),
+        ConnectionStatusFlag::Offload => "OFFLOAD",
+        ConnectionStatusFlag::Helper => "HELPER",
+        ConnectionStatusFlag::Untracked => "UNTRACKED",
+        ConnectionStatusFlag::Template => "TEMPLATE",
+        ConnectionStatusFlag::FixedTimeout => "FIXED_TIMEOUT",
+        ConnectionStatusFlag::Dying => "DYING",
+        ConnectionStatusFlag::DestinationNATDone => "DNAT_DONE",
+        ConnectionStatusFlag::SourceNATDone => "SNAT_DONE",
+        ConnectionStatusFlag::SequenceAdjust => "SEQ_ADJ",
+        ConnectionStatusFlag::DestinationNAT => "DNAT",
+        ConnectionStatusFlag::SourceNAT => "SNAT",
+        ConnectionStatusFlag::Confirmed => "CONFIRMED",
+        ConnectionStatusFlag::Assured => "ASSURED",
+        ConnectionStatusFlag::SeenReply => "SEEN_REPLY",
+        ConnectionStatusFlag::Expected => "EXPECTED",
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
+    use std::{
+        collections::HashSet,
+        time::{Duration, UNIX_EPOCH},
+    };
 
     use netlink_packet_netfilter::ctnetlink::nlas::flow::status::ConnectionStatusFlag;
     use rstest::rstest;
 
-    use super::Status;
+    use netlink_packet_netfilter::ctnetlink::nlas::ct_attr::CtAttr;
+
+    use super::{
+        parse_counter, parse_dccp_info, parse_flow, parse_icmp, parse_master_tuple,
+        parse_sctp_info, parse_secctx, Counter, DccpRole, DccpState, FlatFlow, Flow, FlowBuilder,
+        Icmp, ProtoInfo, Protocol, SctpState, Status,
+    };
+    use crate::message::Message;
+    use netlink_packet_netfilter::ctnetlink::{
+        message::CtNetlinkMessage,
+        nlas::flow::{
+            ip_tuple::{IpTupleBuilder, ProtocolTupleBuilder, TupleNla},
+            nla::FlowNla,
+            protocol_info::{ProtocolInfo, ProtocolInfoTcp},
+            status::ConnectionStatus,
+        },
+    };
 
     use super::TcpState;
 
@@ -689,10 +1851,740 @@ mod tests {
         expected,
         case(1 << 2, Status::assured()),
         case(1 << 1, Status::seen_reply()),
+        case(1 << 3, Status::confirmed()),
+        case(1 << 9, Status::dying()),
+        case(1 << 14, Status::offload()),
         case((1 << 1) + (1 << 2) + (1 << 7), Status{ inner: HashSet::from([ConnectionStatusFlag::Assured, ConnectionStatusFlag::SeenReply, ConnectionStatusFlag::SourceNATDone])}),
     )]
     fn test_status_from_u16(val: u16, expected: Status) {
         let status = Status::from(val);
         assert_eq!(status, expected);
     }
+
+    #[rstest(
+        flow,
+        case("event=update proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 state=ESTABLISHED status=SEEN_REPLY,ASSURED timeout=1000 mark=0 use=1"),
+        case("event=update proto=udp orig=[fd00::1]:1234->[fd00::2]:2345 reply=[fd00::2]:2345->[fd00::1]:1234 status=ASSURED timeout=30"),
+        case("event=new proto=other(1) orig=1.1.1.1:0->2.2.2.2:0 reply=2.2.2.2:0->1.1.1.1:0 status= timeout=0"),
+        case("event=new proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 state=SYN_SENT status=CONFIRMED timeout=30"),
+        case("event=destroy proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 state=TIME_WAIT status=SEEN_REPLY,ASSURED,CONFIRMED timeout=0"),
+    )]
+    fn test_flow_display_from_str_round_trip(flow: &str) {
+        let parsed = flow.parse::<Flow>().unwrap();
+        assert_eq!(parsed.to_string(), flow);
+    }
+
+    #[rstest(
+        flow,
+        case("event=update proto=tcp"),
+        case("event=update proto=foo orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 status= timeout=0"),
+        case("event=update proto=tcp orig=1.1.1.1->2.2.2.2 reply=2.2.2.2:2345->1.1.1.1:1234 status= timeout=0"),
+        case("event=update proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 status=UNKNOWN timeout=0"),
+        case("event=update proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 status= timeout=0 foo=bar"),
+    )]
+    fn test_flow_from_str_invalid(flow: &str) {
+        assert!(flow.parse::<Flow>().is_err());
+    }
+
+    #[test]
+    fn test_parse_secctx() {
+        let ctx = "system_u:object_r:unlabeled_t:s0";
+        // CTA_SECCTX_NAME with a NUL terminated string and padding.
+        let mut value = Vec::new();
+        let len = 4 + ctx.len() + 1;
+        value.extend_from_slice(&(len as u16).to_ne_bytes());
+        value.extend_from_slice(&1u16.to_ne_bytes());
+        value.extend_from_slice(ctx.as_bytes());
+        value.push(0);
+        value.resize(value.len().next_multiple_of(4), 0);
+
+        let raw = CtAttr {
+            nested: None,
+            attr_type: 19,
+            length: (value.len() + 4) as u16,
+            value: Some(value),
+        };
+        assert_eq!(parse_secctx(&raw).unwrap(), Some(ctx.to_string()));
+
+        let nested = CtAttr {
+            nested: Some(vec![CtAttr {
+                nested: None,
+                attr_type: 1,
+                length: len as u16,
+                value: Some(format!("{ctx}\0").into_bytes()),
+            }]),
+            attr_type: 19,
+            length: 0,
+            value: None,
+        };
+        assert_eq!(parse_secctx(&nested).unwrap(), Some(ctx.to_string()));
+
+        // The kernel gives the context as CTA_SECCTX(19).
+        let builder = FlowBuilder::from_nlas(&[FlowNla::Other(raw)], false).unwrap();
+        assert_eq!(builder.secctx, Some(ctx.to_string()));
+    }
+
+    #[rstest(
+        keep_raw,
+        expected,
+        case(false, vec![]),
+        case(true, vec![(20, vec![0x01, 0x02, 0x03, 0x04]), (21, vec![6, 0, 1, 0, 0xff, 0xff, 0, 0])]),
+    )]
+    fn test_flow_builder_keep_raw(keep_raw: bool, expected: Vec<(u16, Vec<u8>)>) {
+        let nlas = vec![
+            FlowNla::Other(CtAttr {
+                nested: None,
+                attr_type: 20,
+                length: 8,
+                value: Some(vec![0x01, 0x02, 0x03, 0x04]),
+            }),
+            FlowNla::Other(CtAttr {
+                nested: Some(vec![CtAttr {
+                    nested: None,
+                    attr_type: 1,
+                    length: 6,
+                    value: Some(vec![0xff, 0xff]),
+                }]),
+                attr_type: 21,
+                length: 12,
+                value: None,
+            }),
+        ];
+        let builder = FlowBuilder::from_nlas(&nlas, keep_raw).unwrap();
+        assert_eq!(builder.raw, expected);
+    }
+
+    #[test]
+    fn test_flow_builder_zero_mark_and_use() {
+        let nlas = vec![FlowNla::Mark(0), FlowNla::Use(0)];
+        let builder = FlowBuilder::from_nlas(&nlas, false).unwrap();
+        assert_eq!(builder.mark, Some(0));
+        assert_eq!(builder.r#use, Some(0));
+    }
+
+    #[test]
+    fn test_status_accessors() {
+        let status = Status::from(
+            ConnectionStatusFlag::Assured as u16 | ConnectionStatusFlag::SourceNAT as u16,
+        );
+        assert!(status.contains(ConnectionStatusFlag::SourceNAT));
+        assert!(!status.contains(ConnectionStatusFlag::DestinationNAT));
+        assert_eq!(
+            status.flags().collect::<Vec<_>>(),
+            vec![
+                ConnectionStatusFlag::Assured,
+                ConnectionStatusFlag::SourceNAT
+            ]
+        );
+        assert!(!status.is_empty());
+        assert!(Status::from(0).is_empty());
+    }
+
+    #[test]
+    fn test_status_all_flags() {
+        let all = Status::all_flags();
+        assert_eq!(all.len(), 15);
+        assert_eq!(all[0], (ConnectionStatusFlag::Expected, "EXPECTED", 1));
+        assert_eq!(all[14], (ConnectionStatusFlag::Offload, "OFFLOAD", 1 << 14));
+        for (flag, name, value) in all {
+            assert_eq!(Status::flag_name(*flag), *name);
+            assert_eq!(u16::from(&Status::try_from(*name).unwrap()) as u32, *value);
+        }
+    }
+
+    #[rstest(
+        status,
+        expected,
+        case(Status::confirmed(), 1 << 3),
+        case(Status::src_nat(), 1 << 4),
+        case(Status::dst_nat(), 1 << 5),
+        case(Status::snat(), 1 << 4),
+        case(Status::dnat(), 1 << 5),
+        case(Status::nat(), (1 << 4) + (1 << 5)),
+        case(Status::dying(), 1 << 9),
+        case(Status::offload(), 1 << 14),
+    )]
+    fn test_status_constructors(status: Status, expected: u16) {
+        assert_eq!(u16::from(&status), expected);
+    }
+
+    #[rstest(
+        val,
+        expected,
+        case(0, ""),
+        case((1 << 1) + (1 << 2), "ASSURED"),
+        case((1 << 3) + (1 << 4) + (1 << 5), "CONFIRMED"),
+        case((1 << 5) + (1 << 9), "DNAT"),
+    )]
+    fn test_status_preferred_one(val: u16, expected: &str) {
+        assert_eq!(Status::from(val).preferred_one(), expected);
+    }
+
+    fn counter_attr(attr_type: u16, packets: u64, bytes: u64) -> CtAttr {
+        CtAttr {
+            nested: Some(vec![
+                CtAttr {
+                    nested: None,
+                    attr_type: 1,
+                    length: 12,
+                    value: Some(packets.to_be_bytes().to_vec()),
+                },
+                CtAttr {
+                    nested: None,
+                    attr_type: 2,
+                    length: 12,
+                    value: Some(bytes.to_be_bytes().to_vec()),
+                },
+            ]),
+            attr_type,
+            length: 28,
+            value: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_counter() {
+        assert_eq!(
+            parse_counter(&counter_attr(9, 10, 1500)).unwrap(),
+            Counter {
+                packets: 10,
+                bytes: 1500
+            }
+        );
+
+        // The kernel gives counters as CTA_COUNTERS_ORIG(9) and CTA_COUNTERS_REPLY(10).
+        let nlas = vec![
+            FlowNla::Other(counter_attr(9, 10, 1500)),
+            FlowNla::Other(counter_attr(10, 20, 3000)),
+        ];
+        let builder = FlowBuilder::from_nlas(&nlas, false).unwrap();
+        assert_eq!(
+            builder.orig_counter,
+            Some(Counter {
+                packets: 10,
+                bytes: 1500
+            })
+        );
+        assert_eq!(
+            builder.reply_counter,
+            Some(Counter {
+                packets: 20,
+                bytes: 3000
+            })
+        );
+    }
+
+    #[rstest(
+        flow,
+        case("event=new proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 state=SYN_SENT status=CONFIRMED timeout=120"),
+        case("event=destroy proto=other(132) orig=[fd00::1]:1234->[fd00::2]:2345 reply=[fd00::2]:2345->[fd00::1]:1234 status=SEEN_REPLY,ASSURED timeout=0 mark=1 use=2")
+    )]
+    fn test_flow_deserialize(flow: &str) {
+        let mut flow: Flow = flow.parse().unwrap();
+        flow.orig_counter = Some(Counter {
+            packets: 10,
+            bytes: 1500,
+        });
+        flow.proto_info = Some(ProtoInfo::Sctp {
+            state: SctpState(9),
+            vtag_original: 1,
+            vtag_reply: 2,
+        });
+        flow.raw = vec![(30, vec![0x00, 0xab])];
+        let json = serde_json::to_string(&flow).unwrap();
+        let res: Flow = serde_json::from_str(&json).unwrap();
+        assert_eq!(res, flow);
+        assert_eq!(res.raw, flow.raw);
+    }
+
+    #[rstest(
+        json,
+        expected,
+        case(r#""Tcp""#, Some(Protocol::Tcp)),
+        case(r#""udp""#, Some(Protocol::Udp)),
+        case(r#"{"Other":47}"#, Some(Protocol::Other(47))),
+        case("6", Some(Protocol::Tcp)),
+        case(r#""other(132)""#, Some(Protocol::Other(132))),
+        case(r#""foo""#, None)
+    )]
+    fn test_protocol_deserialize(json: &str, expected: Option<Protocol>) {
+        assert_eq!(serde_json::from_str::<Protocol>(json).ok(), expected);
+    }
+
+    #[rstest(
+        s,
+        expected,
+        case("tcp", Some(Protocol::Tcp)),
+        case("UDP", Some(Protocol::Udp)),
+        case("icmp", Some(Protocol::Other(1))),
+        case("icmpv6", Some(Protocol::Other(58))),
+        case("sctp", Some(Protocol::Other(132))),
+        case("dccp", Some(Protocol::Other(33))),
+        case("gre", Some(Protocol::Other(47))),
+        case("esp", Some(Protocol::Other(50))),
+        case("6", Some(Protocol::Tcp)),
+        case("17", Some(Protocol::Udp)),
+        case("136", Some(Protocol::Other(136))),
+        case("256", None),
+        case("foo", None)
+    )]
+    fn test_protocol_try_from_str(s: &str, expected: Option<Protocol>) {
+        assert_eq!(Protocol::try_from(s).ok(), expected);
+    }
+
+    #[rstest(
+        json,
+        expected,
+        case(r#""SynSent""#, Some(TcpState::SynSent)),
+        case(r#""TIME_WAIT""#, Some(TcpState::TimeWait)),
+        case(r#""established""#, Some(TcpState::Established)),
+        case(r#""foo""#, None)
+    )]
+    fn test_tcp_state_deserialize(json: &str, expected: Option<TcpState>) {
+        assert_eq!(serde_json::from_str::<TcpState>(json).ok(), expected);
+    }
+
+    #[rstest(
+        json,
+        expected,
+        case(r#"["SEEN_REPLY","ASSURED"]"#, Some((1 << 1) | (1 << 2))),
+        case(r#"["confirmed"]"#, Some(1 << 3)),
+        case("[]", Some(0)),
+        case(r#"["FOO"]"#, None),
+        case(r#""ASSURED""#, None)
+    )]
+    fn test_status_deserialize(json: &str, expected: Option<u16>) {
+        let res = serde_json::from_str::<Status>(json).ok();
+        assert_eq!(res.as_ref().map(u16::from), expected);
+    }
+
+    #[test]
+    fn test_flat_flow() {
+        let mut flow: Flow = "event=update proto=tcp orig=1.1.1.1:1234->2.2.2.2:2345 reply=2.2.2.2:2345->1.1.1.1:1234 state=ESTABLISHED status=SEEN_REPLY,ASSURED timeout=1000 mark=0 use=1".parse().unwrap();
+        flow.orig_counter = Some(Counter {
+            packets: 10,
+            bytes: 1500,
+        });
+        let flat = FlatFlow::from(&flow);
+        assert_eq!(flat.orig_src_addr, flow.original.src_addr);
+        assert_eq!(flat.reply_dst_port, 1234);
+        assert_eq!(flat.orig_packets, Some(10));
+        assert_eq!(flat.orig_bytes, Some(1500));
+        assert_eq!(flat.reply_packets, None);
+    }
+
+    #[test]
+    fn test_parse_sctp_info() {
+        let attr = CtAttr {
+            nested: Some(vec![
+                CtAttr {
+                    nested: None,
+                    attr_type: 1,
+                    length: 5,
+                    value: Some(vec![4]),
+                },
+                CtAttr {
+                    nested: None,
+                    attr_type: 2,
+                    length: 8,
+                    value: Some(0x1234u32.to_be_bytes().to_vec()),
+                },
+                CtAttr {
+                    nested: None,
+                    attr_type: 3,
+                    length: 8,
+                    value: Some(0x5678u32.to_be_bytes().to_vec()),
+                },
+            ]),
+            attr_type: 3,
+            length: 28,
+            value: None,
+        };
+        assert_eq!(
+            parse_sctp_info(&attr).unwrap(),
+            ProtoInfo::Sctp {
+                state: SctpState(4),
+                vtag_original: 0x1234,
+                vtag_reply: 0x5678,
+            }
+        );
+        assert_eq!(String::from(SctpState(4)), "ESTABLISHED");
+    }
+
+    #[rstest(
+        nested,
+        expected,
+        case(vec![(1, vec![4]), (2, vec![0])], ProtoInfo::Dccp { state: DccpState(4), role: Some(DccpRole::Client) }),
+        case(vec![(1, vec![7]), (2, vec![1]), (3, 1u64.to_be_bytes().to_vec())], ProtoInfo::Dccp { state: DccpState(7), role: Some(DccpRole::Server) }),
+        case(vec![(1, vec![1])], ProtoInfo::Dccp { state: DccpState(1), role: None }),
+    )]
+    fn test_parse_dccp_info(nested: Vec<(u16, Vec<u8>)>, expected: ProtoInfo) {
+        let attr = CtAttr {
+            nested: Some(
+                nested
+                    .into_iter()
+                    .map(|(attr_type, value)| CtAttr {
+                        nested: None,
+                        attr_type,
+                        length: 4 + value.len() as u16,
+                        value: Some(value),
+                    })
+                    .collect(),
+            ),
+            attr_type: 2,
+            length: 0,
+            value: None,
+        };
+        assert_eq!(parse_dccp_info(&attr).unwrap(), expected);
+    }
+
+    #[rstest(
+        state,
+        expected,
+        case(DccpState(4), "OPEN"),
+        case(DccpState(7), "TIMEWAIT"),
+        case(DccpState(10), "UNKNOWN(10)")
+    )]
+    fn test_dccp_state_string(state: DccpState, expected: &str) {
+        assert_eq!(String::from(state), expected);
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<DccpState>(&json).unwrap(), state);
+    }
+
+    #[test]
+    fn test_flow_builder_tcp_proto_info() {
+        let nlas = vec![FlowNla::ProtocolInfo(ProtocolInfo::Tcp(ProtocolInfoTcp {
+            state: 3,
+            wscale_original: 7,
+            wscale_reply: 8,
+            flgas_original: 0x2323,
+            flags_reply: 0x2222,
+        }))];
+        let builder = FlowBuilder::from_nlas(&nlas, false).unwrap();
+        assert_eq!(builder.tcp_state, Some(TcpState::Established));
+        assert_eq!(
+            builder.proto_info,
+            Some(ProtoInfo::Tcp {
+                state: TcpState::Established,
+                flags_original: 0x2323,
+                flags_reply: 0x2222,
+                wscale_original: 7,
+                wscale_reply: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_master_tuple() {
+        let attr = CtAttr {
+            nested: Some(vec![
+                CtAttr {
+                    nested: Some(vec![
+                        CtAttr {
+                            nested: None,
+                            attr_type: 1,
+                            length: 8,
+                            value: Some(vec![10, 0, 0, 1]),
+                        },
+                        CtAttr {
+                            nested: None,
+                            attr_type: 2,
+                            length: 8,
+                            value: Some(vec![10, 0, 0, 2]),
+                        },
+                    ]),
+                    attr_type: 1,
+                    length: 20,
+                    value: None,
+                },
+                CtAttr {
+                    nested: Some(vec![
+                        CtAttr {
+                            nested: None,
+                            attr_type: 1,
+                            length: 5,
+                            value: Some(vec![6]),
+                        },
+                        CtAttr {
+                            nested: None,
+                            attr_type: 2,
+                            length: 6,
+                            value: Some(12345u16.to_be_bytes().to_vec()),
+                        },
+                        CtAttr {
+                            nested: None,
+                            attr_type: 3,
+                            length: 6,
+                            value: Some(21u16.to_be_bytes().to_vec()),
+                        },
+                    ]),
+                    attr_type: 2,
+                    length: 32,
+                    value: None,
+                },
+            ]),
+            attr_type: 14,
+            length: 56,
+            value: None,
+        };
+        let tuple = parse_master_tuple(&attr).unwrap();
+        assert_eq!(tuple.to_string(), "10.0.0.1:12345->10.0.0.2:21");
+    }
+
+    #[test]
+    fn test_parse_master_tuple_without_ports() {
+        // The master of an ICMP flow has only the protocol number in CTA_TUPLE_PROTO.
+        let attr = CtAttr {
+            nested: Some(vec![
+                CtAttr {
+                    nested: Some(vec![
+                        CtAttr {
+                            nested: None,
+                            attr_type: 1,
+                            length: 8,
+                            value: Some(vec![10, 0, 0, 1]),
+                        },
+                        CtAttr {
+                            nested: None,
+                            attr_type: 2,
+                            length: 8,
+                            value: Some(vec![10, 0, 0, 2]),
+                        },
+                    ]),
+                    attr_type: 1,
+                    length: 20,
+                    value: None,
+                },
+                CtAttr {
+                    nested: Some(vec![CtAttr {
+                        nested: None,
+                        attr_type: 1,
+                        length: 5,
+                        value: Some(vec![1]),
+                    }]),
+                    attr_type: 2,
+                    length: 12,
+                    value: None,
+                },
+            ]),
+            attr_type: 14,
+            length: 36,
+            value: None,
+        };
+        let tuple = parse_master_tuple(&attr).unwrap();
+        assert_eq!(tuple.to_string(), "10.0.0.1:0->10.0.0.2:0");
+    }
+
+    #[rstest(
+        flow,
+        expected,
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:20 reply=10.0.0.2:20->10.0.0.1:1234 state=ESTABLISHED status=EXPECTED,SEEN_REPLY timeout=100", true),
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:21 reply=10.0.0.2:21->10.0.0.1:1234 state=ESTABLISHED status=SEEN_REPLY timeout=100", false),
+    )]
+    fn test_flow_is_related(flow: &str, expected: bool) {
+        let flow: Flow = flow.parse().unwrap();
+        assert_eq!(flow.is_related(), expected);
+    }
+
+    #[rstest(
+        flow,
+        expected,
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:80 reply=10.0.0.2:80->10.0.0.1:1234 state=ESTABLISHED status=ASSURED timeout=100", false),
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:80 reply=10.0.0.2:0->10.0.0.1:0 state=SYN_SENT status=CONFIRMED timeout=100", true),
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:80 reply=0.0.0.0:0->0.0.0.0:0 state=SYN_SENT status=CONFIRMED timeout=100", true),
+        case("event=update proto=other(1) orig=10.0.0.1:0->10.0.0.2:0 reply=10.0.0.2:0->10.0.0.1:0 status=SEEN_REPLY timeout=30", false),
+    )]
+    fn test_flow_has_orphan_reply(flow: &str, expected: bool) {
+        let flow: Flow = flow.parse().unwrap();
+        assert_eq!(flow.has_orphan_reply(), expected);
+    }
+
+    #[rstest(
+        flow,
+        expected,
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:80 reply=10.0.0.2:80->10.0.0.1:1234 state=ESTABLISHED status=ASSURED timeout=100", 1100),
+        case("event=update proto=udp orig=10.0.0.1:1234->10.0.0.2:53 reply=10.0.0.2:53->10.0.0.1:1234 status=SEEN_REPLY timeout=0", 1000),
+    )]
+    fn test_flow_expires_at(flow: &str, expected: u64) {
+        let flow: Flow = flow.parse().unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(
+            flow.expires_at(now),
+            UNIX_EPOCH + Duration::from_secs(expected)
+        );
+    }
+
+    #[rstest(
+        s,
+        expected,
+        case("assured", Status::assured()),
+        case("ASSURED,SEEN_REPLY", Status::from((1 << 1) + (1 << 2))),
+        case("assured, seen_reply", Status::from((1 << 1) + (1 << 2))),
+        case("snat,dnat,", Status::from((1 << 4) + (1 << 5))),
+        case("", Status::from(0)),
+    )]
+    fn test_status_try_from_str(s: &str, expected: Status) {
+        assert_eq!(Status::try_from(s).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_status_try_from_str_invalid() {
+        assert!(Status::try_from("assured,unknown").is_err());
+    }
+
+    #[test]
+    fn test_flow_sort_key() {
+        let mut flows: Vec<Flow> = [
+            "event=new proto=udp orig=10.0.0.1:53->10.0.0.2:1000 reply=10.0.0.2:1000->10.0.0.1:53 status=ASSURED timeout=30",
+            "event=new proto=tcp orig=10.0.0.2:80->10.0.0.1:1000 reply=10.0.0.1:1000->10.0.0.2:80 state=ESTABLISHED status=ASSURED timeout=100",
+            "event=new proto=tcp orig=10.0.0.1:443->10.0.0.3:1000 reply=10.0.0.3:1000->10.0.0.1:443 state=ESTABLISHED status=ASSURED timeout=100",
+            "event=new proto=tcp orig=10.0.0.1:80->10.0.0.2:1000 reply=10.0.0.2:1000->10.0.0.1:80 state=ESTABLISHED status=ASSURED timeout=100",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        flows.sort_by_key(Flow::sort_key);
+        let got: Vec<String> = flows
+            .iter()
+            .map(|f| format!("{} {}", String::from(f.protocol), f.original))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                "tcp 10.0.0.1:80->10.0.0.2:1000",
+                "tcp 10.0.0.1:443->10.0.0.3:1000",
+                "tcp 10.0.0.2:80->10.0.0.1:1000",
+                "udp 10.0.0.1:53->10.0.0.2:1000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flow_hash() {
+        let s = "event=new proto=tcp orig=10.0.0.1:80->10.0.0.2:1000 reply=10.0.0.2:1000->10.0.0.1:80 state=ESTABLISHED status=ASSURED,SEEN_REPLY timeout=100";
+        let a: Flow = s.parse().unwrap();
+        let b: Flow = s.parse().unwrap();
+        let c: Flow = s
+            .replace("timeout=100", "timeout=100 mark=1")
+            .parse()
+            .unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        let set: HashSet<Flow> = [a, b, c].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[rstest(
+        bytes,
+        case(vec![]),
+        // Shorter than the netlink header.
+        case(vec![20, 0, 0, 0, 0, 1, 0, 0]),
+    )]
+    fn test_parse_flow_invalid(bytes: Vec<u8>) {
+        assert!(parse_flow(&bytes).is_err());
+    }
+
+    // A netlink attribute with padding.
+    fn nla(kind: u16, value: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&((value.len() + 4) as u16).to_ne_bytes());
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(value);
+        buf.resize(buf.len().next_multiple_of(4), 0);
+        buf
+    }
+
+    // CTA_TUPLE_ORIG with the ICMP attributes in CTA_TUPLE_PROTO.
+    fn icmp_tuple_attrs(protocol: u8, id: u16, r#type: u8, code: u8) -> Vec<u8> {
+        // CTA_PROTO_ICMP_* and CTA_PROTO_ICMPV6_* differ by 3.
+        let offset = if protocol == 58 { 3 } else { 0 };
+        let mut proto = nla(1, &[protocol]);
+        proto.extend(nla(4 + offset, &id.to_be_bytes()));
+        proto.extend(nla(5 + offset, &[r#type]));
+        proto.extend(nla(6 + offset, &[code]));
+        nla(1 | 0x8000, &nla(2 | 0x8000, &proto))
+    }
+
+    #[rstest(
+        attrs,
+        expected,
+        case(icmp_tuple_attrs(1, 1234, 8, 0), Some(Icmp { r#type: 8, code: 0, id: 1234 })),
+        case(icmp_tuple_attrs(58, 4321, 128, 0), Some(Icmp { r#type: 128, code: 0, id: 4321 })),
+        case(vec![], None),
+        // CTA_TUPLE_REPLY is not read.
+        case(nla(2 | 0x8000, &nla(2 | 0x8000, &nla(4, &1u16.to_be_bytes()))), None),
+    )]
+    fn test_parse_icmp(attrs: Vec<u8>, expected: Option<Icmp>) {
+        assert_eq!(parse_icmp(&attrs).unwrap(), expected);
+    }
+
+    #[rstest(
+        protocol,
+        expected,
+        case(1, Some(Icmp { r#type: 8, code: 0, id: 1234 })),
+        // Attributes are not kept for other protocols.
+        case(6, None),
+    )]
+    fn test_flow_try_from_message_icmp(protocol: u8, expected: Option<Icmp>) {
+        let tuple = |src: &str, dst: &str| {
+            vec![
+                TupleNla::Ip(
+                    IpTupleBuilder::default()
+                        .src_addr(src.parse().unwrap())
+                        .dst_addr(dst.parse().unwrap())
+                        .build()
+                        .unwrap(),
+                ),
+                TupleNla::Protocol(
+                    ProtocolTupleBuilder::default()
+                        .src_port(0)
+                        .dst_port(0)
+                        .protocol(protocol)
+                        .build()
+                        .unwrap(),
+                ),
+            ]
+        };
+        let nlas = vec![
+            FlowNla::Orig(tuple("10.0.0.1", "10.0.0.2")),
+            FlowNla::Reply(tuple("10.0.0.2", "10.0.0.1")),
+            FlowNla::Timeout(30),
+            FlowNla::Status(ConnectionStatus::from(0)),
+        ];
+        // The netlink header and the nfgenmsg header are skipped.
+        let mut bytes = vec![0; 20];
+        bytes.extend(icmp_tuple_attrs(1, 1234, 8, 0));
+        let msg = Message::new(CtNetlinkMessage::New(nlas), 0, 0).with_bytes(&bytes);
+        let flow = Flow::try_from(&msg).unwrap();
+        assert_eq!(flow.icmp, expected);
+    }
+
+    #[test]
+    fn test_protocol_ord() {
+        let mut protocols = vec![
+            Protocol::Udp,
+            Protocol::Other(132),
+            Protocol::Tcp,
+            Protocol::Other(1),
+        ];
+        protocols.sort();
+        assert_eq!(
+            protocols,
+            vec![
+                Protocol::Other(1),
+                Protocol::Tcp,
+                Protocol::Udp,
+                Protocol::Other(132)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_protocol_eq() {
+        assert_eq!(Protocol::Other(6), Protocol::Tcp);
+        assert_eq!(Protocol::Other(17), Protocol::Udp);
+        assert_ne!(Protocol::Other(1), Protocol::Tcp);
+        let protocols = std::collections::HashSet::from([Protocol::Tcp, Protocol::Other(6)]);
+        assert_eq!(protocols.len(), 1);
+    }
 }
@@ -1,25 +1,40 @@
-use std::{net::IpAddr, task::Poll};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    task::{ready, Poll},
+    time::Duration,
+};
 
 use error::Error;
 use event::Event;
-use futures::Stream;
+use flow::Flow;
+use futures::{Stream, TryStreamExt};
 use message::{Message, MessageGroup};
 use netlink_packet_netfilter::constants::{AF_INET, AF_INET6, AF_UNSPEC};
 use pin_project_lite::pin_project;
-use request::{Filter, Request};
-use socket::{ConntrackSocket, NfConntrackSocket};
+use request::{Filter, Request, RequestMeta, RequestOperation};
+use serde::Serialize;
+use socket::{ConntrackSocket, DatagramHook, NfConntrackSocket};
 
+pub mod count;
 pub mod error;
 pub mod event;
 pub mod flow;
 pub mod message;
+pub mod proc;
 pub mod request;
 pub mod socket;
 pub mod stats;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ConntrackOption {
     flow_event_group: MessageGroup,
+    netns: Option<PathBuf>,
+    keep_raw: bool,
+    port_id: Option<u32>,
+    skip_incomplete: bool,
+    poll_budget: Option<usize>,
+    datagram_hook: Option<DatagramHook>,
 }
 
 impl ConntrackOption {
@@ -31,6 +46,69 @@ impl ConntrackOption {
         self.flow_event_group = group;
         self
     }
+
+    pub fn netns(&self) -> Option<&Path> {
+        self.netns.as_deref()
+    }
+
+    // The path must point to a network namespace file such as /var/run/netns/<name>.
+    pub fn set_netns(mut self, path: impl Into<PathBuf>) -> ConntrackOption {
+        self.netns = Some(path.into());
+        self
+    }
+
+    pub fn keep_raw(&self) -> bool {
+        self.keep_raw
+    }
+
+    // Keep attributes that are not parsed into Flow fields as raw bytes.
+    pub fn set_keep_raw(mut self, keep: bool) -> ConntrackOption {
+        self.keep_raw = keep;
+        self
+    }
+
+    pub fn port_id(&self) -> Option<u32> {
+        self.port_id
+    }
+
+    // Bind the socket with the given netlink portid instead of letting the kernel assign one.
+    // It makes portids deterministic in packet captures when several consumers run.
+    // Sockets in the same process can't share a portid.
+    pub fn set_port_id(mut self, id: u32) -> ConntrackOption {
+        self.port_id = Some(id);
+        self
+    }
+
+    pub fn skip_incomplete(&self) -> bool {
+        self.skip_incomplete
+    }
+
+    // Skip flows which fail to be parsed or have an orphan reply tuple instead of aborting the whole dump.
+    pub fn set_skip_incomplete(mut self, skip: bool) -> ConntrackOption {
+        self.skip_incomplete = skip;
+        self
+    }
+
+    pub fn poll_budget(&self) -> Option<usize> {
+        self.poll_budget
+    }
+
+    // Bound the number of messages parsed in a poll of the stream. By default, a whole datagram is parsed at once.
+    // See NfConntrackSocket::poll_budget().
+    pub fn set_poll_budget(mut self, budget: usize) -> ConntrackOption {
+        self.poll_budget = Some(budget);
+        self
+    }
+
+    pub fn datagram_hook(&self) -> Option<&DatagramHook> {
+        self.datagram_hook.as_ref()
+    }
+
+    // Call the hook with each raw datagram received from the kernel. See socket::DatagramHook.
+    pub fn set_datagram_hook(mut self, hook: DatagramHook) -> ConntrackOption {
+        self.datagram_hook = Some(hook);
+        self
+    }
 }
 
 pin_project! {
@@ -38,15 +116,29 @@ pin_project! {
         #[pin]
         socket: S,
         filter: Option<Filter>,
+        keep_raw: bool,
+        skip_incomplete: bool,
+        // Flows skipped by skip_incomplete since the last request.
+        skipped: usize,
     }
 }
 
 impl Conntrack<NfConntrackSocket> {
     pub fn new(opt: ConntrackOption) -> Result<Conntrack<NfConntrackSocket>, Error> {
-        let socket = NfConntrackSocket::new(opt.flow_event_group())?;
+        let mut socket =
+            NfConntrackSocket::new(opt.flow_event_group(), opt.netns(), opt.port_id())?;
+        if let Some(budget) = opt.poll_budget() {
+            socket = socket.poll_budget(budget);
+        }
+        if let Some(hook) = opt.datagram_hook() {
+            socket = socket.datagram_hook(hook.clone());
+        }
         Ok(Conntrack {
             socket,
             filter: None,
+            keep_raw: opt.keep_raw(),
+            skip_incomplete: opt.skip_incomplete(),
+            skipped: 0,
         })
     }
 }
@@ -59,11 +151,15 @@ where
         Conntrack {
             socket,
             filter: None,
+            keep_raw: false,
+            skip_incomplete: false,
+            skipped: 0,
         }
     }
 
     pub async fn request(&mut self, req: Request) -> Result<(), Error> {
         self.filter = req.filter();
+        self.skipped = 0;
         if let Some(msg) = req.message()? {
             self.socket.send(msg).await?;
         }
@@ -71,16 +167,94 @@ where
         Ok(())
     }
 
+    // The number of flows skipped because they couldn't be parsed or had an orphan reply tuple.
+    // This is counted only when ConntrackOption::skip_incomplete is set.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    // Receive events from a single datagram. Use the Stream impl to read a whole dump.
     pub async fn recv_once(&mut self) -> Result<Vec<Event>, Error> {
+        let keep_raw = self.keep_raw;
         self.socket
             .recv_once()
             .await?
             .iter()
-            .map(Event::try_from)
+            .map(|msg| Event::from_message(msg, keep_raw))
             .collect()
     }
+
+    // Abort an in-progress dump such as when a caller stops reading entries early.
+    // Remaining messages are discarded so that the next request on this socket doesn't receive them.
+    pub async fn abort(&mut self) -> Result<(), Error> {
+        self.filter = None;
+        self.socket.drain().await
+    }
+
+    // Abort a dump with abort() and give the connection back only when it completes within the timeout,
+    // so a reused connection never yields entries of the previous dump.
+    // Otherwise it is dropped and Error::DrainTimeout is returned.
+    // Dropping this future drops the connection too, so it is safe to cancel.
+    pub async fn drain_dump(mut self, timeout: Duration) -> Result<Conntrack<S>, Error> {
+        match tokio::time::timeout(timeout, self.abort()).await {
+            Ok(res) => res.map(|_| self),
+            Err(_) => Err(Error::DrainTimeout),
+        }
+    }
+
+    // Request the number of entries in the table and wait for the reply.
+    // Error::CounterNotAvailable is returned when the reply has no count.
+    pub async fn count(&mut self, family: Family, table: Table) -> Result<u32, Error> {
+        let meta = RequestMeta::default().family(family).table(table);
+        self.request(Request::new(meta, RequestOperation::Count))
+            .await?;
+        let c = self
+            .recv_once()
+            .await?
+            .iter()
+            .find_map(|event| match event {
+                Event::Count(c) => Some(*c),
+                _ => None,
+            })
+            // Without the reply, the table would look empty and healthy.
+            .ok_or(Error::CounterNotAvailable)?;
+        Ok(c)
+    }
+}
+
+impl<S> Conntrack<S>
+where
+    S: ConntrackSocket + Stream<Item = Result<Vec<Message>, Error>> + Unpin,
+{
+    // Send a request and collect all flows of the dump. The filter of the request is applied.
+    // This buffers the whole table, so use the Stream impl to process a huge table incrementally.
+    pub async fn list_vec(&mut self, req: Request) -> Result<Vec<Flow>, Error> {
+        self.request(req).await?;
+        let mut flows = Vec::new();
+        while let Some(events) = self.try_next().await? {
+            flows.extend(events.into_iter().filter_map(|event| match event {
+                Event::Flow(flow) => Some(flow),
+                _ => None,
+            }));
+        }
+        Ok(flows)
+    }
+
+    // Regroup events into chunks of the given size regardless of how many events each datagram has.
+    // It panics when the size is zero.
+    pub fn chunks(self, size: usize) -> Chunks<Self> {
+        assert!(size > 0, "chunk size must be greater than zero");
+        Chunks {
+            inner: self,
+            size,
+            buf: Vec::with_capacity(size),
+            done: false,
+        }
+    }
 }
 
+// Events are yielded batch by batch as they arrive from the socket.
+// This is the incremental way to read a large table without buffering all entries.
 impl<S> Stream for Conntrack<S>
 where
     S: ConntrackSocket + Stream<Item = Result<Vec<Message>, Error>>,
@@ -93,25 +267,39 @@ where
     ) -> std::task::Poll<Option<Self::Item>> {
         // clone the filter object to use each flow.
         let filter_opt = self.filter.clone();
-        match self.project().socket.poll_next(cx) {
+        let keep_raw = self.keep_raw;
+        let skip_incomplete = self.skip_incomplete;
+        let this = self.project();
+        match this.socket.poll_next(cx) {
             Poll::Ready(msgs) => match msgs {
                 Some(msgs) => match msgs {
                     Ok(msgs) => {
-                        let events: Result<Vec<Event>, Error> =
-                            msgs.iter().map(Event::try_from).collect();
-                        let events = match events {
-                            Ok(f) => f
-                                .into_iter()
-                                .filter(|e| match &filter_opt {
-                                    Some(filter) => match e {
-                                        Event::Flow(f) => filter.apply(f),
-                                        _ => false,
-                                    },
-                                    None => true,
-                                })
-                                .collect(),
-                            Err(e) => return Poll::Ready(Some(Err(e))),
-                        };
+                        let mut events = Vec::with_capacity(msgs.len());
+                        for msg in msgs.iter() {
+                            let event = match Event::from_message(msg, keep_raw) {
+                                Ok(Event::Flow(f)) if skip_incomplete && f.has_orphan_reply() => {
+                                    *this.skipped += 1;
+                                    continue;
+                                }
+                                Ok(event) => event,
+                                // Only the flow is dropped and the rest of the dump continues.
+                                Err(Error::Flow(_)) if skip_incomplete => {
+                                    *this.skipped += 1;
+                                    continue;
+                                }
+                                Err(e) => return Poll::Ready(Some(Err(e))),
+                            };
+                            let matched = match &filter_opt {
+                                Some(filter) => match &event {
+                                    Event::Flow(f) => filter.apply(f),
+                                    _ => false,
+                                },
+                                None => true,
+                            };
+                            if matched {
+                                events.push(event);
+                            }
+                        }
                         Poll::Ready(Some(Ok(events)))
                     }
                     Err(e) => Poll::Ready(Some(Err(e))),
@@ -123,8 +311,55 @@ where
     }
 }
 
+pin_project! {
+    // Events from a stream in chunks of a fixed size, e.g. to insert them into a database in fixed transaction sizes.
+    // The last chunk may be smaller. An error is yielded as soon as it occurs and buffered events are kept.
+    pub struct Chunks<S> {
+        #[pin]
+        inner: S,
+        size: usize,
+        buf: Vec<Event>,
+        done: bool,
+    }
+}
+
+impl<S> Stream for Chunks<S>
+where
+    S: Stream<Item = Result<Vec<Event>, Error>>,
+{
+    type Item = Result<Vec<Event>, Error>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if this.buf.len() >= *this.size {
+                let rest = this.buf.split_off(*this.size);
+                return Poll::Ready(Some(Ok(std::mem::replace(this.buf, rest))));
+            }
+            if *this.done {
+                if this.buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                return Poll::Ready(Some(Ok(std::mem::take(this.buf))));
+            }
+            match ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(events)) => this.buf.extend(events),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => *this.done = true,
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Family {
+    // A dump with AF_UNSPEC returns entries of all families in a single dump.
+    // ctnetlink checks the l3 protocol of entries only when the family of the request is set,
+    // so ipv4 and ipv6 entries come interleaved in the order of the hash table.
+    // There is no fallback to a dump per family since every kernel with ctnetlink dumps all families for AF_UNSPEC.
     Unspec,
     #[default]
     Ipv4,
@@ -132,6 +367,14 @@ pub enum Family {
 }
 
 impl Family {
+    // The family of an address. An address never has Unspec.
+    pub fn from_addr(addr: IpAddr) -> Family {
+        match addr {
+            IpAddr::V4(_) => Family::Ipv4,
+            IpAddr::V6(_) => Family::Ipv6,
+        }
+    }
+
     pub fn is_matched(&self, addr: IpAddr) -> bool {
         match self {
             Family::Unspec => true,
@@ -175,7 +418,7 @@ impl TryFrom<&str> for Family {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize)]
 pub enum Table {
     #[default]
     Conntrack,
@@ -196,16 +439,34 @@ impl TryFrom<&str> for Table {
     }
 }
 
+impl From<Table> for String {
+    fn from(t: Table) -> Self {
+        match t {
+            Table::Conntrack => String::from("conntrack"),
+            Table::Dying => String::from("dying"),
+            Table::Unconfirmed => String::from("unconfirmed"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use futures::TryStreamExt;
+    use netlink_packet_netfilter::ctnetlink::{
+        message::CtNetlinkMessage, nlas::stat::nla::StatNla,
+    };
+    use rstest::rstest;
 
     use crate::{
+        error::Error,
+        event::Event,
         flow::{Flow, FlowBuilder, Protocol, Status, TcpState, TupleBuilder},
-        message::MessageType,
-        request::{Filter, Request, RequestMeta, RequestOperation},
+        message::{Message, MessageType},
+        request::{Filter, FilterSet, Request, RequestMeta, RequestOperation},
         socket::MockConntrackSocket,
-        Conntrack, ConntrackOption, Family,
+        Conntrack, ConntrackOption, Family, Table,
     };
 
     fn ipv4_tcp_flow() -> Flow {
@@ -269,6 +530,134 @@ mod tests {
             .unwrap()
     }
 
+    #[tokio::test]
+    async fn test_conntrack_abort() {
+        let ipv4_flows = vec![ipv4_tcp_flow(), ipv4_tcp_flow(), ipv4_tcp_flow()];
+        let mock_socket = MockConntrackSocket::with_flow(ipv4_flows, vec![]);
+        let mut ct = Conntrack::with_socket(mock_socket);
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(None),
+        ))
+        .await
+        .unwrap();
+        // Stop reading in the middle of the dump.
+        let flows = ct.try_next().await.unwrap().unwrap();
+        assert_eq!(flows.len(), 1);
+        ct.abort().await.unwrap();
+        // The rest of the dump is consumed.
+        assert_eq!(ct.socket.drained, 2);
+
+        // The next request starts a new dump.
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(None),
+        ))
+        .await
+        .unwrap();
+        let mut received = 0;
+        while let Some(flows) = ct.try_next().await.unwrap() {
+            received += flows.len();
+        }
+        assert_eq!(received, 3);
+    }
+
+    #[rstest(
+        size,
+        expected,
+        case(1, vec![1, 1, 1, 1, 1]),
+        case(2, vec![2, 2, 1]),
+        case(5, vec![5]),
+        case(10, vec![5])
+    )]
+    #[tokio::test]
+    async fn test_conntrack_chunks(size: usize, expected: Vec<usize>) {
+        let ipv4_flows = vec![ipv4_tcp_flow(); 5];
+        let mock_socket = MockConntrackSocket::with_flow(ipv4_flows, vec![]);
+        let mut ct = Conntrack::with_socket(mock_socket);
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(None),
+        ))
+        .await
+        .unwrap();
+        let sizes = ct
+            .chunks(size)
+            .map_ok(|events| events.len())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(sizes, expected);
+    }
+
+    #[rstest(skip_incomplete, case(true), case(false))]
+    #[tokio::test]
+    async fn test_conntrack_skip_incomplete(skip_incomplete: bool) {
+        let mut orphan = ipv4_tcp_flow();
+        orphan.reply.src_port = 0;
+        orphan.reply.dst_port = 0;
+        // A flow without tuples fails to be built.
+        let msgs = [
+            CtNetlinkMessage::New(vec![]),
+            CtNetlinkMessage::try_from(&orphan).unwrap(),
+            CtNetlinkMessage::try_from(&ipv4_tcp_flow()).unwrap(),
+        ]
+        .into_iter()
+        .map(|msg| Message::new(msg, 0, 0))
+        .collect();
+        let mock_socket = MockConntrackSocket::with_event(msgs, vec![]);
+        let mut ct = Conntrack {
+            socket: mock_socket,
+            filter: None,
+            keep_raw: false,
+            skip_incomplete,
+            skipped: 0,
+        };
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(None),
+        ))
+        .await
+        .unwrap();
+        let res = (&mut ct).try_concat().await;
+        if skip_incomplete {
+            assert_eq!(res.unwrap().len(), 1);
+            assert_eq!(ct.skipped(), 2);
+        } else {
+            assert!(matches!(res, Err(Error::Flow(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conntrack_drain_dump() {
+        let ipv4_flows = vec![ipv4_tcp_flow(), ipv4_tcp_flow(), ipv4_tcp_flow()];
+        let mock_socket = MockConntrackSocket::with_flow(ipv4_flows, vec![]);
+        let mut ct = Conntrack::with_socket(mock_socket);
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(None),
+        ))
+        .await
+        .unwrap();
+        // Stop reading in the middle of the dump.
+        let flows = ct.try_next().await.unwrap().unwrap();
+        assert_eq!(flows.len(), 1);
+        let mut ct = ct.drain_dump(Duration::from_secs(1)).await.unwrap();
+
+        // The next request starts a new dump.
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(None),
+        ))
+        .await
+        .unwrap();
+        let mut received = 0;
+        while let Some(flows) = ct.try_next().await.unwrap() {
+            received += flows.len();
+        }
+        assert_eq!(received, 3);
+    }
+
     #[tokio::test]
     async fn test_conntrack_poll_by_poll() {
         let base_ipv4_tcp_flow = ipv4_tcp_flow();
@@ -360,6 +749,25 @@ mod tests {
         }
         assert_eq!(received, 1);
 
+        // filter any of orig_src_addr or l4 protocol
+        ct.request(Request::new(
+            RequestMeta::default().family(Family::Unspec),
+            RequestOperation::List(Some(
+                Filter::default().matches_any(
+                    FilterSet::default()
+                        .or(Filter::default().orig_src_addr("1.1.1.2/32".parse().unwrap()))
+                        .or(Filter::default().protocol(Protocol::Udp)),
+                ),
+            )),
+        ))
+        .await
+        .unwrap();
+        let mut received = 0;
+        while let Some(flows) = ct.try_next().await.unwrap() {
+            received += flows.len();
+        }
+        assert_eq!(received, 2);
+
         // no matched flows
         ct.request(Request::new(
             RequestMeta::default().family(Family::Ipv6),
@@ -374,6 +782,107 @@ mod tests {
         assert_eq!(received, 0);
     }
 
+    #[rstest(
+        family,
+        filter,
+        expected,
+        case(Family::Unspec, None, 3),
+        case(Family::Ipv4, None, 2),
+        case(Family::Unspec, Some(Filter::default().protocol(Protocol::Udp)), 1)
+    )]
+    #[tokio::test]
+    async fn test_conntrack_list_vec(family: Family, filter: Option<Filter>, expected: usize) {
+        let mut ipv4_tcp_time_wait = ipv4_tcp_flow();
+        ipv4_tcp_time_wait.tcp_state = Some(TcpState::TimeWait);
+        let mock_socket = MockConntrackSocket::with_flow(
+            vec![ipv4_tcp_flow(), ipv4_tcp_time_wait],
+            vec![ipv6_udp_flow()],
+        );
+        let mut ct = Conntrack::with_socket(mock_socket);
+        let flows = ct
+            .list_vec(Request::new(
+                RequestMeta::default().family(family),
+                RequestOperation::List(filter),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(flows.len(), expected);
+    }
+
+    #[rstest(
+        reply,
+        expected,
+        case(Some(CtNetlinkMessage::GetStats(Some(vec![StatNla::Searched(42)]))), Some(42)),
+        case(Some(CtNetlinkMessage::GetStats(None)), None),
+        // No reply is received.
+        case(None, None)
+    )]
+    #[tokio::test]
+    async fn test_conntrack_count(reply: Option<CtNetlinkMessage>, expected: Option<u32>) {
+        let msgs = reply
+            .into_iter()
+            .map(|msg| Message::new(msg, 0, 0))
+            .collect();
+        let mock_socket = MockConntrackSocket::with_event(msgs, vec![]);
+        let mut ct = Conntrack::with_socket(mock_socket);
+        let res = ct.count(Family::Ipv4, Table::Conntrack).await;
+        match expected {
+            Some(count) => assert_eq!(res.unwrap(), count),
+            None => assert!(matches!(res, Err(Error::CounterNotAvailable))),
+        }
+    }
+
+    // A netlink attribute with padding.
+    fn nla(kind: u16, value: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&((value.len() + 4) as u16).to_ne_bytes());
+        buf.extend_from_slice(&kind.to_ne_bytes());
+        buf.extend_from_slice(value);
+        buf.resize(buf.len().next_multiple_of(4), 0);
+        buf
+    }
+
+    // An ICMP echo request as received, whose attributes keep CTA_PROTO_ICMP_* of the original tuple.
+    fn icmp_message(id: u16) -> Message {
+        let mut flow = ipv4_tcp_flow();
+        flow.protocol = Protocol::Other(1);
+        flow.tcp_state = None;
+        let mut proto = nla(1, &[1]);
+        proto.extend(nla(4, &id.to_be_bytes()));
+        proto.extend(nla(5, &[8]));
+        proto.extend(nla(6, &[0]));
+        // The netlink header and the nfgenmsg header precede attributes.
+        let mut bytes = vec![0; 20];
+        bytes.extend(nla(1 | 0x8000, &nla(2 | 0x8000, &proto)));
+        Message::new(CtNetlinkMessage::try_from(&flow).unwrap(), 0, 0).with_bytes(&bytes)
+    }
+
+    #[tokio::test]
+    async fn test_conntrack_icmp_id() {
+        let msgs = vec![
+            icmp_message(1234),
+            icmp_message(4321),
+            Message::new(CtNetlinkMessage::try_from(&ipv4_tcp_flow()).unwrap(), 0, 0),
+        ];
+        let mock_socket = MockConntrackSocket::with_event(msgs, vec![]);
+        let mut ct = Conntrack::with_socket(mock_socket);
+        ct.request(Request::new(
+            RequestMeta::default(),
+            RequestOperation::List(Some(Filter::default().icmp_id(1234))),
+        ))
+        .await
+        .unwrap();
+        let events = (&mut ct).try_concat().await.unwrap();
+        assert_eq!(events.len(), 1);
+        let Event::Flow(flow) = &events[0] else {
+            panic!("a flow is expected");
+        };
+        assert_eq!(
+            flow.icmp.map(|icmp| (icmp.id, icmp.r#type)),
+            Some((1234, 8))
+        );
+    }
+
     #[ignore = "With privilege"]
     #[tokio::test]
     async fn test_conntrack_poll_with_privilege() {
@@ -391,4 +900,80 @@ mod tests {
             .expect("Failed to parse Flow from CtNetlink message")
         {}
     }
+
+    async fn list_family(family: Family) -> Vec<Flow> {
+        Conntrack::new(ConntrackOption::default())
+            .unwrap()
+            .list_vec(Request::new(
+                RequestMeta::default().family(family),
+                RequestOperation::List(None),
+            ))
+            .await
+            .unwrap()
+    }
+
+    #[ignore = "With privilege"]
+    #[tokio::test]
+    async fn test_conntrack_list_unspec_with_privilege() {
+        // Make entries of both families on loopback. Conntrack must be enabled in the namespace to track them.
+        let socks = ["127.0.0.1:0", "[::1]:0"].map(|addr| {
+            let sock = std::net::UdpSocket::bind(addr).unwrap();
+            sock.send_to(b"rconntrack", sock.local_addr().unwrap())
+                .unwrap();
+            sock.local_addr().unwrap()
+        });
+        let is_made = |f: &Flow| {
+            socks
+                .iter()
+                .any(|addr| f.original.src_addr == addr.ip() && f.original.src_port == addr.port())
+        };
+        let ipv4 = list_family(Family::Ipv4).await;
+        let ipv6 = list_family(Family::Ipv6).await;
+        let unspec = list_family(Family::Unspec).await;
+
+        assert!(ipv4
+            .iter()
+            .any(|f| is_made(f) && f.original.src_addr.is_ipv4()));
+        assert!(ipv6
+            .iter()
+            .any(|f| is_made(f) && f.original.src_addr.is_ipv6()));
+        assert!(unspec
+            .iter()
+            .any(|f| is_made(f) && f.original.src_addr.is_ipv4()));
+        assert!(unspec
+            .iter()
+            .any(|f| is_made(f) && f.original.src_addr.is_ipv6()));
+    }
+
+    #[ignore = "With privilege"]
+    #[tokio::test]
+    async fn test_conntrack_port_id_with_privilege() {
+        // Pick a portid unlikely to be used by other processes.
+        let opt = ConntrackOption::default().set_port_id(0x7fff_0001);
+        let ct = Conntrack::new(opt.clone()).unwrap();
+        assert!(matches!(
+            Conntrack::new(opt.clone()),
+            Err(Error::PortIdInUse(0x7fff_0001))
+        ));
+        drop(ct);
+        assert!(Conntrack::new(opt).is_ok());
+    }
+
+    #[rstest(
+        addr,
+        expected,
+        case("10.0.0.1", Family::Ipv4),
+        case("::ffff:10.0.0.1", Family::Ipv6),
+        case("fd00::1", Family::Ipv6)
+    )]
+    fn test_family_from_addr(addr: &str, expected: Family) {
+        let family = Family::from_addr(addr.parse().unwrap());
+        assert_eq!(family, expected);
+        assert!(family.is_matched(addr.parse().unwrap()));
+    }
+
+    #[rstest(family, case(Family::Unspec), case(Family::Ipv4), case(Family::Ipv6))]
+    fn test_family_u8_round_trip(family: Family) {
+        assert_eq!(Family::try_from(u8::from(family)).unwrap(), family);
+    }
 }
@@ -1,29 +1,69 @@
 use netlink_packet_core::{
-    NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_DUMP, NLM_F_MATCH, NLM_F_ROOT,
+    NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_DUMP, NLM_F_MATCH,
+    NLM_F_REPLACE, NLM_F_ROOT,
 };
 use netlink_packet_netfilter::{
     constants::{NFNETLINK_V0, NLM_F_REQUEST},
-    ctnetlink::{message::CtNetlinkMessage, nlas::flow::nla::FlowNla},
+    ctnetlink::{
+        message::CtNetlinkMessage,
+        nlas::flow::{ip_tuple::TupleNla, nla::FlowNla},
+    },
     NetfilterHeader, NetfilterMessage, NetfilterMessageInner,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{request::GetParams, Family, Table};
+use crate::{
+    request::{DeleteParams, GetParams, UpdateParams},
+    Family, Table,
+};
 
 #[derive(Debug, Clone)]
 pub struct Message {
     pub flag: u16,
     pub res_id: u16,
     pub msg: CtNetlinkMessage,
+    // Attributes of the message as received.
+    // netlink_packet_netfilter drops CTA_PROTO_ICMP_* while parsing, so they are read from here.
+    // This is kept only for ICMP flows and empty otherwise.
+    pub(crate) attrs: Vec<u8>,
 }
 
 impl Message {
     pub fn new(msg: CtNetlinkMessage, flag: u16, res_id: u16) -> Message {
-        Message { flag, msg, res_id }
+        Message {
+            flag,
+            msg,
+            res_id,
+            attrs: Vec::new(),
+        }
+    }
+
+    // Keep the attributes of an ICMP flow from `bytes`, the whole netlink message as received.
+    pub fn with_bytes(mut self, bytes: &[u8]) -> Message {
+        // The netlink header and the nfgenmsg header precede attributes.
+        const HEADER_LEN: usize = 16 + 4;
+        if has_icmp_tuple(&self.msg) {
+            self.attrs = bytes.get(HEADER_LEN..).unwrap_or_default().to_vec();
+        }
+        self
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+fn has_icmp_tuple(msg: &CtNetlinkMessage) -> bool {
+    const IPPROTO_ICMP: u8 = 1;
+    const IPPROTO_ICMPV6: u8 = 58;
+    let (CtNetlinkMessage::New(nlas) | CtNetlinkMessage::Delete(nlas)) = msg else {
+        return false;
+    };
+    nlas.iter().any(|nla| match nla {
+        FlowNla::Orig(tuple) => tuple.iter().any(|t| {
+            matches!(t, TupleNla::Protocol(p) if p.protocol == IPPROTO_ICMP || p.protocol == IPPROTO_ICMPV6)
+        }),
+        _ => false,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MessageType {
     New = 1,
     Update = 2,
@@ -50,6 +90,21 @@ impl MessageGroup {
         self.inner |= t as u32;
         self
     }
+
+    // Subscribe new, update and destroy events.
+    pub fn all() -> Self {
+        MessageGroup::default()
+            .set(MessageType::Update)
+            .set(MessageType::Destroy)
+    }
+}
+
+// Unlike default(), only the given types are set.
+impl FromIterator<MessageType> for MessageGroup {
+    fn from_iter<I: IntoIterator<Item = MessageType>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(MessageGroup::from(0), |group, t| group.set(t))
+    }
 }
 
 impl Default for MessageGroup {
@@ -79,6 +134,7 @@ pub(super) struct MessageBuilder {
     res_id: u16,
     flag: u16,
     zero: bool,
+    filter: Vec<FlowNla>,
 }
 
 impl MessageBuilder {
@@ -89,6 +145,7 @@ impl MessageBuilder {
             res_id: 0,
             flag: NLM_F_REQUEST, // MessageBuilder will be used for building a request message.
             zero: false,
+            filter: Vec::new(),
         }
     }
 
@@ -103,6 +160,13 @@ impl MessageBuilder {
         self
     }
 
+    // Attributes to filter a dump in the kernel.
+    // The kernel supports filtering only for the conntrack table.
+    pub(super) fn filter(mut self, nlas: Vec<FlowNla>) -> MessageBuilder {
+        self.filter = nlas;
+        self
+    }
+
     pub(super) fn res_id(mut self, id: u16) -> MessageBuilder {
         self.res_id = id;
         self
@@ -113,12 +177,17 @@ impl MessageBuilder {
         hdr.flags = self.flag | NLM_F_DUMP;
         // Should we set sequence number?
 
+        let filter = if self.filter.is_empty() {
+            None
+        } else {
+            Some(self.filter.clone())
+        };
         let mut msg = if self.zero {
             NetlinkMessage::new(
                 hdr,
                 NetlinkPayload::from(NetfilterMessage::new(
                     NetfilterHeader::new(self.family.into(), NFNETLINK_V0, self.res_id),
-                    CtNetlinkMessage::GetCrtZero(None),
+                    CtNetlinkMessage::GetCrtZero(filter),
                 )),
             )
         } else {
@@ -127,7 +196,7 @@ impl MessageBuilder {
                     hdr,
                     NetlinkPayload::from(NetfilterMessage::new(
                         NetfilterHeader::new(self.family.into(), NFNETLINK_V0, self.res_id),
-                        CtNetlinkMessage::Get(None),
+                        CtNetlinkMessage::Get(filter),
                     )),
                 ),
                 Table::Dying => NetlinkMessage::new(
@@ -176,6 +245,38 @@ impl MessageBuilder {
         msg
     }
 
+    pub(super) fn delete(&self, param: &DeleteParams) -> NetlinkMessage<NetfilterMessage> {
+        let mut hdr = NetlinkHeader::default();
+        // Ask the kernel to reply an acknowledgement because nothing is replied on success.
+        hdr.flags = self.flag | NLM_F_ACK;
+        let nlas = Vec::<FlowNla>::from(param);
+        let mut msg = NetlinkMessage::new(
+            hdr,
+            NetlinkPayload::from(NetfilterMessage::new(
+                NetfilterHeader::new(self.family.into(), NFNETLINK_V0, self.res_id),
+                CtNetlinkMessage::Delete(nlas),
+            )),
+        );
+        msg.finalize();
+        msg
+    }
+
+    pub(super) fn update(&self, param: &UpdateParams) -> NetlinkMessage<NetfilterMessage> {
+        let mut hdr = NetlinkHeader::default();
+        // Without NLM_F_CREATE, the kernel only changes an existing entry and never creates a new one.
+        hdr.flags = self.flag | NLM_F_REPLACE | NLM_F_ACK;
+        let nlas = Vec::<FlowNla>::from(param);
+        let mut msg = NetlinkMessage::new(
+            hdr,
+            NetlinkPayload::from(NetfilterMessage::new(
+                NetfilterHeader::new(self.family.into(), NFNETLINK_V0, self.res_id),
+                CtNetlinkMessage::New(nlas),
+            )),
+        );
+        msg.finalize();
+        msg
+    }
+
     pub(super) fn count(&self) -> NetlinkMessage<NetfilterMessage> {
         let mut hdr = NetlinkHeader::default();
         hdr.flags = self.flag;
@@ -208,17 +309,22 @@ impl MessageBuilder {
 
 #[cfg(test)]
 mod tests {
-    use netlink_packet_core::NetlinkPayload;
+    use netlink_packet_core::{NetlinkPayload, NLM_F_ACK, NLM_F_CREATE, NLM_F_REPLACE};
     use netlink_packet_netfilter::{
         constants::{AF_INET, AF_INET6, NFNETLINK_V0},
-        ctnetlink::message::CtNetlinkMessage,
+        ctnetlink::{message::CtNetlinkMessage, nlas::flow::nla::FlowNla},
         NetfilterHeader, NetfilterMessage, NetfilterMessageInner,
     };
     use rstest::rstest;
+    use std::{net::IpAddr, str::FromStr};
 
-    use crate::{Family, Table};
+    use crate::{
+        flow::{Protocol, Tuple},
+        request::{DeleteParams, Direction, UpdateParams},
+        Family, Table,
+    };
 
-    use super::MessageBuilder;
+    use super::{MessageBuilder, MessageGroup, MessageType};
 
     const NF_HDR_IPV4: NetfilterHeader = NetfilterHeader {
         family: AF_INET,
@@ -259,6 +365,14 @@ mod tests {
         case(MessageBuilder::new(Family::Ipv6, Table::Conntrack), BASE_LIST_IPV6),
         case(MessageBuilder::default().zero(), LIST_ZERO),
         case(MessageBuilder::new(Family::Ipv4, Table::Dying), LIST_DYING),
+        case(MessageBuilder::new(Family::Ipv4, Table::Dying).filter(vec![FlowNla::Mark(1)]), LIST_DYING),
+        case(
+            MessageBuilder::default().filter(vec![FlowNla::Mark(1)]),
+            NetfilterMessage {
+                header: NF_HDR_IPV4,
+                inner: NetfilterMessageInner::CtNetlink(CtNetlinkMessage::Get(Some(vec![FlowNla::Mark(1)]))),
+            }
+        ),
     )]
     fn test_message_builder_list(builder: MessageBuilder, expected: NetfilterMessage) {
         let payload = builder.list().payload;
@@ -269,4 +383,71 @@ mod tests {
             panic!("NetlinkPayload::InnerMessage(msg) is expected, but got {payload_type}")
         }
     }
+
+    #[rstest]
+    fn test_message_builder_delete() {
+        let param = DeleteParams::new(
+            Protocol::Tcp,
+            Direction::Orig(Tuple {
+                src_addr: IpAddr::from_str("10.0.0.1").unwrap(),
+                dst_addr: IpAddr::from_str("10.0.0.2").unwrap(),
+                src_port: 12345,
+                dst_port: 80,
+            }),
+        )
+        .id(1234);
+        let msg = MessageBuilder::default().delete(&param);
+        assert_ne!(msg.header.flags & NLM_F_ACK, 0);
+        if let NetlinkPayload::InnerMessage(NetfilterMessage {
+            inner: NetfilterMessageInner::CtNetlink(CtNetlinkMessage::Delete(nlas)),
+            ..
+        }) = msg.payload
+        {
+            assert!(matches!(nlas.first(), Some(FlowNla::Orig(_))));
+            assert_eq!(nlas.last(), Some(&FlowNla::Id(1234)));
+        } else {
+            panic!("CtNetlinkMessage::Delete is expected")
+        }
+    }
+
+    #[rstest]
+    fn test_message_builder_update() {
+        let param = UpdateParams::new(
+            Protocol::Tcp,
+            Direction::Orig(Tuple {
+                src_addr: IpAddr::from_str("10.0.0.1").unwrap(),
+                dst_addr: IpAddr::from_str("10.0.0.2").unwrap(),
+                src_port: 12345,
+                dst_port: 80,
+            }),
+        )
+        .mark(1)
+        .timeout(60);
+        let msg = MessageBuilder::default().update(&param);
+        assert_ne!(msg.header.flags & NLM_F_REPLACE, 0);
+        assert_ne!(msg.header.flags & NLM_F_ACK, 0);
+        assert_eq!(msg.header.flags & NLM_F_CREATE, 0);
+        if let NetlinkPayload::InnerMessage(NetfilterMessage {
+            inner: NetfilterMessageInner::CtNetlink(CtNetlinkMessage::New(nlas)),
+            ..
+        }) = msg.payload
+        {
+            assert!(matches!(nlas.first(), Some(FlowNla::Orig(_))));
+            assert_eq!(nlas[1..], [FlowNla::Timeout(60), FlowNla::Mark(1)]);
+        } else {
+            panic!("CtNetlinkMessage::New is expected")
+        }
+    }
+
+    #[rstest(
+        group,
+        expected,
+        case(MessageGroup::default(), 1),
+        case(MessageGroup::all(), 1 | 2 | 4),
+        case(MessageGroup::from_iter([MessageType::Destroy]), 4),
+        case(MessageGroup::from_iter([MessageType::New, MessageType::Update]), 1 | 2),
+    )]
+    fn test_message_group(group: MessageGroup, expected: u32) {
+        assert_eq!(u32::from(group), expected);
+    }
 }
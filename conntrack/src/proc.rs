@@ -0,0 +1,272 @@
+use std::path::PathBuf;
+
+use crate::{
+    error::Error,
+    flow::{Counter, Flow, FlowBuilder, FlowError, Icmp, Protocol, Status, TcpState, TupleBuilder},
+    message::MessageType,
+    request::Filter,
+};
+
+// Read-only access to the conntrack table through the procfs text format.
+// This is useful on systems where netlink is blocked but /proc/net/nf_conntrack is readable.
+// Only the conntrack table is available and events can't be subscribed.
+#[derive(Debug, Clone)]
+pub struct ProcConntrackSource {
+    path: PathBuf,
+}
+
+impl Default for ProcConntrackSource {
+    fn default() -> Self {
+        ProcConntrackSource::new(ProcConntrackSource::DEFAULT_PATH)
+    }
+}
+
+impl ProcConntrackSource {
+    pub const DEFAULT_PATH: &str = "/proc/net/nf_conntrack";
+
+    pub fn new(path: impl Into<PathBuf>) -> ProcConntrackSource {
+        ProcConntrackSource { path: path.into() }
+    }
+
+    // Read all entries and return flows which match the filter.
+    // procfs has no way to filter entries, so the filter is applied after parsing.
+    pub async fn list(&self, filter: Option<&Filter>) -> Result<Vec<Flow>, Error> {
+        let s = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(Error::Proc)?;
+        let mut flows = Vec::new();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let flow = parse_proc_line(line).map_err(Error::Flow)?;
+            if filter.is_none_or(|f| f.apply(&flow)) {
+                flows.push(flow);
+            }
+        }
+        Ok(flows)
+    }
+}
+
+// Parse a line of /proc/net/nf_conntrack like
+// "ipv4 2 tcp 6 431999 ESTABLISHED src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234 [ASSURED] mark=0 zone=0 use=2".
+// The first group of src, dst, sport and dport is the original tuple and the second one is the reply tuple.
+// Only the TCP state is parsed because the procfs format doesn't have the rest of the protocol info.
+pub fn parse_proc_line(line: &str) -> Result<Flow, FlowError> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [_l3, _l3num, _l4, l4num, timeout, rest @ ..] = fields.as_slice() else {
+        return Err(FlowError::InvalidFormat(line.to_string()));
+    };
+    let protocol = Protocol::from(parse_number::<u8>(l4num)?);
+    let mut builder = FlowBuilder::default()
+        .event_type(MessageType::Update)
+        .protocol(protocol)
+        .timeout(parse_number(timeout)?);
+
+    // Protocols without ports like ICMP have zero ports as netlink dumps do.
+    let mut tuples = [
+        TupleBuilder::default().src_port(0).dst_port(0),
+        TupleBuilder::default().src_port(0).dst_port(0),
+    ];
+    let mut counters = [None::<Counter>, None];
+    let mut icmp = None::<Icmp>;
+    let mut srcs = 0;
+    // Entries in the table are always confirmed.
+    let mut status = u16::from(&Status::confirmed());
+    let mut replied = true;
+    for field in rest {
+        let Some((key, value)) = field.split_once('=') else {
+            match *field {
+                "[UNREPLIED]" => replied = false,
+                "[ASSURED]" => status |= u16::from(&Status::assured()),
+                "[OFFLOAD]" | "[HW_OFFLOAD]" => status |= u16::from(&Status::offload()),
+                // The state comes before the tuples.
+                s if srcs == 0 && protocol.eq(&Protocol::Tcp) => {
+                    builder = builder.tcp_state(TcpState::try_from(s)?)
+                }
+                _ => {}
+            }
+            continue;
+        };
+        if key == "src" {
+            srcs += 1;
+        }
+        let dir = srcs.clamp(1, 2) - 1;
+        match key {
+            "src" => tuples[dir] = std::mem::take(&mut tuples[dir]).src_addr(parse_addr(value)?),
+            "dst" => tuples[dir] = std::mem::take(&mut tuples[dir]).dst_addr(parse_addr(value)?),
+            "sport" => {
+                tuples[dir] = std::mem::take(&mut tuples[dir]).src_port(parse_number(value)?)
+            }
+            "dport" => {
+                tuples[dir] = std::mem::take(&mut tuples[dir]).dst_port(parse_number(value)?)
+            }
+            // ICMP fields of the reply tuple are derived from the original ones.
+            "type" if dir == 0 => icmp.get_or_insert_default().r#type = parse_number(value)?,
+            "code" if dir == 0 => icmp.get_or_insert_default().code = parse_number(value)?,
+            "id" if dir == 0 => icmp.get_or_insert_default().id = parse_number(value)?,
+            "packets" => counters[dir].get_or_insert_default().packets = parse_number(value)?,
+            "bytes" => counters[dir].get_or_insert_default().bytes = parse_number(value)?,
+            "mark" => builder = builder.mark(parse_number(value)?),
+            "use" => builder = builder.r#use(parse_number(value)?),
+            "secctx" => builder = builder.secctx(value.to_string()),
+            _ => {}
+        }
+    }
+    if replied {
+        status |= u16::from(&Status::seen_reply());
+    }
+    let [original, reply] = tuples;
+    builder = builder
+        .original(original.build()?)
+        .reply(reply.build()?)
+        .status(Status::from(status));
+    if let Some(c) = counters[0] {
+        builder = builder.orig_counter(c);
+    }
+    if let Some(c) = counters[1] {
+        builder = builder.reply_counter(c);
+    }
+    if let Some(icmp) = icmp {
+        builder = builder.icmp(icmp);
+    }
+    builder.build()
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, FlowError> {
+    s.parse::<T>()
+        .map_err(|_| FlowError::InvalidFormat(s.to_string()))
+}
+
+fn parse_addr(s: &str) -> Result<std::net::IpAddr, FlowError> {
+    s.parse()
+        .map_err(|_| FlowError::InvalidFormat(s.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{
+        flow::{Counter, Icmp, Protocol, Status, TcpState},
+        request::Filter,
+    };
+
+    use super::{parse_proc_line, ProcConntrackSource};
+
+    #[rstest(
+        line,
+        protocol,
+        tcp_state,
+        status,
+        case(
+            "ipv4     2 tcp      6 431999 ESTABLISHED src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234 [ASSURED] mark=0 zone=0 use=2",
+            Protocol::Tcp,
+            Some(TcpState::Established),
+            (1 << 1) | (1 << 2) | (1 << 3)
+        ),
+        case(
+            "ipv4     2 tcp      6 119 SYN_SENT2 src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234 mark=0 zone=0 use=2",
+            Protocol::Tcp,
+            Some(TcpState::Listen),
+            (1 << 1) | (1 << 3)
+        ),
+        case(
+            "ipv4     2 udp      17 29 src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=53 [UNREPLIED] src=10.0.0.2 dst=10.0.0.1 sport=53 dport=1234 mark=0 zone=0 use=2",
+            Protocol::Udp,
+            None,
+            1 << 3
+        ),
+        case(
+            "ipv6     10 icmpv6   58 29 src=fd00::1 dst=fd00::2 type=128 code=0 id=1 src=fd00::2 dst=fd00::1 type=129 code=0 id=1 mark=0 zone=0 use=2",
+            Protocol::Other(58),
+            None,
+            (1 << 1) | (1 << 3)
+        )
+    )]
+    fn test_parse_proc_line(
+        line: &str,
+        protocol: Protocol,
+        tcp_state: Option<TcpState>,
+        status: u16,
+    ) {
+        let flow = parse_proc_line(line).unwrap();
+        assert_eq!(flow.protocol, protocol);
+        assert_eq!(flow.tcp_state, tcp_state);
+        assert_eq!(u16::from(&flow.status), status);
+        assert_eq!(flow.original.src_addr, flow.reply.dst_addr);
+        assert_eq!(flow.original.dst_addr, flow.reply.src_addr);
+        assert_eq!(flow.original.src_port, flow.reply.dst_port);
+        assert_eq!(flow.r#use, Some(2));
+    }
+
+    #[rstest(
+        line,
+        expected,
+        case("ipv4 2 icmp 1 29 src=10.0.0.1 dst=10.0.0.2 type=8 code=0 id=4660 src=10.0.0.2 dst=10.0.0.1 type=0 code=0 id=4660 mark=0 use=1", Some(Icmp { r#type: 8, code: 0, id: 4660 })),
+        case("ipv6 10 icmpv6 58 29 src=fd00::1 dst=fd00::2 type=128 code=0 id=1 src=fd00::2 dst=fd00::1 type=129 code=0 id=1 mark=0 use=1", Some(Icmp { r#type: 128, code: 0, id: 1 })),
+        case("ipv4 2 udp 17 29 src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=53 src=10.0.0.2 dst=10.0.0.1 sport=53 dport=1234 mark=0 use=1", None),
+    )]
+    fn test_parse_proc_line_icmp(line: &str, expected: Option<Icmp>) {
+        let flow = parse_proc_line(line).unwrap();
+        assert_eq!(flow.icmp, expected);
+        assert_eq!(
+            Filter::default().icmp_id(4660).apply(&flow),
+            expected.is_some_and(|icmp| icmp.id == 4660)
+        );
+    }
+
+    #[test]
+    fn test_parse_proc_line_counters() {
+        let flow = parse_proc_line("ipv4 2 tcp 6 100 TIME_WAIT src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80 packets=5 bytes=300 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234 packets=4 bytes=1200 [ASSURED] mark=1 use=1").unwrap();
+        assert_eq!(flow.timeout, 100);
+        assert_eq!(flow.mark, Some(1));
+        assert_eq!(
+            flow.orig_counter,
+            Some(Counter {
+                packets: 5,
+                bytes: 300
+            })
+        );
+        assert_eq!(
+            flow.reply_counter,
+            Some(Counter {
+                packets: 4,
+                bytes: 1200
+            })
+        );
+    }
+
+    #[rstest(
+        line,
+        case("ipv4 2 tcp 6"),
+        case("ipv4 2 tcp 6 100 ESTABLISHED src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80"),
+        case("ipv4 2 tcp 6 100 UNKNOWN src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234"),
+        case("ipv4 2 tcp 6 100 ESTABLISHED src=10.0.0.300 dst=10.0.0.2 sport=1234 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234")
+    )]
+    fn test_parse_proc_line_invalid(line: &str) {
+        assert!(parse_proc_line(line).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_proc_conntrack_source_list() {
+        let path = std::env::temp_dir().join(format!("nf_conntrack-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "ipv4     2 tcp      6 431999 ESTABLISHED src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=1234 [ASSURED] mark=0 zone=0 use=2\n\
+             ipv4     2 udp      17 29 src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=53 [UNREPLIED] src=10.0.0.2 dst=10.0.0.1 sport=53 dport=1234 mark=0 zone=0 use=2\n",
+        )
+        .unwrap();
+        let source = ProcConntrackSource::new(&path);
+        let all = source.list(None).await;
+        let udp = source
+            .list(Some(&Filter::default().protocol(Protocol::Udp)))
+            .await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(all.unwrap().len(), 2);
+        let udp = udp.unwrap();
+        assert_eq!(udp.len(), 1);
+        assert_eq!(
+            u16::from(&udp[0].status) & u16::from(&Status::seen_reply()),
+            0
+        );
+    }
+}
@@ -1,16 +1,22 @@
+use std::{net::IpAddr, str::FromStr};
+
 use ipnet::IpNet;
 use netlink_packet_core::NetlinkMessage;
 use netlink_packet_netfilter::{
-    ctnetlink::nlas::flow::{
-        ip_tuple::{IpTuple, ProtocolTuple, TupleNla},
-        nla::FlowNla,
+    ctnetlink::nlas::{
+        ct_attr::CtAttr,
+        flow::{
+            ip_tuple::{IpTuple, ProtocolTuple, TupleNla},
+            nla::FlowNla,
+            status::ConnectionStatusFlag,
+        },
     },
     NetfilterMessage,
 };
 
 use crate::{
     error::Error,
-    flow::{Flow, Protocol, Status, TcpState, Tuple},
+    flow::{Flow, FlowError, ProtoInfo, Protocol, SctpState, Status, TcpState, Tuple},
     message::MessageBuilder,
     Family, Table,
 };
@@ -30,8 +36,11 @@ impl Request {
         let builder = MessageBuilder::from(&self.meta);
 
         match &self.op {
-            RequestOperation::List(_) => Ok(Some(builder.list())),
+            RequestOperation::List(Some(f)) => Ok(Some(builder.filter(f.nlas()).list())),
+            RequestOperation::List(None) => Ok(Some(builder.list())),
             RequestOperation::Get(param) => Ok(Some(builder.get(param))),
+            RequestOperation::Delete(param) => Ok(Some(builder.delete(param))),
+            RequestOperation::Update(param) => Ok(Some(builder.update(param))),
             RequestOperation::Event(_) => Ok(None),
             RequestOperation::Count => Ok(Some(builder.count())),
             RequestOperation::Stat => Ok(Some(builder.stat())),
@@ -41,12 +50,19 @@ impl Request {
     pub fn filter(&self) -> Option<Filter> {
         self.op.filter()
     }
+
+    // Whether the reply is a dump which ends with NLMSG_DONE.
+    pub fn is_dump(&self) -> bool {
+        matches!(self.op, RequestOperation::List(_))
+    }
 }
 
 #[derive(Debug)]
 pub enum RequestOperation {
     List(Option<Filter>),
     Get(GetParams),
+    Delete(DeleteParams),
+    Update(UpdateParams),
     Event(Option<Filter>),
     Count,
     Stat,
@@ -57,6 +73,8 @@ impl RequestOperation {
         match self {
             RequestOperation::List(f) => f.clone(),
             RequestOperation::Get(_) => None,
+            RequestOperation::Delete(_) => None,
+            RequestOperation::Update(_) => None,
             RequestOperation::Event(f) => f.clone(),
             RequestOperation::Count => None,
             RequestOperation::Stat => None,
@@ -119,10 +137,27 @@ pub struct Filter {
     orig_dst_port: Option<u16>,
     reply_src_port: Option<u16>,
     reply_dst_port: Option<u16>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
     mark: Option<u32>,
+    mark_mask: Option<u32>,
     r#use: Option<u32>,
     tcp_state: Option<TcpState>,
+    proto_state: Option<ProtoState>,
     status: Option<Status>,
+    status_all: Option<Status>,
+    status_none: Option<Status>,
+    timeout_lt: Option<u32>,
+    timeout_gt: Option<u32>,
+    src_nat: Option<Option<IpNet>>,
+    dst_nat: Option<Option<IpNet>>,
+    no_link_local: bool,
+    self_connection: bool,
+    no_orphan_reply: bool,
+    icmp_id: Option<u16>,
+    confirmed: Option<bool>,
+    any: Vec<FilterSet>,
+    none: Vec<FilterSet>,
 }
 
 impl Filter {
@@ -176,11 +211,96 @@ impl Filter {
         self
     }
 
+    // Match flows which have the port as the source port of either the original or the reply tuple.
+    pub fn src_port(mut self, p: u16) -> Self {
+        self.src_port = Some(p);
+        self
+    }
+
+    // Match flows which have the port as the destination port of either the original or the reply tuple.
+    pub fn dst_port(mut self, p: u16) -> Self {
+        self.dst_port = Some(p);
+        self
+    }
+
     pub fn mark(mut self, m: u32) -> Self {
         self.mark = Some(m);
         self
     }
 
+    // Only bits set in the mask are compared. Without the mask, all bits are compared.
+    pub fn mark_mask(mut self, m: u32) -> Self {
+        self.mark_mask = Some(m);
+        self
+    }
+
+    // Match ICMP flows with the id, e.g. to pair an echo request flow across directions.
+    // Flows without the ICMP id never match. See Flow::icmp.
+    pub fn icmp_id(mut self, id: u16) -> Self {
+        self.icmp_id = Some(id);
+        self
+    }
+
+    // Match flows which match any of the filters in the set, e.g. port 80 or port 443.
+    // Each call adds a group and a flow must match every group.
+    pub fn matches_any(mut self, set: FilterSet) -> Self {
+        self.any.push(set);
+        self
+    }
+
+    // Exclude flows which match any of the filters in the set, e.g. private destinations.
+    // An empty set excludes nothing.
+    pub fn matches_none(mut self, set: FilterSet) -> Self {
+        self.none.push(set);
+        self
+    }
+
+    // Attributes to ask the kernel to filter a dump.
+    // Filters which the kernel doesn't support are applied in apply() after receiving flows.
+    // Sub-filters given by matches_any() and matches_none() are never sent because the kernel only supports AND.
+    pub(super) fn nlas(&self) -> Vec<FlowNla> {
+        // These constants are defined in Linux kernel (linux/netfilter/nfnetlink_conntrack.h)
+        const CTA_MARK_MASK: u16 = 21;
+        const CTA_FILTER: u16 = 25;
+        const CTA_FILTER_ORIG_FLAGS: u16 = 1;
+        const CTA_FILTER_FLAG_CTA_PROTO_NUM: u32 = 1 << 3;
+        let mut nlas = Vec::new();
+        // The kernel filters by the protocol of the original tuple since Linux 5.8.
+        // Older kernels ignore CTA_FILTER, so apply() still checks the protocol.
+        // CTA_FILTER is rejected with EOPNOTSUPP for AF_UNSPEC, so it is sent only when the family is given.
+        let has_family = matches!(self.family, Some(Family::Ipv4 | Family::Ipv6));
+        if let Some(p) = self.protocol.filter(|_| has_family) {
+            // Ports are not compared because their flags are not set.
+            nlas.push(FlowNla::Orig(vec![TupleNla::Protocol(ProtocolTuple {
+                src_port: 0,
+                dst_port: 0,
+                protocol: u8::from(p),
+            })]));
+            nlas.push(FlowNla::Other(CtAttr {
+                nested: Some(vec![CtAttr {
+                    nested: None,
+                    attr_type: CTA_FILTER_ORIG_FLAGS,
+                    length: 8,
+                    value: Some(CTA_FILTER_FLAG_CTA_PROTO_NUM.to_ne_bytes().to_vec()),
+                }]),
+                attr_type: CTA_FILTER,
+                length: 12,
+                value: None,
+            }));
+        }
+        if let Some(mark) = self.mark {
+            let mask = self.mark_mask.unwrap_or(u32::MAX);
+            nlas.push(FlowNla::Mark(mark));
+            nlas.push(FlowNla::Other(CtAttr {
+                nested: None,
+                attr_type: CTA_MARK_MASK,
+                length: 8,
+                value: Some(mask.to_be_bytes().to_vec()),
+            }));
+        }
+        nlas
+    }
+
     pub fn r#use(mut self, u: u32) -> Self {
         self.r#use = Some(u);
         self
@@ -191,11 +311,85 @@ impl Filter {
         self
     }
 
+    // Match flows in the given state of their own protocol. See ProtoState for valid states.
+    pub fn proto_state(mut self, s: ProtoState) -> Self {
+        self.proto_state = Some(s);
+        self
+    }
+
+    // Match flows which have any of the given flags.
     pub fn status(mut self, s: Status) -> Self {
         self.status = Some(s);
         self
     }
 
+    // Match flows which have all of the given flags.
+    // Flags are accumulated when this is called more than once.
+    pub fn status_all(mut self, s: Status) -> Self {
+        let current = self.status_all.as_ref().map(u16::from).unwrap_or(0);
+        self.status_all = Some(Status::from(current | u16::from(&s)));
+        self
+    }
+
+    // Match flows which have none of the given flags.
+    // Flags are accumulated when this is called more than once.
+    pub fn status_none(mut self, s: Status) -> Self {
+        let current = self.status_none.as_ref().map(u16::from).unwrap_or(0);
+        self.status_none = Some(Status::from(current | u16::from(&s)));
+        self
+    }
+
+    // The timeout range is exclusive at both ends and is checked only in apply()
+    // because CTA_FILTER has no attribute for the timeout.
+    pub fn timeout_lt(mut self, t: u32) -> Self {
+        self.timeout_lt = Some(t);
+        self
+    }
+
+    pub fn timeout_gt(mut self, t: u32) -> Self {
+        self.timeout_gt = Some(t);
+        self
+    }
+
+    // Match flows whose source address is translated.
+    // When the address is given, the translated-to address(the destination of the reply tuple) must be in it.
+    pub fn src_nat(mut self, n: Option<IpNet>) -> Self {
+        self.src_nat = Some(n);
+        self
+    }
+
+    // Match flows whose destination address is translated.
+    // When the address is given, the translated-to address(the source of the reply tuple) must be in it.
+    pub fn dst_nat(mut self, n: Option<IpNet>) -> Self {
+        self.dst_nat = Some(n);
+        self
+    }
+
+    // Exclude IPv6 flows which have a link-local(fe80::/10) or multicast(ff00::/8) address in either tuple.
+    pub fn no_link_local(mut self) -> Self {
+        self.no_link_local = true;
+        self
+    }
+
+    // Match flows whose source and destination are the same host, e.g. a misconfigured loopback service.
+    pub fn self_connection(mut self) -> Self {
+        self.self_connection = true;
+        self
+    }
+
+    // Exclude half-created flows whose reply tuple is zeroed. See Flow::has_orphan_reply().
+    pub fn no_orphan_reply(mut self) -> Self {
+        self.no_orphan_reply = true;
+        self
+    }
+
+    // Match flows which have the CONFIRMED flag when true, and flows which don't have it when false.
+    // Unlike the unconfirmed table, this checks the flag of each entry in the dumped table.
+    pub fn confirmed(mut self, c: bool) -> Self {
+        self.confirmed = Some(c);
+        self
+    }
+
     pub(super) fn apply(&self, flow: &Flow) -> bool {
         if let Some(f) = self.family {
             // Is it enough to check that flow.original.src_addr is matched?
@@ -220,23 +414,28 @@ impl Filter {
                 None => return false,
             }
         }
+        if let Some(s) = &self.proto_state {
+            if !s.matches(flow) {
+                return false;
+            }
+        }
         if let Some(cidr) = self.orig_src_addr {
-            if !cidr.contains(&flow.original.src_addr) {
+            if !contains(&cidr, flow.original.src_addr) {
                 return false;
             }
         }
         if let Some(cidr) = self.orig_dst_addr {
-            if !cidr.contains(&flow.original.dst_addr) {
+            if !contains(&cidr, flow.original.dst_addr) {
                 return false;
             }
         }
         if let Some(cidr) = self.reply_src_addr {
-            if !cidr.contains(&flow.reply.src_addr) {
+            if !contains(&cidr, flow.reply.src_addr) {
                 return false;
             }
         }
         if let Some(cidr) = self.reply_dst_addr {
-            if !cidr.contains(&flow.reply.dst_addr) {
+            if !contains(&cidr, flow.reply.dst_addr) {
                 return false;
             }
         }
@@ -260,14 +459,22 @@ impl Filter {
                 return false;
             }
         }
+        if let Some(port) = self.src_port {
+            if port.ne(&flow.original.src_port) && port.ne(&flow.reply.src_port) {
+                return false;
+            }
+        }
+        if let Some(port) = self.dst_port {
+            if port.ne(&flow.original.dst_port) && port.ne(&flow.reply.dst_port) {
+                return false;
+            }
+        }
         if let Some(mark) = self.mark {
-            match flow.mark {
-                Some(m) => {
-                    if mark != m {
-                        return false;
-                    }
-                }
-                None => return false,
+            // The kernel filters the dump by mark too. This check is kept for events.
+            // Events omit CTA_MARK when the mark is zero, so an absent mark is 0.
+            let mask = self.mark_mask.unwrap_or(u32::MAX);
+            if mark & mask != flow.mark.unwrap_or(0) & mask {
+                return false;
             }
         }
         if let Some(us) = self.r#use {
@@ -287,11 +494,151 @@ impl Filter {
                 return false;
             }
         }
+        if let Some(s) = &self.status_all {
+            let flow_status = u16::from(&flow.status);
+            let filter_status = u16::from(s);
+            if flow_status & filter_status != filter_status {
+                return false;
+            }
+        }
+        if let Some(s) = &self.status_none {
+            let flow_status = u16::from(&flow.status);
+            let filter_status = u16::from(s);
+            if flow_status & filter_status != 0 {
+                return false;
+            }
+        }
+        if let Some(t) = self.timeout_lt {
+            if flow.timeout >= t {
+                return false;
+            }
+        }
+        if let Some(t) = self.timeout_gt {
+            if flow.timeout <= t {
+                return false;
+            }
+        }
+        if let Some(n) = &self.src_nat {
+            if !flow.status.contains(ConnectionStatusFlag::SourceNAT) {
+                return false;
+            }
+            if let Some(cidr) = n {
+                if !contains(cidr, flow.reply.dst_addr) {
+                    return false;
+                }
+            }
+        }
+        if let Some(n) = &self.dst_nat {
+            if !flow.status.contains(ConnectionStatusFlag::DestinationNAT) {
+                return false;
+            }
+            if let Some(cidr) = n {
+                if !contains(cidr, flow.reply.src_addr) {
+                    return false;
+                }
+            }
+        }
+        if self.no_link_local
+            && [
+                flow.original.src_addr,
+                flow.original.dst_addr,
+                flow.reply.src_addr,
+                flow.reply.dst_addr,
+            ]
+            .into_iter()
+            .any(is_link_local)
+        {
+            return false;
+        }
+        if self.self_connection && flow.original.src_addr != flow.original.dst_addr {
+            return false;
+        }
+        if self.no_orphan_reply && flow.has_orphan_reply() {
+            return false;
+        }
+        if let Some(id) = self.icmp_id {
+            if flow.icmp.is_none_or(|icmp| icmp.id != id) {
+                return false;
+            }
+        }
+        if let Some(c) = self.confirmed {
+            if flow.status.contains(ConnectionStatusFlag::Confirmed) != c {
+                return false;
+            }
+        }
+        if !self.any.iter().all(|set| set.apply(flow)) {
+            return false;
+        }
+        if self
+            .none
+            .iter()
+            .any(|set| !set.filters.is_empty() && set.apply(flow))
+        {
+            return false;
+        }
 
         true
     }
 }
 
+// Filters combined with OR. A flow matches when any of them matches.
+// An empty set matches all flows.
+#[derive(Debug, Default, Clone)]
+pub struct FilterSet {
+    filters: Vec<Filter>,
+}
+
+impl FilterSet {
+    pub fn new(filters: Vec<Filter>) -> FilterSet {
+        FilterSet { filters }
+    }
+
+    pub fn or(mut self, f: Filter) -> Self {
+        self.filters.push(f);
+        self
+    }
+
+    pub fn apply(&self, flow: &Flow) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|f| f.apply(flow))
+    }
+}
+
+// A state name like "established" which is resolved to the state machine of each flow's protocol.
+// Valid states are:
+//   TCP:  none, syn_sent, syn_recv, established, fin_wait, close_wait, last_ack, time_wait, close, listen
+//   SCTP: none, closed, cookie_wait, cookie_echoed, established, shutdown_sent, shutdown_recd, shutdown_ack_sent, heartbeat_sent
+// A name valid for only one of them never matches flows of the other. Flows of other protocols never match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtoState {
+    tcp: Option<TcpState>,
+    sctp: Option<SctpState>,
+}
+
+impl ProtoState {
+    fn matches(&self, flow: &Flow) -> bool {
+        match (&flow.proto_info, flow.tcp_state) {
+            (Some(ProtoInfo::Sctp { state, .. }), _) => self.sctp.is_some_and(|s| s.eq(state)),
+            (_, Some(state)) => self.tcp.is_some_and(|s| s.eq(&state)),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for ProtoState {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let state = ProtoState {
+            tcp: TcpState::try_from(s).ok(),
+            sctp: SctpState::try_from(s).ok(),
+        };
+        if state.tcp.is_none() && state.sctp.is_none() {
+            return Err(FlowError::InvalidProtoState(s.to_string()));
+        }
+        Ok(state)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Direction {
     Orig(Tuple),
@@ -311,6 +658,119 @@ impl GetParams {
             directed_tuple,
         }
     }
+
+    // The dying and unconfirmed tables support only dumps.
+    // The entry is searched in them by a dump with this filter matching the exact tuple.
+    pub fn filter(&self) -> Filter {
+        let filter = Filter::default().protocol(self.protocol);
+        match &self.directed_tuple {
+            Direction::Orig(t) => filter
+                .orig_src_addr(IpNet::from(t.src_addr))
+                .orig_dst_addr(IpNet::from(t.dst_addr))
+                .orig_src_port(t.src_port)
+                .orig_dst_port(t.dst_port),
+            Direction::Reply(t) => filter
+                .reply_src_addr(IpNet::from(t.src_addr))
+                .reply_dst_addr(IpNet::from(t.dst_addr))
+                .reply_src_port(t.src_port)
+                .reply_dst_port(t.dst_port),
+        }
+    }
+}
+
+// An IPv4-mapped IPv6 address like ::ffff:1.2.3.4 is also compared as its embedded IPv4 address.
+// A flow from a dual-stack socket may have such addresses.
+fn contains(cidr: &IpNet, addr: IpAddr) -> bool {
+    if cidr.contains(&addr) {
+        return true;
+    }
+    match addr {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .is_some_and(|v4| cidr.contains(&IpAddr::V4(v4))),
+        IpAddr::V4(_) => false,
+    }
+}
+
+// Link-local and multicast addresses are noisy on IPv6 hosts.
+fn is_link_local(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V6(v6) => v6.segments()[0] & 0xffc0 == 0xfe80 || v6.is_multicast(),
+        IpAddr::V4(_) => false,
+    }
+}
+
+// The kernel flushes all entries when a delete request has no tuple.
+// So, a delete request always has a tuple and the id is used to make sure that the entry is the expected one.
+#[derive(Debug, Clone)]
+pub struct DeleteParams {
+    params: GetParams,
+    id: Option<u32>,
+}
+
+impl DeleteParams {
+    pub fn new(protocol: Protocol, directed_tuple: Direction) -> DeleteParams {
+        DeleteParams {
+            params: GetParams::new(protocol, directed_tuple),
+            id: None,
+        }
+    }
+
+    pub fn id(mut self, id: u32) -> DeleteParams {
+        self.id = Some(id);
+        self
+    }
+}
+
+impl From<&DeleteParams> for Vec<FlowNla> {
+    fn from(param: &DeleteParams) -> Self {
+        let mut nlas = Vec::<FlowNla>::from(&param.params);
+        if let Some(id) = param.id {
+            nlas.push(FlowNla::Id(id));
+        }
+        nlas
+    }
+}
+
+// An update request carries the tuple to find the entry and the attributes to change.
+#[derive(Debug, Clone)]
+pub struct UpdateParams {
+    params: GetParams,
+    mark: Option<u32>,
+    timeout: Option<u32>,
+}
+
+impl UpdateParams {
+    pub fn new(protocol: Protocol, directed_tuple: Direction) -> UpdateParams {
+        UpdateParams {
+            params: GetParams::new(protocol, directed_tuple),
+            mark: None,
+            timeout: None,
+        }
+    }
+
+    pub fn mark(mut self, mark: u32) -> UpdateParams {
+        self.mark = Some(mark);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: u32) -> UpdateParams {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+impl From<&UpdateParams> for Vec<FlowNla> {
+    fn from(param: &UpdateParams) -> Self {
+        let mut nlas = Vec::<FlowNla>::from(&param.params);
+        if let Some(timeout) = param.timeout {
+            nlas.push(FlowNla::Timeout(timeout));
+        }
+        if let Some(mark) = param.mark {
+            nlas.push(FlowNla::Mark(mark));
+        }
+        nlas
+    }
 }
 
 impl From<&GetParams> for Vec<FlowNla> {
@@ -354,11 +814,17 @@ mod tests {
     use rstest::rstest;
 
     use crate::{
-        flow::{Flow, FlowBuilder, Protocol, Status, TcpState, TupleBuilder},
+        flow::{Flow, FlowBuilder, ProtoInfo, Protocol, SctpState, Status, TcpState, TupleBuilder},
         message::MessageType,
+        Family,
+    };
+
+    use netlink_packet_netfilter::ctnetlink::nlas::flow::{
+        ip_tuple::{ProtocolTuple, TupleNla},
+        nla::FlowNla,
     };
 
-    use super::Filter;
+    use super::{is_link_local, Direction, Filter, FilterSet, GetParams, ProtoState};
 
     fn ipv4_tcp_flow() -> Flow {
         FlowBuilder::default()
@@ -391,6 +857,25 @@ mod tests {
             .unwrap()
     }
 
+    fn ipv4_self_tcp_flow() -> Flow {
+        let mut flow = ipv4_tcp_flow();
+        flow.original.dst_addr = flow.original.src_addr;
+        flow
+    }
+
+    fn ipv4_orphan_tcp_flow() -> Flow {
+        let mut flow = ipv4_tcp_flow();
+        flow.reply.src_port = 0;
+        flow.reply.dst_port = 0;
+        flow
+    }
+
+    fn ipv4_confirmed_tcp_flow() -> Flow {
+        let mut flow = ipv4_tcp_flow();
+        flow.status = Status::from(u16::from(&Status::assured()) | u16::from(&Status::confirmed()));
+        flow
+    }
+
     fn ipv6_udp_flow() -> Flow {
         FlowBuilder::default()
             .event_type(MessageType::Update)
@@ -421,72 +906,229 @@ mod tests {
             .unwrap()
     }
 
-    #[rstest(
-        filter,
-        flow,
-        expected,
-        case(
-            Filter::default(),
-            ipv4_tcp_flow(),
-            true),
-        case(
-            Filter::default()
-                .protocol(Protocol::Tcp),
-            ipv4_tcp_flow(),
-            true),
-        case(
-            Filter::default()
-                .protocol(Protocol::Udp),
-            ipv4_tcp_flow(),
-            false),
-        case(
-            Filter::default()
-                .orig_src_addr("1.1.1.1/32".parse::<IpNet>().unwrap())
-                .orig_dst_addr("2.2.2.2/32".parse::<IpNet>().unwrap())
-                .reply_src_addr("3.3.3.3/32".parse::<IpNet>().unwrap())
-                .reply_dst_addr("4.4.4.4/32".parse::<IpNet>().unwrap()),
-            ipv4_tcp_flow(),
-            true),
-        case(
-            Filter::default()
-                .orig_src_addr("1.1.1.1/32".parse::<IpNet>().unwrap())
-                .orig_dst_addr("2.2.2.2/32".parse::<IpNet>().unwrap())
-                .reply_src_addr("3.3.3.0/32".parse::<IpNet>().unwrap()) // Here doesn't match
-                .reply_dst_addr("4.4.4.4/32".parse::<IpNet>().unwrap()),
-            ipv4_tcp_flow(),
-            false),
-        case(
-            Filter::default()
-                .protocol(Protocol::Tcp)
-                .reply_dst_port(4567),
-            ipv4_tcp_flow(),
-            true),
-        case(
-            Filter::default()
-                .protocol(Protocol::Tcp)
-                .tcp_state(TcpState::Established),
-            ipv4_tcp_flow(),
-            true),
-        case(
-            Filter::default()
-                .protocol(Protocol::Tcp)
-                .tcp_state(TcpState::TimeWait),
-            ipv4_tcp_flow(),
-            false),
-        case(
-            Filter::default()
-                .protocol(Protocol::Tcp)
-                .tcp_state(TcpState::TimeWait),
-            ipv4_tcp_flow(),
-            false),
-        case(
-            Filter::default()
-                .mark(1),
-            ipv4_tcp_flow(),
-            true),
-        case(
-            Filter::default()
-                .r#use(1),
+    fn ipv6_link_local_udp_flow() -> Flow {
+        FlowBuilder::default()
+            .event_type(MessageType::Update)
+            .original(
+                TupleBuilder::default()
+                    .src_addr("fe80::1".parse().unwrap())
+                    .dst_addr("ff02::1:2".parse().unwrap())
+                    .src_port(546)
+                    .dst_port(547)
+                    .build()
+                    .unwrap(),
+            )
+            .reply(
+                TupleBuilder::default()
+                    .src_addr("ff02::1:2".parse().unwrap())
+                    .dst_addr("fe80::1".parse().unwrap())
+                    .src_port(547)
+                    .dst_port(546)
+                    .build()
+                    .unwrap(),
+            )
+            .protocol(Protocol::Udp)
+            .timeout(30)
+            .status(Status::from(0))
+            .build()
+            .unwrap()
+    }
+
+    fn ipv4_snat_udp_flow() -> Flow {
+        FlowBuilder::default()
+            .event_type(MessageType::Update)
+            .original(
+                TupleBuilder::default()
+                    .src_addr("10.0.0.1".parse().unwrap())
+                    .dst_addr("8.8.8.8".parse().unwrap())
+                    .src_port(1234)
+                    .dst_port(53)
+                    .build()
+                    .unwrap(),
+            )
+            .reply(
+                TupleBuilder::default()
+                    .src_addr("8.8.8.8".parse().unwrap())
+                    .dst_addr("192.168.0.1".parse().unwrap())
+                    .src_port(53)
+                    .dst_port(1234)
+                    .build()
+                    .unwrap(),
+            )
+            .protocol(Protocol::Udp)
+            .timeout(30)
+            .status(Status::src_nat())
+            .build()
+            .unwrap()
+    }
+
+    fn ipv4_sctp_flow() -> Flow {
+        FlowBuilder::default()
+            .event_type(MessageType::Update)
+            .original(
+                TupleBuilder::default()
+                    .src_addr("10.0.0.1".parse().unwrap())
+                    .dst_addr("10.0.0.2".parse().unwrap())
+                    .src_port(5000)
+                    .dst_port(38412)
+                    .build()
+                    .unwrap(),
+            )
+            .reply(
+                TupleBuilder::default()
+                    .src_addr("10.0.0.2".parse().unwrap())
+                    .dst_addr("10.0.0.1".parse().unwrap())
+                    .src_port(38412)
+                    .dst_port(5000)
+                    .build()
+                    .unwrap(),
+            )
+            .protocol(Protocol::from(132))
+            .proto_info(ProtoInfo::Sctp {
+                state: SctpState(4),
+                vtag_original: 1,
+                vtag_reply: 2,
+            })
+            .timeout(1000)
+            .status(Status::assured())
+            .build()
+            .unwrap()
+    }
+
+    fn ipv4_mapped_tcp_flow() -> Flow {
+        FlowBuilder::default()
+            .event_type(MessageType::Update)
+            .original(
+                TupleBuilder::default()
+                    .src_addr("::ffff:1.1.1.1".parse().unwrap())
+                    .dst_addr("::ffff:2.2.2.2".parse().unwrap())
+                    .src_port(1234)
+                    .dst_port(2345)
+                    .build()
+                    .unwrap(),
+            )
+            .reply(
+                TupleBuilder::default()
+                    .src_addr("::ffff:2.2.2.2".parse().unwrap())
+                    .dst_addr("::ffff:1.1.1.1".parse().unwrap())
+                    .src_port(2345)
+                    .dst_port(1234)
+                    .build()
+                    .unwrap(),
+            )
+            .protocol(Protocol::Tcp)
+            .tcp_state(TcpState::Established)
+            .timeout(1000)
+            .status(Status::assured())
+            .build()
+            .unwrap()
+    }
+
+    #[rstest(
+        filter,
+        flow,
+        expected,
+        case(
+            Filter::default(),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default(),
+            ipv6_udp_flow(),
+            true),
+        case(
+            Filter::default()
+                .protocol(Protocol::Tcp),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .protocol(Protocol::Udp),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .orig_src_addr("1.1.1.1/32".parse::<IpNet>().unwrap())
+                .orig_dst_addr("2.2.2.2/32".parse::<IpNet>().unwrap())
+                .reply_src_addr("3.3.3.3/32".parse::<IpNet>().unwrap())
+                .reply_dst_addr("4.4.4.4/32".parse::<IpNet>().unwrap()),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .orig_src_addr("1.1.1.1/32".parse::<IpNet>().unwrap())
+                .orig_dst_addr("2.2.2.2/32".parse::<IpNet>().unwrap())
+                .reply_src_addr("3.3.3.0/32".parse::<IpNet>().unwrap()) // Here doesn't match
+                .reply_dst_addr("4.4.4.4/32".parse::<IpNet>().unwrap()),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .protocol(Protocol::Tcp)
+                .reply_dst_port(4567),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .dst_port(2345),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .dst_port(4567),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .dst_port(1234),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .src_port(3456),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .src_port(2345),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .protocol(Protocol::Tcp)
+                .tcp_state(TcpState::Established),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .protocol(Protocol::Tcp)
+                .tcp_state(TcpState::TimeWait),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .protocol(Protocol::Tcp)
+                .tcp_state(TcpState::TimeWait),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .protocol(Protocol::from(17)),
+            ipv6_udp_flow(),
+            true),
+        case(
+            Filter::default()
+                .protocol(Protocol::from(47)),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .mark(1),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .r#use(1),
             ipv4_tcp_flow(),
             true),
         case(
@@ -504,6 +1146,45 @@ mod tests {
                 .status(Status::from((1<<1) + (1<<2))),
             ipv4_tcp_flow(),
             true),
+        case(
+            Filter::default()
+                .status_all(Status::assured()),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .status_all(Status::assured())
+                .status_all(Status::seen_reply()),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .status(Status::assured())
+                .status_all(Status::seen_reply()),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .status_none(Status::assured()),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .status_none(Status::seen_reply()),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .status_none(Status::seen_reply())
+                .status_none(Status::assured()),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .status_all(Status::assured())
+                .status_none(Status::seen_reply()),
+            ipv4_tcp_flow(),
+            true),
         case(
             Filter::default()
                 .orig_src_addr("fd00::1/128".parse::<IpNet>().unwrap()),
@@ -514,9 +1195,371 @@ mod tests {
                 .tcp_state(TcpState::Established),
             ipv6_udp_flow(),
             false),
+        case(
+            Filter::default()
+                .timeout_lt(1001),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .timeout_lt(1000),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .timeout_gt(999)
+                .timeout_lt(1001),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .timeout_gt(1000),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .timeout_gt(999),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .timeout_gt(1000)
+                .timeout_lt(1001),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .timeout_gt(999)
+                .timeout_lt(1000),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .orig_src_addr("1.1.1.1/32".parse::<IpNet>().unwrap())
+                .orig_dst_addr("2.2.2.0/24".parse::<IpNet>().unwrap())
+                .reply_src_addr("2.2.2.2/32".parse::<IpNet>().unwrap())
+                .reply_dst_addr("1.1.1.1/32".parse::<IpNet>().unwrap()),
+            ipv4_mapped_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .src_nat(None),
+            ipv4_snat_udp_flow(),
+            true),
+        case(
+            Filter::default()
+                .src_nat(Some("192.168.0.0/24".parse::<IpNet>().unwrap())),
+            ipv4_snat_udp_flow(),
+            true),
+        case(
+            Filter::default()
+                .src_nat(Some("192.168.1.0/24".parse::<IpNet>().unwrap())),
+            ipv4_snat_udp_flow(),
+            false),
+        case(
+            Filter::default()
+                .dst_nat(None),
+            ipv4_snat_udp_flow(),
+            false),
+        case(
+            Filter::default()
+                .src_nat(None),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .mark(0x11)
+                .mark_mask(0x0f),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .mark(0x11),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .mark(0),
+            ipv4_snat_udp_flow(),
+            true),
+        case(
+            Filter::default()
+                .mark(0),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .mark(1),
+            ipv4_snat_udp_flow(),
+            false),
+        case(
+            Filter::default()
+                .orig_src_addr("::ffff:1.1.1.1/128".parse::<IpNet>().unwrap()),
+            ipv4_mapped_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .orig_src_addr("1.1.1.2/32".parse::<IpNet>().unwrap()),
+            ipv4_mapped_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .matches_any(FilterSet::default()
+                    .or(Filter::default().orig_dst_port(80))
+                    .or(Filter::default().orig_dst_port(2345))),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .matches_any(FilterSet::default()
+                    .or(Filter::default().orig_dst_port(80))
+                    .or(Filter::default().orig_dst_port(443))),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .protocol(Protocol::Udp)
+                .matches_any(FilterSet::new(vec![
+                    Filter::default().orig_dst_port(2345),
+                    Filter::default().orig_dst_port(53),
+                ])),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .matches_any(FilterSet::new(vec![
+                    Filter::default().orig_src_port(1234),
+                    Filter::default().orig_src_port(1),
+                ]))
+                .matches_any(FilterSet::new(vec![
+                    Filter::default().orig_dst_port(80),
+                    Filter::default().orig_dst_port(443),
+                ])),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .matches_any(FilterSet::default()),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .matches_none(FilterSet::default()
+                    .or(Filter::default().reply_dst_addr("10.0.0.0/8".parse::<IpNet>().unwrap()))
+                    .or(Filter::default().reply_dst_addr("4.4.4.0/24".parse::<IpNet>().unwrap()))),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .matches_none(FilterSet::default()
+                    .or(Filter::default().reply_dst_addr("10.0.0.0/8".parse::<IpNet>().unwrap()))),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .matches_none(FilterSet::default()),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .no_link_local(),
+            ipv6_link_local_udp_flow(),
+            false),
+        case(
+            Filter::default()
+                .no_link_local(),
+            ipv6_udp_flow(),
+            true),
+        case(
+            Filter::default()
+                .no_link_local(),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .self_connection(),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .self_connection(),
+            ipv4_self_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .no_orphan_reply(),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .no_orphan_reply(),
+            ipv4_orphan_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .confirmed(true),
+            ipv4_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .confirmed(false),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .confirmed(true),
+            ipv4_confirmed_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .confirmed(false),
+            ipv4_confirmed_tcp_flow(),
+            false),
+        case(
+            Filter::default()
+                .proto_state("established".parse().unwrap()),
+            ipv4_tcp_flow(),
+            true),
+        case(
+            Filter::default()
+                .proto_state("established".parse().unwrap()),
+            ipv4_sctp_flow(),
+            true),
+        case(
+            Filter::default()
+                .proto_state("cookie_wait".parse().unwrap()),
+            ipv4_sctp_flow(),
+            false),
+        case(
+            Filter::default()
+                .proto_state("time_wait".parse().unwrap()),
+            ipv4_sctp_flow(),
+            false),
+        case(
+            Filter::default()
+                .proto_state("established".parse().unwrap()),
+            ipv6_udp_flow(),
+            false),
     )]
     fn test_filter_apply(filter: Filter, flow: Flow, expected: bool) {
         let res = filter.apply(&flow);
         assert_eq!(res, expected);
     }
+
+    #[rstest(
+        addr,
+        expected,
+        case("fe80::1", true),
+        case("febf::1", true),
+        case("fec0::1", false),
+        case("ff02::1", true),
+        case("fd00::1", false),
+        case("224.0.0.1", false)
+    )]
+    fn test_is_link_local(addr: &str, expected: bool) {
+        assert_eq!(is_link_local(addr.parse().unwrap()), expected);
+    }
+
+    #[rstest(
+        s,
+        expected,
+        case("established", Some((Some(TcpState::Established), Some(SctpState(4))))),
+        case("TIME_WAIT", Some((Some(TcpState::TimeWait), None))),
+        case("shutdown_ack_sent", Some((None, Some(SctpState(7))))),
+        case("unknown", None)
+    )]
+    fn test_proto_state_from_str(s: &str, expected: Option<(Option<TcpState>, Option<SctpState>)>) {
+        let res = s.parse::<ProtoState>().ok().map(|p| (p.tcp, p.sctp));
+        assert_eq!(res, expected);
+    }
+
+    fn tuple(src: &str, dst: &str, src_port: u16, dst_port: u16) -> crate::flow::Tuple {
+        TupleBuilder::default()
+            .src_addr(src.parse().unwrap())
+            .dst_addr(dst.parse().unwrap())
+            .src_port(src_port)
+            .dst_port(dst_port)
+            .build()
+            .unwrap()
+    }
+
+    #[rstest(
+        params,
+        expected,
+        case(
+            GetParams::new(
+                Protocol::Tcp,
+                Direction::Orig(tuple("1.1.1.1", "2.2.2.2", 1234, 2345))
+            ),
+            true
+        ),
+        case(
+            GetParams::new(
+                Protocol::Tcp,
+                Direction::Reply(tuple("3.3.3.3", "4.4.4.4", 3456, 4567))
+            ),
+            true
+        ),
+        case(
+            GetParams::new(
+                Protocol::Tcp,
+                Direction::Orig(tuple("1.1.1.1", "2.2.2.3", 1234, 2345))
+            ),
+            false
+        ),
+        case(
+            GetParams::new(
+                Protocol::Tcp,
+                Direction::Reply(tuple("3.3.3.3", "4.4.4.4", 3456, 4568))
+            ),
+            false
+        ),
+        case(
+            GetParams::new(
+                Protocol::Udp,
+                Direction::Orig(tuple("1.1.1.1", "2.2.2.2", 1234, 2345))
+            ),
+            false
+        )
+    )]
+    fn test_get_params_filter(params: GetParams, expected: bool) {
+        assert_eq!(params.filter().apply(&ipv4_tcp_flow()), expected);
+    }
+
+    #[test]
+    fn test_filter_nlas_protocol() {
+        assert!(Filter::default().nlas().is_empty());
+        let nlas = Filter::default()
+            .family(Family::Ipv4)
+            .protocol(Protocol::Udp)
+            .nlas();
+        assert_eq!(nlas.len(), 2);
+        assert_eq!(
+            nlas[0],
+            FlowNla::Orig(vec![TupleNla::Protocol(ProtocolTuple {
+                src_port: 0,
+                dst_port: 0,
+                protocol: 17,
+            })])
+        );
+        let FlowNla::Other(attr) = &nlas[1] else {
+            panic!("CTA_FILTER is expected, but got {:?}", nlas[1]);
+        };
+        assert_eq!(attr.attr_type, 25);
+        assert_eq!(
+            attr.nested.as_ref().unwrap()[0].value,
+            Some((1u32 << 3).to_ne_bytes().to_vec())
+        );
+    }
+
+    #[rstest(
+        filter,
+        case(Filter::default().protocol(Protocol::Udp)),
+        case(Filter::default().family(Family::Unspec).protocol(Protocol::Udp)),
+    )]
+    fn test_filter_nlas_protocol_unspec(filter: Filter) {
+        // The protocol is checked only by apply().
+        assert!(filter.nlas().is_empty());
+        assert!(!filter.apply(&ipv4_tcp_flow()));
+    }
 }
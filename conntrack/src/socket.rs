@@ -1,4 +1,13 @@
-use std::{pin::Pin, task::Poll};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::ErrorKind,
+    os::fd::{AsRawFd, RawFd},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+};
 
 use async_trait::async_trait;
 use futures::Stream;
@@ -8,9 +17,10 @@ use netlink_packet_netfilter::{
     ctnetlink::message::CtNetlinkMessage,
     NetfilterMessage, NetfilterMessageInner,
 };
-use netlink_sys::{
-    protocols::NETLINK_NETFILTER, AsyncSocket, AsyncSocketExt, SocketAddr, TokioSocket,
-};
+use netlink_packet_utils::DecodeError;
+use netlink_sys::{protocols::NETLINK_NETFILTER, AsyncSocket, AsyncSocketExt, SocketAddr};
+// Re-exported to build a socket for NfConntrackSocket::from_socket() without depending on netlink-sys directly.
+pub use netlink_sys::TokioSocket;
 
 use crate::{
     error::{Error, NetlinkError},
@@ -18,35 +28,248 @@ use crate::{
     message::{Message, MessageGroup},
 };
 
+// Emit a debug event when the tracing feature is enabled. This is a no-op otherwise.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
 #[async_trait]
 pub trait ConntrackSocket: Stream {
     async fn send(&mut self, msg: NetlinkMessage<NetfilterMessage>) -> Result<(), Error>;
+    // Eager variant: receive messages until the dump completes and return all of them at once.
+    // This buffers the whole table in memory, so read large tables through the Stream impl,
+    // which yields each batch of messages as it arrives and holds only one batch at a time.
     async fn recv(&mut self) -> Result<Vec<Message>, Error>;
+    // Receive messages from a single datagram.
     async fn recv_once(&mut self) -> Result<Vec<Message>, Error>;
+    // Discard messages remaining from an interrupted dump.
+    async fn drain(&mut self) -> Result<(), Error>;
+}
+
+// A callback given each datagram as received from the socket, before parsing it.
+// This is for debugging, e.g. to attach the exact bytes of a flow which fails to be parsed to an issue.
+#[derive(Clone)]
+pub struct DatagramHook(Arc<DatagramFn>);
+
+type DatagramFn = dyn Fn(&[u8]) + Send + Sync;
+
+impl DatagramHook {
+    pub fn new(f: impl Fn(&[u8]) + Send + Sync + 'static) -> DatagramHook {
+        DatagramHook(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for DatagramHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DatagramHook")
+    }
 }
 
 pub struct NfConntrackSocket {
     inner: TokioSocket,
+    // The portid given by the caller. It is released when the socket is dropped.
+    port_id: Option<u32>,
+    // The maximum number of messages parsed in a poll. None parses the whole datagram.
+    budget: Option<usize>,
+    // Complete messages left by the budget and the offset to parse them from in the next poll.
+    pending: Vec<u8>,
+    pending_read: usize,
+    hook: Option<DatagramHook>,
 }
 
+// Portids pinned by sockets in this process.
+// The kernel rejects a duplicated portid only with EADDRINUSE, so collisions are reported with the portid here.
+static PORT_IDS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
 impl NfConntrackSocket {
     const SOCKET_AUTOPID: u32 = 0;
-    pub(super) fn new(group: MessageGroup) -> Result<NfConntrackSocket, Error> {
+    const CURRENT_NETNS: &str = "/proc/thread-self/ns/net";
+    const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+    // When port_id is None, the kernel assigns a portid.
+    pub(super) fn new(
+        group: MessageGroup,
+        netns: Option<&Path>,
+        port_id: Option<u32>,
+    ) -> Result<NfConntrackSocket, Error> {
+        let reserved = PortIdGuard::reserve(port_id)?;
+        let socket = match netns {
+            Some(path) => {
+                // A netlink socket belongs to the network namespace where it is created.
+                // So, switch the namespace of the current thread only while opening the socket.
+                let current = File::open(Self::CURRENT_NETNS).map_err(Error::Netns)?;
+                let target = File::open(path).map_err(Error::Netns)?;
+                setns(&target)?;
+                let socket = Self::open(group, port_id);
+                setns(&current)?;
+                socket?
+            }
+            None => Self::open(group, port_id)?,
+        };
+        Ok(NfConntrackSocket {
+            inner: socket,
+            // The socket releases the portid when it is dropped.
+            port_id: reserved.commit(),
+            budget: None,
+            pending: Vec::new(),
+            pending_read: 0,
+            hook: None,
+        })
+    }
+
+    // Wrap a socket configured by the caller, e.g. with custom buffer sizes or in another namespace.
+    // The socket must be created with NETLINK_NETFILTER and bound to receive replies.
+    pub fn from_socket(socket: TokioSocket) -> NfConntrackSocket {
+        NfConntrackSocket {
+            inner: socket,
+            port_id: None,
+            budget: None,
+            pending: Vec::new(),
+            pending_read: 0,
+            hook: None,
+        }
+    }
+
+    pub fn datagram_hook(mut self, hook: DatagramHook) -> NfConntrackSocket {
+        self.hook = Some(hook);
+        self
+    }
+
+    // Parse at most `budget` messages in a poll so that a huge datagram doesn't monopolize a cooperative executor.
+    // The rest of the datagram is parsed in the next poll without receiving from the socket.
+    pub fn poll_budget(mut self, budget: usize) -> NfConntrackSocket {
+        self.budget = Some(budget.max(1));
+        self
+    }
+
+    // A signal such as SIGWINCH may interrupt the syscall while a long dump runs.
+    // It is not a failure of the dump, so retry it.
+    // Netlink never splits a message across datagrams, so each datagram is parsed on its own.
+    async fn recv_datagram(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            match self.inner.recv_from_full().await {
+                Ok((data, _)) => {
+                    debug!(bytes = data.len(), "received a datagram");
+                    if let Some(hook) = &self.hook {
+                        (hook.0)(&data);
+                    }
+                    return Ok(data);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!(error = %e, "failed to receive a datagram");
+                    return Err(Error::Recv(e));
+                }
+            }
+        }
+    }
+
+    fn clear_pending(&mut self) {
+        self.pending.clear();
+        self.pending_read = 0;
+    }
+
+    fn open(group: MessageGroup, port_id: Option<u32>) -> Result<TokioSocket, Error> {
         let mut socket = TokioSocket::new(NETLINK_NETFILTER).map_err(Error::Socket)?;
         let socket_ref_mut = socket.socket_mut();
         socket_ref_mut
-            .bind(&SocketAddr::new(Self::SOCKET_AUTOPID, group.into()))
+            .bind(&SocketAddr::new(
+                port_id.unwrap_or(Self::SOCKET_AUTOPID),
+                group.into(),
+            ))
             .map_err(Error::Socket)?;
-        Ok(NfConntrackSocket { inner: socket })
+        Ok(socket)
+    }
+}
+
+impl Drop for NfConntrackSocket {
+    fn drop(&mut self) {
+        release_port_id(self.port_id);
+    }
+}
+
+// A portid reserved in PORT_IDS. It is released when the guard is dropped unless a socket takes it over,
+// so every early return while opening a socket releases it.
+struct PortIdGuard(Option<u32>);
+
+impl PortIdGuard {
+    fn reserve(port_id: Option<u32>) -> Result<PortIdGuard, Error> {
+        if let Some(id) = port_id {
+            if !PORT_IDS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(id)
+            {
+                return Err(Error::PortIdInUse(id));
+            }
+        }
+        Ok(PortIdGuard(port_id))
+    }
+
+    fn commit(mut self) -> Option<u32> {
+        self.0.take()
+    }
+}
+
+impl Drop for PortIdGuard {
+    fn drop(&mut self) {
+        release_port_id(self.0);
+    }
+}
+
+fn release_port_id(port_id: Option<u32>) {
+    if let Some(id) = port_id {
+        PORT_IDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+    }
+}
+
+// The file descriptor can be registered to an external event loop.
+impl AsRawFd for NfConntrackSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.socket_ref().as_raw_fd()
     }
 }
 
+// A message which fails to be decoded is logged with the offset in the datagram.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn decode_error(e: DecodeError, offset: usize) -> Error {
+    debug!(error = ?e, offset, "failed to decode a message");
+    Error::Netfilter(e)
+}
+
+// An error message from the kernel, e.g. ENOBUFS when the receive buffer overflows.
+fn netlink_error(code: i32) -> Error {
+    debug!(code, "received an error message");
+    Error::NetlinkMessage(NetlinkError::from(code))
+}
+
+fn setns(ns: &File) -> Result<(), Error> {
+    // SAFETY: the file descriptor is valid while ns is alive.
+    let res = unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) };
+    if res < 0 {
+        return Err(Error::Netns(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl ConntrackSocket for NfConntrackSocket {
     async fn send(&mut self, msg: NetlinkMessage<NetfilterMessage>) -> Result<(), Error> {
+        // Messages left from the previous request must not be returned as replies of this one.
+        self.clear_pending();
         let mut buf = vec![0u8; msg.header.length as usize];
         msg.serialize(&mut buf[..]);
-        self.inner.send(&buf).await.map_err(Error::Send)?;
+        debug!(bytes = buf.len(), "send a message");
+        if let Err(e) = self.inner.send(&buf).await {
+            debug!(error = %e, "failed to send a message");
+            return Err(Error::Send(e));
+        }
         Ok(())
     }
 
@@ -54,12 +277,13 @@ impl ConntrackSocket for NfConntrackSocket {
         let mut events = Vec::new();
         let mut done = false;
         loop {
-            let (data, _) = self.inner.recv_from_full().await.map_err(Error::Recv)?;
+            let data = self.recv_datagram().await?;
             let data_l = data.len();
             let mut read = 0;
             while data_l > read {
                 let msg = <NetlinkMessage<NetfilterMessage>>::deserialize(&data[read..])
-                    .map_err(Error::Netfilter)?;
+                    .map_err(|e| decode_error(e, read))?;
+                let start = read;
                 read += msg.buffer_len();
                 let flag = msg.header.flags;
                 match msg.payload {
@@ -67,13 +291,18 @@ impl ConntrackSocket for NfConntrackSocket {
                         done = true;
                         break;
                     }
-                    NetlinkPayload::Error(e) => {
-                        return Err(Error::NetlinkMessage(NetlinkError::from(e.raw_code())))
+                    // An error message with code 0 is an acknowledgement.
+                    NetlinkPayload::Error(e) if e.raw_code() == 0 => {
+                        done = true;
+                        break;
                     }
+                    NetlinkPayload::Error(e) => return Err(netlink_error(e.raw_code())),
                     NetlinkPayload::InnerMessage(msg) => {
                         let res_id = msg.header.res_id;
                         if let NetfilterMessageInner::CtNetlink(msg) = msg.inner {
-                            events.push(Message::new(msg, flag, res_id));
+                            events.push(
+                                Message::new(msg, flag, res_id).with_bytes(&data[start..read]),
+                            );
                         }
                     }
                     _ => {}
@@ -83,39 +312,67 @@ impl ConntrackSocket for NfConntrackSocket {
                 break;
             }
         }
+        debug!(messages = events.len(), "received a reply");
 
         Ok(events)
     }
 
     async fn recv_once(&mut self) -> Result<Vec<Message>, Error> {
         let mut events = Vec::new();
-        let (data, _) = self.inner.recv_from_full().await.map_err(Error::Recv)?;
+        let data = self.recv_datagram().await?;
         let data_l = data.len();
         let mut read = 0;
         while data_l > read {
             let msg = <NetlinkMessage<NetfilterMessage>>::deserialize(&data[read..])
-                .map_err(Error::Netfilter)?;
+                .map_err(|e| decode_error(e, read))?;
+            let start = read;
             read += msg.buffer_len();
             let flag = msg.header.flags;
             match msg.payload {
                 NetlinkPayload::Done(_) => {
                     break;
                 }
-                NetlinkPayload::Error(e) => {
-                    return Err(Error::NetlinkMessage(NetlinkError::from(e.raw_code())))
+                // An error message with code 0 is an acknowledgement.
+                NetlinkPayload::Error(e) if e.raw_code() == 0 => {
+                    break;
                 }
+                NetlinkPayload::Error(e) => return Err(netlink_error(e.raw_code())),
                 NetlinkPayload::InnerMessage(msg) => {
                     let res_id = msg.header.res_id;
                     if let NetfilterMessageInner::CtNetlink(msg) = msg.inner {
-                        events.push(Message::new(msg, flag, res_id));
+                        events.push(Message::new(msg, flag, res_id).with_bytes(&data[start..read]));
                     }
                 }
                 _ => {}
             }
         }
+        debug!(messages = events.len(), "received messages once");
 
         Ok(events)
     }
+
+    async fn drain(&mut self) -> Result<(), Error> {
+        self.clear_pending();
+        drain_messages(self, Self::DRAIN_TIMEOUT).await.map(|_| ())
+    }
+}
+
+// Receive and discard messages until the end of a dump and return the number of discarded messages.
+// The dump ends with the done message, an error message, or nothing queued within the timeout.
+async fn drain_messages<S>(socket: &mut S, timeout: std::time::Duration) -> Result<usize, Error>
+where
+    S: ConntrackSocket + Send,
+{
+    let mut discarded = 0;
+    loop {
+        match tokio::time::timeout(timeout, socket.recv_once()).await {
+            Err(_) => return Ok(discarded),
+            Ok(Ok(msgs)) if msgs.is_empty() => return Ok(discarded),
+            Ok(Err(Error::NetlinkMessage(_))) => return Ok(discarded),
+            Ok(Ok(msgs)) => discarded += msgs.len(),
+            Ok(Err(e)) => return Err(e),
+        }
+    }
 }
 
 impl Stream for NfConntrackSocket {
@@ -125,49 +382,80 @@ impl Stream for NfConntrackSocket {
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        match self.inner.poll_recv_from_full(cx) {
-            Poll::Ready(res) => match res {
-                Ok((buf, _)) => {
-                    let mut events = Vec::new();
-                    let l = buf.len();
-                    let mut read = 0;
-                    while l > read {
-                        let msg =
-                            match <NetlinkMessage<NetfilterMessage>>::deserialize(&buf[read..]) {
-                                Ok(msg) => msg,
-                                Err(e) => return Poll::Ready(Some(Err(Error::Netfilter(e)))),
-                            };
-                        read += msg.buffer_len();
-                        let flag = msg.header.flags;
-                        match msg.payload {
-                            NetlinkPayload::Done(_) => {
-                                // When receiving a done message, msgs must be empty.
-                                // Even if msgs is not empty, ignore it.
-                                return Poll::Ready(None);
-                            }
-                            NetlinkPayload::Error(e) => {
-                                return Poll::Ready(Some(Err(Error::NetlinkMessage(
-                                    NetlinkError::from(e.raw_code()),
-                                ))));
-                            }
-                            NetlinkPayload::InnerMessage(msg) => {
-                                let res_id = msg.header.res_id;
-                                if let NetfilterMessageInner::CtNetlink(msg) = msg.inner {
-                                    events.push(Message::new(msg, flag, res_id));
-                                }
-                            }
-                            _ => {}
-                        }
+        // Messages left by the budget are returned before receiving the next datagram.
+        if self.pending.is_empty() {
+            match self.inner.poll_recv_from_full(cx) {
+                Poll::Ready(Ok((buf, _))) => {
+                    debug!(bytes = buf.len(), "polled a datagram");
+                    if let Some(hook) = &self.hook {
+                        (hook.0)(&buf);
                     }
-                    Poll::Ready(Some(Ok(events)))
+                    self.pending = buf;
+                    self.pending_read = 0;
                 }
-                Err(e) => Poll::Ready(Some(Err(Error::Poll(e)))),
-            },
-            Poll::Pending => Poll::Pending,
+                // Poll again as well as recv_datagram() retries.
+                Poll::Ready(Err(e)) if e.kind() == ErrorKind::Interrupted => {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(Err(e)) => {
+                    debug!(error = %e, "failed to poll a datagram");
+                    return Poll::Ready(Some(Err(Error::Poll(e))));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let buf = std::mem::take(&mut self.pending);
+        let (res, read) = parse_polled(&buf[self.pending_read..], self.budget);
+        // The rest is dropped when the dump ends or fails.
+        if matches!(res, Some(Ok(_))) && self.pending_read + read < buf.len() {
+            self.pending_read += read;
+            self.pending = buf;
         }
+        Poll::Ready(res)
     }
 }
 
+// Parse messages of a polled datagram up to the budget.
+// Return the item of poll_next() and the length of parsed messages. None means the end of the dump.
+fn parse_polled(buf: &[u8], budget: Option<usize>) -> (Option<Result<Vec<Message>, Error>>, usize) {
+    let mut events = Vec::new();
+    let mut parsed = 0;
+    let mut read = 0;
+    while buf.len() > read {
+        if budget.is_some_and(|b| parsed >= b) {
+            break;
+        }
+        let msg = match <NetlinkMessage<NetfilterMessage>>::deserialize(&buf[read..]) {
+            Ok(msg) => msg,
+            Err(e) => return (Some(Err(decode_error(e, read))), read),
+        };
+        let start = read;
+        read += msg.buffer_len();
+        parsed += 1;
+        let flag = msg.header.flags;
+        match msg.payload {
+            NetlinkPayload::Done(_) => {
+                // When receiving a done message, msgs must be empty.
+                // Even if msgs is not empty, ignore it.
+                return (None, read);
+            }
+            NetlinkPayload::Error(e) => {
+                return (Some(Err(netlink_error(e.raw_code()))), read);
+            }
+            NetlinkPayload::InnerMessage(msg) => {
+                let res_id = msg.header.res_id;
+                if let NetfilterMessageInner::CtNetlink(msg) = msg.inner {
+                    events.push(Message::new(msg, flag, res_id).with_bytes(&buf[start..read]));
+                }
+            }
+            _ => {}
+        }
+    }
+    debug!(messages = events.len(), "polled messages");
+    (Some(Ok(events)), read)
+}
+
 #[derive(Debug, Default)]
 pub(super) struct MockConntrackSocket {
     request: Option<NetfilterMessage>,
@@ -175,6 +463,8 @@ pub(super) struct MockConntrackSocket {
     ipv6_data: Vec<Message>,
     ipv4_index: usize,
     ipv6_index: usize,
+    // The number of messages discarded by drain().
+    pub(super) drained: usize,
 }
 
 impl MockConntrackSocket {
@@ -186,6 +476,7 @@ impl MockConntrackSocket {
             ipv6_data: Vec::new(),
             ipv4_index: 0,
             ipv6_index: 0,
+            drained: 0,
         }
     }
 
@@ -206,6 +497,7 @@ impl MockConntrackSocket {
             ipv6_data: ipv6_msgs,
             ipv4_index: 0,
             ipv6_index: 0,
+            drained: 0,
         }
     }
 
@@ -220,6 +512,33 @@ impl MockConntrackSocket {
             ipv6_data: ipv6_event,
             ipv4_index: 0,
             ipv6_index: 0,
+            drained: 0,
+        }
+    }
+
+    fn next_ipv4(&mut self) -> Option<Vec<Message>> {
+        let msg = self.ipv4_data.get(self.ipv4_index)?.clone();
+        self.ipv4_index += 1;
+        Some(vec![msg])
+    }
+
+    fn next_ipv6(&mut self) -> Option<Vec<Message>> {
+        let msg = self.ipv6_data.get(self.ipv6_index)?.clone();
+        self.ipv6_index += 1;
+        Some(vec![msg])
+    }
+
+    // Yield one message at a time. An unspec request yields ipv4 messages and then ipv6 ones
+    // while the kernel interleaves them. Tests of Conntrack must not depend on the order across families.
+    fn next_message(&mut self) -> Option<Vec<Message>> {
+        match self.request.as_ref()?.header.family {
+            AF_INET => self.next_ipv4(),
+            AF_INET6 => self.next_ipv6(),
+            AF_UNSPEC => match self.next_ipv4() {
+                Some(data) => Some(data),
+                None => self.next_ipv6(),
+            },
+            _ => None,
         }
     }
 
@@ -254,8 +573,30 @@ impl ConntrackSocket for MockConntrackSocket {
         Ok(vec![])
     }
 
+    // Messages are yielded one by one like the Stream impl. An empty vec tells the end of them.
     async fn recv_once(&mut self) -> Result<Vec<Message>, Error> {
-        Ok(vec![])
+        if self.request.is_none() {
+            return Err(Error::Recv(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "request is not received yet",
+            )));
+        }
+        match self.next_message() {
+            Some(data) => Ok(data),
+            None => {
+                self.clear();
+                Ok(vec![])
+            }
+        }
+    }
+
+    // The rest of the dump is consumed as NfConntrackSocket does, so that tests can tell stale messages are dropped.
+    async fn drain(&mut self) -> Result<(), Error> {
+        if self.request.is_some() {
+            self.drained += drain_messages(self, NfConntrackSocket::DRAIN_TIMEOUT).await?;
+        }
+        self.clear();
+        Ok(())
     }
 }
 
@@ -273,87 +614,22 @@ impl Stream for MockConntrackSocket {
             )))));
         }
 
-        let family = self.request.as_ref().unwrap().header.family;
-
-        match family {
-            AF_INET => {
-                if self.ipv4_data.is_empty() {
-                    return Poll::Ready(Some(Err(Error::Poll(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        "empty",
-                    )))));
-                }
-                if self.ipv4_data.len() > self.ipv4_index {
-                    if self.ipv4_index + 1 > self.ipv4_data.len() {
-                        let data = self.ipv4_data[self.ipv4_index..(self.ipv4_index + 1)].to_vec();
-                        self.ipv4_index += 2;
-                        Poll::Ready(Some(Ok(data)))
-                    } else {
-                        let data = vec![self.ipv4_data[self.ipv4_index].clone()];
-                        self.ipv4_index += 1;
-                        Poll::Ready(Some(Ok(data)))
-                    }
-                } else {
-                    self.clear();
-                    Poll::Ready(None)
-                }
-            }
-            AF_INET6 => {
-                if self.ipv6_data.is_empty() {
-                    return Poll::Ready(Some(Err(Error::Poll(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        "empty",
-                    )))));
-                }
-                if self.ipv6_data.len() > self.ipv6_index {
-                    if self.ipv6_index + 1 > self.ipv6_data.len() {
-                        let data = self.ipv6_data[self.ipv6_index..(self.ipv6_index + 1)].to_vec();
-                        self.ipv6_index += 2;
-                        Poll::Ready(Some(Ok(data)))
-                    } else {
-                        let data = vec![self.ipv6_data[self.ipv6_index].clone()];
-                        self.ipv6_index += 1;
-                        Poll::Ready(Some(Ok(data)))
-                    }
-                } else {
-                    self.clear();
-                    Poll::Ready(None)
-                }
-            }
-            AF_UNSPEC => {
-                if self.ipv4_data.is_empty() && self.ipv6_data.is_empty() {
-                    return Poll::Ready(Some(Err(Error::Poll(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        "empty",
-                    )))));
-                }
-                if self.ipv4_data.len() > self.ipv4_index {
-                    if self.ipv4_index + 1 > self.ipv4_data.len() {
-                        let data = self.ipv4_data[self.ipv4_index..(self.ipv4_index + 1)].to_vec();
-                        self.ipv4_index += 2;
-                        return Poll::Ready(Some(Ok(data)));
-                    } else {
-                        let data = vec![self.ipv4_data[self.ipv4_index].clone()];
-                        self.ipv4_index += 1;
-                        return Poll::Ready(Some(Ok(data)));
-                    }
-                }
-                if self.ipv6_data.len() > self.ipv6_index {
-                    if self.ipv6_index + 1 > self.ipv6_data.len() {
-                        let data = self.ipv6_data[self.ipv6_index..(self.ipv6_index + 1)].to_vec();
-                        self.ipv6_index += 2;
-                        Poll::Ready(Some(Ok(data)))
-                    } else {
-                        let data = vec![self.ipv6_data[self.ipv6_index].clone()];
-                        self.ipv6_index += 1;
-                        Poll::Ready(Some(Ok(data)))
-                    }
-                } else {
-                    self.clear();
-                    Poll::Ready(None)
-                }
-            }
-            _ => {
+        let empty = match self.request.as_ref().unwrap().header.family {
+            AF_INET => self.ipv4_data.is_empty(),
+            AF_INET6 => self.ipv6_data.is_empty(),
+            AF_UNSPEC => self.ipv4_data.is_empty() && self.ipv6_data.is_empty(),
+            _ => false,
+        };
+        if empty {
+            return Poll::Ready(Some(Err(Error::Poll(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "empty",
+            )))));
+        }
+        let data = self.next_message();
+        match data {
+            Some(data) => Poll::Ready(Some(Ok(data))),
+            None => {
                 self.clear();
                 Poll::Ready(None)
             }
@@ -363,12 +639,19 @@ impl Stream for MockConntrackSocket {
 
 #[cfg(test)]
 mod tests {
-    use futures::TryStreamExt;
-    use netlink_packet_netfilter::ctnetlink::message::CtNetlinkMessage;
+    use std::{collections::VecDeque, pin::Pin, task::Poll, time::Duration};
+
+    use async_trait::async_trait;
+    use futures::{Stream, TryStreamExt};
+    use netlink_packet_core::NetlinkMessage;
+    use netlink_packet_netfilter::{ctnetlink::message::CtNetlinkMessage, NetfilterMessage};
+
+    use rstest::rstest;
 
     use crate::{
+        error::{Error, NetlinkError},
         message::{Message, MessageBuilder},
-        socket::{ConntrackSocket, MockConntrackSocket},
+        socket::{drain_messages, parse_polled, ConntrackSocket, MockConntrackSocket, PortIdGuard},
         Family, Table,
     };
 
@@ -377,26 +660,31 @@ mod tests {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
         Message {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
         Message {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
         Message {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
         Message {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
     ];
 
@@ -405,16 +693,19 @@ mod tests {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
         Message {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
         Message {
             flag: 0,
             res_id: 0,
             msg: CtNetlinkMessage::New(vec![]),
+            attrs: Vec::new(),
         },
     ];
 
@@ -432,4 +723,167 @@ mod tests {
         }
         assert_eq!(8, read);
     }
+
+    fn msgs(res_ids: &[u16]) -> Vec<Message> {
+        res_ids
+            .iter()
+            .map(|id| Message {
+                flag: 0,
+                res_id: *id,
+                msg: CtNetlinkMessage::New(vec![]),
+                attrs: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[rstest(
+        family,
+        expected,
+        case(Family::Ipv4, vec![1, 2, 3]),
+        case(Family::Ipv6, vec![4]),
+        case(Family::Unspec, vec![1, 2, 3, 4]),
+    )]
+    #[tokio::test]
+    async fn test_mock_conntrack_socket_poll_order(family: Family, expected: Vec<u16>) {
+        // Odd number of messages to catch skipped entries.
+        let mut mock_socket = MockConntrackSocket::with_event(msgs(&[1, 2, 3]), msgs(&[4]));
+        mock_socket
+            .send(MessageBuilder::new(family, Table::Conntrack).list())
+            .await
+            .unwrap();
+        let mut res_ids = Vec::new();
+        while let Some(msgs) = mock_socket.try_next().await.unwrap() {
+            assert_eq!(msgs.len(), 1);
+            res_ids.extend(msgs.iter().map(|m| m.res_id));
+        }
+        assert_eq!(res_ids, expected);
+    }
+
+    fn nlmsg(len: u32) -> Vec<u8> {
+        let mut buf = len.to_ne_bytes().to_vec();
+        buf.resize(len as usize, 0);
+        buf
+    }
+
+    // A message with the given type and a zeroed payload.
+    fn nlmsg_with_type(len: u32, kind: u16) -> Vec<u8> {
+        let mut buf = nlmsg(len);
+        buf[4..6].copy_from_slice(&kind.to_ne_bytes());
+        buf
+    }
+
+    #[rstest(
+        budget,
+        expected,
+        // Three NLMSG_NOOP messages and NLMSG_DONE.
+        case(None, vec![(false, 68)]),
+        case(Some(1), vec![(true, 16), (true, 16), (true, 16), (false, 20)]),
+        case(Some(2), vec![(true, 32), (false, 36)]),
+        case(Some(4), vec![(false, 68)]),
+    )]
+    fn test_parse_polled(budget: Option<usize>, expected: Vec<(bool, usize)>) {
+        let buf = [
+            nlmsg_with_type(16, 1),
+            nlmsg_with_type(16, 1),
+            nlmsg_with_type(16, 1),
+            nlmsg_with_type(20, 3),
+        ]
+        .concat();
+        let mut offset = 0;
+        let mut res = Vec::new();
+        while offset < buf.len() {
+            let (item, read) = parse_polled(&buf[offset..], budget);
+            offset += read;
+            res.push((item.is_some(), read));
+            if item.is_none() {
+                break;
+            }
+        }
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn test_port_id_guard() {
+        // Pick a portid unlikely to be used by other tests.
+        let id = 0x7fff_0002;
+        let guard = PortIdGuard::reserve(Some(id)).unwrap();
+        assert!(matches!(
+            PortIdGuard::reserve(Some(id)),
+            Err(crate::error::Error::PortIdInUse(_))
+        ));
+        // A failure after the reservation releases the portid.
+        drop(guard);
+        let guard = PortIdGuard::reserve(Some(id)).unwrap();
+        // The committed portid is kept until the socket releases it.
+        assert_eq!(guard.commit(), Some(id));
+        assert!(PortIdGuard::reserve(Some(id)).is_err());
+        super::release_port_id(Some(id));
+        assert!(PortIdGuard::reserve(Some(id)).is_ok());
+        assert!(PortIdGuard::reserve(None).is_ok());
+    }
+
+    // Replies are given in order. Nothing is received after them, as no more messages are queued.
+    struct ScriptedSocket {
+        replies: VecDeque<Result<Vec<Message>, Error>>,
+    }
+
+    #[async_trait]
+    impl ConntrackSocket for ScriptedSocket {
+        async fn send(&mut self, _msg: NetlinkMessage<NetfilterMessage>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> Result<Vec<Message>, Error> {
+            Ok(vec![])
+        }
+
+        async fn recv_once(&mut self) -> Result<Vec<Message>, Error> {
+            match self.replies.pop_front() {
+                Some(reply) => reply,
+                None => std::future::pending().await,
+            }
+        }
+
+        async fn drain(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Stream for ScriptedSocket {
+        type Item = Result<Vec<Message>, Error>;
+
+        fn poll_next(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<Option<Self::Item>> {
+            Poll::Ready(None)
+        }
+    }
+
+    #[rstest(
+        replies,
+        expected,
+        remaining,
+        // The done message ends the dump.
+        case(vec![Ok(msgs(&[1, 2])), Ok(msgs(&[3])), Ok(vec![]), Ok(msgs(&[4]))], Some(3), 1),
+        // An error message ends the dump too.
+        case(vec![Ok(msgs(&[1])), Err(Error::NetlinkMessage(NetlinkError::from(-2))), Ok(msgs(&[2]))], Some(1), 1),
+        // Nothing is queued within the timeout.
+        case(vec![Ok(msgs(&[1])), Ok(msgs(&[2]))], Some(2), 0),
+        // Other errors are returned.
+        case(vec![Ok(msgs(&[1])), Err(Error::Recv(std::io::Error::other("recv"))), Ok(vec![])], None, 1)
+    )]
+    #[tokio::test]
+    async fn test_drain_messages(
+        replies: Vec<Result<Vec<Message>, Error>>,
+        expected: Option<usize>,
+        remaining: usize,
+    ) {
+        let mut socket = ScriptedSocket {
+            replies: replies.into(),
+        };
+        let res = drain_messages(&mut socket, Duration::from_millis(10)).await;
+        assert_eq!(res.ok(), expected);
+        assert_eq!(socket.replies.len(), remaining);
+    }
 }
@@ -0,0 +1,120 @@
+use conntrack::count::Count;
+use serde::Serialize;
+
+use crate::{Column, Row, ToColumnOptions, ToColumns};
+
+#[derive(Debug, Default)]
+pub struct CountRow {
+    delta: bool,
+}
+
+impl CountRow {
+    pub fn new() -> CountRow {
+        CountRow { delta: false }
+    }
+
+    // Show the DELTA column with the change from the previous count.
+    pub fn delta(mut self, show: bool) -> CountRow {
+        self.delta = show;
+        self
+    }
+}
+
+impl Row for CountRow {
+    fn row<C: Column, E: Serialize + ToColumns<C> + Send + Sync>(&self, entry: &E) -> String {
+        let mut row_str = String::new();
+
+        let columns = entry.to_columns(ToColumnOptions {
+            delta: self.delta,
+            ..Default::default()
+        });
+
+        for (i, c) in columns.iter().enumerate() {
+            row_str += &c.column(false);
+            if i != columns.len() - 1 {
+                row_str += " ";
+            }
+        }
+        row_str += "\n";
+
+        row_str
+    }
+
+    fn header(&self) -> String {
+        let mut header = vec![CountColumn::Table(String::new()), CountColumn::Count(0)];
+        if self.delta {
+            header.push(CountColumn::Delta(None));
+        }
+        let mut row_str = String::new();
+        for (i, c) in header.iter().enumerate() {
+            row_str += &c.column(true);
+            if i != header.len() - 1 {
+                row_str += " ";
+            }
+        }
+        row_str += "\n";
+
+        row_str
+    }
+}
+
+#[derive(Debug)]
+pub enum CountColumn {
+    Table(String),
+    Count(u32),
+    // None is shown as "-" for the first count.
+    Delta(Option<i64>),
+}
+
+impl Column for CountColumn {
+    fn header(&self) -> String {
+        match self {
+            CountColumn::Table(_) => String::from("TABLE"),
+            CountColumn::Count(_) => String::from("COUNT"),
+            CountColumn::Delta(_) => String::from("DELTA"),
+        }
+    }
+
+    fn column(&self, header: bool) -> String {
+        match self {
+            CountColumn::Table(t) => {
+                if header {
+                    format!("{:>11}", self.header())
+                } else {
+                    format!("{:>11}", t)
+                }
+            }
+            CountColumn::Count(c) => {
+                if header {
+                    format!("{:>10}", self.header())
+                } else {
+                    format!("{:>10}", c)
+                }
+            }
+            CountColumn::Delta(d) => {
+                if header {
+                    format!("{:>10}", self.header())
+                } else {
+                    match d {
+                        Some(d) => format!("{:>+10}", d),
+                        None => format!("{:>10}", "-"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ToColumns<CountColumn> for Count {
+    fn to_columns(&self, opt: ToColumnOptions) -> Vec<CountColumn> {
+        // Make sure the order is correct.
+        let mut columns = vec![
+            CountColumn::Table(self.table.map(String::from).unwrap_or(String::from("-"))),
+            CountColumn::Count(self.count),
+        ];
+        if opt.delta {
+            columns.push(CountColumn::Delta(self.delta));
+        }
+        columns
+    }
+}
@@ -6,4 +6,6 @@ pub enum Error {
     Json(serde_json::Error),
     #[error("I/O error: {0}")]
     IO(std::io::Error),
+    #[error("unknown field: {0}")]
+    UnknownField(String),
 }
@@ -16,10 +16,17 @@ PROTOCOL PROTONUM    TIMEOUT   TCP_STATE   ORIG_SRC_ADDR   ORIG_DST_ADDR ORIG_SR
      tcp        6 4294967295 ESTABLISHED xxx.xxx.xxx.xxx xxx.xxx.xxx.xxx         65535         65535 xxx.xxx.xxx.xxx xxx.xxx.xxx.xxx          65535          65535  FIXED_TIMEOUT 65535 65535
  */
 
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::OnceLock,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, SecondsFormat};
 
 use conntrack::{
-    flow::{Flow, Protocol, Status},
+    flow::{Counter, FlatFlow, Flow, Protocol, Status, Tuple},
     Family,
 };
 use serde::Serialize;
@@ -28,30 +35,73 @@ use crate::{Column, Row, ToColumnOptions, ToColumns};
 
 pub struct FlowRow {
     detailed_status: bool,
+    status_names: bool,
     family: Family,
-    protocol: Protocol, // default is Tcp, Tcp shows TCP_STATE(when showing Udp flows, TCP_STATE is empty.).
+    protocol: Option<Protocol>, // None shows TCP_STATE for mixed protocols and leaves it empty for flows without a state.
+    secctx: bool,
+    show_id: bool,
+    max_width: Option<usize>,
+    addr_width: OnceLock<Option<usize>>,
+    compact: bool,
+    expires: Option<SystemTime>,
 }
 
 impl FlowRow {
-    pub fn new(detailed_status: bool, family: Family, protocol: Protocol) -> FlowRow {
+    pub fn new(detailed_status: bool, family: Family, protocol: Option<Protocol>) -> FlowRow {
         FlowRow {
             detailed_status,
+            status_names: false,
             family,
             protocol,
+            secctx: false,
+            show_id: false,
+            max_width: None,
+            addr_width: OnceLock::new(),
+            compact: false,
+            expires: None,
         }
     }
+
+    // Show the reply tuple only for translated flows in the last column instead of four reply columns.
+    pub fn compact(mut self, compact: bool) -> FlowRow {
+        self.compact = compact;
+        self
+    }
+
+    // Show when flows expire counting from `now`, the time of the dump.
+    pub fn expires(mut self, now: Option<SystemTime>) -> FlowRow {
+        self.expires = now;
+        self
+    }
+
+    // Show all status flags like "ASSURED,CONFIRMED,SNAT" instead of the preferred one.
+    pub fn status_names(mut self, show: bool) -> FlowRow {
+        self.status_names = show;
+        self
+    }
+
+    pub fn secctx(mut self, show: bool) -> FlowRow {
+        self.secctx = show;
+        self
+    }
+
+    pub fn show_id(mut self, show: bool) -> FlowRow {
+        self.show_id = show;
+        self
+    }
+
+    // Truncate address columns to fit rows in the width. None shows full addresses.
+    pub fn max_width(mut self, width: Option<usize>) -> FlowRow {
+        self.max_width = width;
+        self
+    }
 }
 
 impl Row for FlowRow {
     fn row<C: Column, E: Serialize + ToColumns<C> + Send + Sync>(&self, entry: &E) -> String {
         let mut row_str = String::new();
 
-        let columns = entry.to_columns(ToColumnOptions {
-            event: false,
-            detailed_status: self.detailed_status,
-            omit_tcp_state: self.protocol.eq(&Protocol::Udp),
-            family: self.family,
-        });
+        let columns = entry.to_columns(self.column_options());
 
         for (i, c) in columns.iter().enumerate() {
             row_str += &c.column(false);
@@ -65,6 +115,72 @@ impl Row for FlowRow {
     }
 
     fn header(&self) -> String {
+        self.header_with(self.addr_width())
+    }
+
+    fn cells<C: Column, E: Serialize + ToColumns<C> + Send + Sync>(
+        &self,
+        entry: &E,
+    ) -> Option<Vec<String>> {
+        let columns = entry.to_columns(self.column_options());
+        Some(
+            columns
+                .iter()
+                .map(|c| c.column(false).trim().to_string())
+                .collect(),
+        )
+    }
+
+    fn header_cells(&self) -> Option<Vec<String>> {
+        Some(
+            self.header_columns(self.addr_width())
+                .iter()
+                .map(|c| c.column(true).trim().to_string())
+                .collect(),
+        )
+    }
+}
+
+impl FlowRow {
+    fn column_options(&self) -> ToColumnOptions {
+        ToColumnOptions {
+            event: false,
+            detailed_status: self.detailed_status,
+            status_names: self.status_names,
+            omit_tcp_state: !has_state(self.protocol),
+            family: self.family,
+            secctx: self.secctx,
+            show_id: self.show_id,
+            classic: false,
+            addr_width: self.addr_width(),
+            compact: self.compact,
+            expires: self.expires,
+            delta: false,
+        }
+    }
+
+    fn addr_width(&self) -> Option<usize> {
+        *self
+            .addr_width
+            .get_or_init(|| fit_addr_width(self.max_width, &self.header_with(None)))
+    }
+
+    fn header_with(&self, addr_width: Option<usize>) -> String {
+        let header_columns = self.header_columns(addr_width);
+        let mut row_str = String::new();
+
+        for (i, c) in header_columns.iter().enumerate() {
+            row_str += &c.column(true);
+            if i != header_columns.len() - 1 {
+                row_str += " ";
+            }
+        }
+        row_str += "\n";
+
+        row_str
+    }
+
+    fn header_columns(&self, addr_width: Option<usize>) -> Vec<FlowColumn> {
         let dummy_addr = match self.family {
             Family::Ipv4 => IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
             Family::Ipv6 | Family::Unspec => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
@@ -74,18 +190,18 @@ impl Row for FlowRow {
         } else {
             (String::new(), false)
         };
-        let header_columns: Vec<FlowColumn> = if self.protocol.eq(&Protocol::Tcp) {
+        let mut header_columns: Vec<FlowColumn> = if has_state(self.protocol) {
             vec![
                 FlowColumn::Protocol(String::new()),
                 FlowColumn::ProtocolNumber(0),
                 FlowColumn::Timeout(0),
                 FlowColumn::TcpState(None),
-                FlowColumn::OrigSrcAddr((dummy_addr, self.family)),
-                FlowColumn::OrigDstAddr((dummy_addr, self.family)),
+                FlowColumn::OrigSrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::OrigDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::OrigSrcPort(0),
                 FlowColumn::OrigDstPort(0),
-                FlowColumn::ReplySrcAddr((dummy_addr, self.family)),
-                FlowColumn::ReplyDstAddr((dummy_addr, self.family)),
+                FlowColumn::ReplySrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::ReplyDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::ReplySrcPort(0),
                 FlowColumn::ReplyDstPort(0),
                 FlowColumn::Flags(dummy_flag),
@@ -97,12 +213,12 @@ impl Row for FlowRow {
                 FlowColumn::Protocol(String::new()),
                 FlowColumn::ProtocolNumber(0),
                 FlowColumn::Timeout(0),
-                FlowColumn::OrigSrcAddr((dummy_addr, self.family)),
-                FlowColumn::OrigDstAddr((dummy_addr, self.family)),
+                FlowColumn::OrigSrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::OrigDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::OrigSrcPort(0),
                 FlowColumn::OrigDstPort(0),
-                FlowColumn::ReplySrcAddr((dummy_addr, self.family)),
-                FlowColumn::ReplyDstAddr((dummy_addr, self.family)),
+                FlowColumn::ReplySrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::ReplyDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::ReplySrcPort(0),
                 FlowColumn::ReplyDstPort(0),
                 FlowColumn::Flags(dummy_flag),
@@ -110,43 +226,70 @@ impl Row for FlowRow {
                 FlowColumn::Use(None),
             ]
         };
-        let mut row_str = String::new();
-
-        for (i, c) in header_columns.iter().enumerate() {
-            row_str += &c.column(true);
-            if i != header_columns.len() - 1 {
-                row_str += " ";
-            }
+        if self.show_id {
+            header_columns.push(FlowColumn::Id(None));
         }
-        row_str += "\n";
-
-        row_str
+        if self.secctx {
+            header_columns.push(FlowColumn::SecCtx(None));
+        }
+        if self.expires.is_some() {
+            header_columns.push(FlowColumn::Expires(None));
+        }
+        if self.compact {
+            header_columns.retain(|c| !c.is_reply());
+            header_columns.push(FlowColumn::Reply(None));
+        }
+        header_columns
     }
 }
 
 #[derive(Debug)]
 pub enum FlowColumn {
+    Time(String),
     Event(String),
     Protocol(String),
     ProtocolNumber(u8),
     Timeout(u32),
     TcpState(Option<String>),
-    OrigSrcAddr((IpAddr, Family)),
-    OrigDstAddr((IpAddr, Family)),
+    // The last value is a name shown instead of the address.
+    OrigSrcAddr((IpAddr, Family, Option<usize>, Option<String>)),
+    OrigDstAddr((IpAddr, Family, Option<usize>, Option<String>)),
     OrigSrcPort(u16),
     OrigDstPort(u16),
-    ReplySrcAddr((IpAddr, Family)),
-    ReplyDstAddr((IpAddr, Family)),
+    ReplySrcAddr((IpAddr, Family, Option<usize>, Option<String>)),
+    ReplyDstAddr((IpAddr, Family, Option<usize>, Option<String>)),
     ReplySrcPort(u16),
     ReplyDstPort(u16),
     Flags((String, bool)),
     Mark(Option<u32>),
     Use(Option<u32>),
+    Id(Option<u32>),
+    SecCtx(Option<String>),
+    Bps(Option<u64>),
+    Pps(Option<u64>),
+    // None is shown as "never" for flows with FIXED_TIMEOUT.
+    Expires(Option<String>),
+    // The reply tuple of a translated flow in the compact mode.
+    Reply(Option<String>),
+    Classic(String),
+}
+
+impl FlowColumn {
+    fn is_reply(&self) -> bool {
+        matches!(
+            self,
+            FlowColumn::ReplySrcAddr(_)
+                | FlowColumn::ReplyDstAddr(_)
+                | FlowColumn::ReplySrcPort(_)
+                | FlowColumn::ReplyDstPort(_)
+        )
+    }
 }
 
 impl Column for FlowColumn {
     fn header(&self) -> String {
         match self {
+            FlowColumn::Time(_) => String::from("TIME"),
             FlowColumn::Event(_) => String::from("EVENT"),
             FlowColumn::Protocol(_) => String::from("PROTOCOL"),
             FlowColumn::ProtocolNumber(_) => String::from("PROTONUM"),
@@ -163,24 +306,69 @@ impl Column for FlowColumn {
             FlowColumn::Flags(_) => String::from("FLAGS"),
             FlowColumn::Mark(_) => String::from("MARK"),
             FlowColumn::Use(_) => String::from("USE"),
+            FlowColumn::Id(_) => String::from("ID"),
+            FlowColumn::SecCtx(_) => String::from("SECCTX"),
+            FlowColumn::Bps(_) => String::from("BPS"),
+            FlowColumn::Pps(_) => String::from("PPS"),
+            FlowColumn::Expires(_) => String::from("EXPIRES"),
+            FlowColumn::Reply(_) => String::from("REPLY"),
+            FlowColumn::Classic(_) => String::new(),
         }
     }
 
     fn column(&self, header: bool) -> String {
-        let format_addr = |addr: &IpAddr, family: Family| -> String {
-            match family {
-                Family::Ipv4 => format!("{:>15}", addr),
-                Family::Ipv6 | Family::Unspec => format!("{:>39}", addr),
-            }
-        };
-        let format_addr_header = |h: &str, addr: &IpAddr| -> String {
-            match addr {
-                IpAddr::V4(_addr) => format!("{:>15}", h),
-                IpAddr::V6(_addr) => format!("{:>39}", h),
-            }
+        let format_addr =
+            |addr: &IpAddr, family: Family, max: Option<usize>, name: &Option<String>| -> String {
+                let width = match family {
+                    Family::Ipv4 => IPV4_ADDR_WIDTH,
+                    Family::Ipv6 | Family::Unspec => IPV6_ADDR_WIDTH,
+                };
+                let addr = name.clone().unwrap_or_else(|| addr.to_string());
+                let Some(max) = max else {
+                    return format!("{:>width$}", addr);
+                };
+                let width = width.min(max);
+                if addr.chars().count() > width {
+                    // Keep the head of the address and mark the rest as truncated.
+                    // Names may have non-ASCII characters, so they are cut by chars.
+                    let head: String = addr.chars().take(width - 1).collect();
+                    format!("{}…", head)
+                } else {
+                    format!("{:>width$}", addr)
+                }
+            };
+        let format_addr_header = |h: &str, addr: &IpAddr, max: Option<usize>| -> String {
+            let width = match addr {
+                IpAddr::V4(_addr) => IPV4_ADDR_WIDTH,
+                IpAddr::V6(_addr) => IPV6_ADDR_WIDTH,
+            };
+            let width = max.map_or(width, |m| width.min(m));
+            format!("{:>width$}", h)
         };
 
         match self {
+            FlowColumn::Reply(r) => {
+                if header {
+                    self.header()
+                } else {
+                    r.clone().unwrap_or(String::from("-"))
+                }
+            }
+            FlowColumn::Expires(e) => {
+                if header {
+                    format!("{:>25}", self.header())
+                } else {
+                    format!("{:>25}", e.as_deref().unwrap_or("never"))
+                }
+            }
+            FlowColumn::Classic(line) => line.clone(),
+            FlowColumn::Time(t) => {
+                if header {
+                    format!("{:>29}", self.header())
+                } else {
+                    format!("{:>29}", t)
+                }
+            }
             FlowColumn::Event(e) => {
                 if header {
                     format!("{:>7}", self.header())
@@ -225,16 +413,16 @@ impl Column for FlowColumn {
             }
             FlowColumn::OrigSrcAddr(a) => {
                 if header {
-                    format_addr_header(&self.header(), &a.0)
+                    format_addr_header(&self.header(), &a.0, a.2)
                 } else {
-                    format_addr(&a.0, a.1)
+                    format_addr(&a.0, a.1, a.2, &a.3)
                 }
             }
             FlowColumn::OrigDstAddr(a) => {
                 if header {
-                    format_addr_header(&self.header(), &a.0)
+                    format_addr_header(&self.header(), &a.0, a.2)
                 } else {
-                    format_addr(&a.0, a.1)
+                    format_addr(&a.0, a.1, a.2, &a.3)
                 }
             }
             FlowColumn::OrigSrcPort(n) => {
@@ -253,16 +441,16 @@ impl Column for FlowColumn {
             }
             FlowColumn::ReplySrcAddr(a) => {
                 if header {
-                    format_addr_header(&self.header(), &a.0)
+                    format_addr_header(&self.header(), &a.0, a.2)
                 } else {
-                    format_addr(&a.0, a.1)
+                    format_addr(&a.0, a.1, a.2, &a.3)
                 }
             }
             FlowColumn::ReplyDstAddr(a) => {
                 if header {
-                    format_addr_header(&self.header(), &a.0)
+                    format_addr_header(&self.header(), &a.0, a.2)
                 } else {
-                    format_addr(&a.0, a.1)
+                    format_addr(&a.0, a.1, a.2, &a.3)
                 }
             }
             FlowColumn::ReplySrcPort(n) => {
@@ -324,12 +512,50 @@ impl Column for FlowColumn {
                     }
                 }
             }
+            FlowColumn::Id(id) => {
+                if header {
+                    format!("{:>10}", self.header())
+                } else {
+                    match id {
+                        Some(id) => format!("{:>10}", id),
+                        None => format!("{:>10}", ""),
+                    }
+                }
+            }
+            FlowColumn::SecCtx(ctx) => {
+                if header {
+                    format!("{:>32}", self.header())
+                } else {
+                    match ctx {
+                        Some(ctx) => {
+                            format!("{:>32}", ctx)
+                        }
+                        None => {
+                            format!("{:>32}", "")
+                        }
+                    }
+                }
+            }
+            FlowColumn::Bps(v) | FlowColumn::Pps(v) => {
+                if header {
+                    format!("{:>12}", self.header())
+                } else {
+                    match v {
+                        Some(v) => format!("{:>12}", v),
+                        None => format!("{:>12}", ""),
+                    }
+                }
+            }
         }
     }
 }
 
 impl ToColumns<FlowColumn> for Flow {
     fn to_columns(&self, opt: ToColumnOptions) -> Vec<FlowColumn> {
+        if opt.classic {
+            return vec![FlowColumn::Classic(classic_line(self, opt.event))];
+        }
+
         // Make sure the order is correct.
         let mut columns = Vec::new();
 
@@ -343,63 +569,412 @@ impl ToColumns<FlowColumn> for Flow {
         columns.push(FlowColumn::ProtocolNumber(u8::from(self.protocol)));
         columns.push(FlowColumn::Timeout(self.timeout));
         if !opt.omit_tcp_state {
-            columns.push(FlowColumn::TcpState(self.tcp_state.map(String::from)));
+            let state = match self.protocol {
+                Protocol::Udp => Some(String::from(udp_state(&self.status))),
+                Protocol::Other(IPPROTO_DCCP) => self.dccp_state().map(String::from),
+                _ => self.tcp_state.map(String::from),
+            };
+            columns.push(FlowColumn::TcpState(state));
         }
         columns.push(FlowColumn::OrigSrcAddr((
             self.original.src_addr,
             opt.family,
+            opt.addr_width,
+            None,
         )));
         columns.push(FlowColumn::OrigDstAddr((
             self.original.dst_addr,
             opt.family,
+            opt.addr_width,
+            None,
         )));
         columns.push(FlowColumn::OrigSrcPort(self.original.src_port));
         columns.push(FlowColumn::OrigDstPort(self.original.dst_port));
-        columns.push(FlowColumn::ReplySrcAddr((self.reply.src_addr, opt.family)));
-        columns.push(FlowColumn::ReplyDstAddr((self.reply.dst_addr, opt.family)));
+        columns.push(FlowColumn::ReplySrcAddr((
+            self.reply.src_addr,
+            opt.family,
+            opt.addr_width,
+            None,
+        )));
+        columns.push(FlowColumn::ReplyDstAddr((
+            self.reply.dst_addr,
+            opt.family,
+            opt.addr_width,
+            None,
+        )));
         columns.push(FlowColumn::ReplySrcPort(self.reply.src_port));
         columns.push(FlowColumn::ReplyDstPort(self.reply.dst_port));
         if opt.detailed_status {
             columns.push(FlowColumn::Flags((
-                ct_status_to_string(&self.status, true),
+                ct_status_to_string(&self.status, true, false),
                 true,
             )));
         } else {
             columns.push(FlowColumn::Flags((
-                ct_status_to_string(&self.status, false),
+                ct_status_to_string(&self.status, false, opt.status_names),
                 false,
             )));
         }
         columns.push(FlowColumn::Mark(self.mark));
         columns.push(FlowColumn::Use(self.r#use));
+        if opt.show_id {
+            columns.push(FlowColumn::Id(self.id));
+        }
+        if opt.secctx {
+            columns.push(FlowColumn::SecCtx(self.secctx.clone()));
+        }
+        if let Some(now) = opt.expires {
+            columns.push(FlowColumn::Expires(
+                (u16::from(&self.status) & u16::from(&Status::fixed_timeout()) == 0).then(|| {
+                    DateTime::<Local>::from(self.expires_at(now))
+                        .to_rfc3339_opts(SecondsFormat::Secs, false)
+                }),
+            ));
+        }
+        // The reply column is the last one because its width varies.
+        if opt.compact {
+            columns.retain(|c| !c.is_reply());
+            columns.push(FlowColumn::Reply(
+                (!is_mirror(&self.original, &self.reply)).then(|| self.reply.to_string()),
+            ));
+        }
+
+        columns
+    }
+}
+
+// A flow serialized in the nested form by default or in the flat form for --json-flat.
+// Columns are the same as the flow.
+#[derive(Debug)]
+pub struct FlowJson<'a> {
+    flow: &'a Flow,
+    flat: bool,
+    names: Option<&'a HashMap<IpAddr, String>>,
+}
+
+impl<'a> FlowJson<'a> {
+    pub fn new(flow: &'a Flow) -> FlowJson<'a> {
+        FlowJson {
+            flow,
+            flat: false,
+            names: None,
+        }
+    }
 
+    pub fn flat(mut self, flat: bool) -> Self {
+        self.flat = flat;
+        self
+    }
+
+    // Show names instead of addresses in the table. JSON keeps addresses.
+    pub fn names(mut self, names: Option<&'a HashMap<IpAddr, String>>) -> Self {
+        self.names = names;
+        self
+    }
+}
+
+impl Serialize for FlowJson<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.flat {
+            FlatFlow::from(self.flow).serialize(serializer)
+        } else {
+            self.flow.serialize(serializer)
+        }
+    }
+}
+
+impl ToColumns<FlowColumn> for FlowJson<'_> {
+    fn to_columns(&self, opt: ToColumnOptions) -> Vec<FlowColumn> {
+        let mut columns = self.flow.to_columns(opt);
+        if let Some(names) = self.names {
+            for c in columns.iter_mut() {
+                if let FlowColumn::OrigSrcAddr(a)
+                | FlowColumn::OrigDstAddr(a)
+                | FlowColumn::ReplySrcAddr(a)
+                | FlowColumn::ReplyDstAddr(a) = c
+                {
+                    a.3 = names.get(&a.0).cloned();
+                }
+            }
+        }
         columns
     }
 }
 
-fn ct_status_to_string(status: &Status, detail: bool) -> String {
+// A flow with information observed when the event was received.
+#[derive(Debug, Serialize)]
+pub struct EventFlow<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Rate>,
+    #[serde(flatten)]
+    pub flow: FlowJson<'a>,
+}
+
+impl<'a> EventFlow<'a> {
+    pub fn new(flow: &'a Flow) -> EventFlow<'a> {
+        EventFlow {
+            timestamp: None,
+            rate: None,
+            flow: FlowJson::new(flow),
+        }
+    }
+
+    pub fn flat(mut self, flat: bool) -> Self {
+        self.flow = self.flow.flat(flat);
+        self
+    }
+}
+
+// Throughput of a flow since the previous observation.
+// Values are None when it cannot be computed such as at the first observation.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Rate {
+    pub bps: Option<u64>,
+    pub pps: Option<u64>,
+}
+
+impl ToColumns<FlowColumn> for EventFlow<'_> {
+    fn to_columns(&self, opt: ToColumnOptions) -> Vec<FlowColumn> {
+        if opt.classic {
+            return self.flow.to_columns(opt);
+        }
+        let mut columns = Vec::new();
+        if let Some(timestamp) = &self.timestamp {
+            columns.push(FlowColumn::Time(timestamp.clone()));
+        }
+        columns.extend(self.flow.to_columns(opt));
+        if let Some(rate) = self.rate {
+            columns.push(FlowColumn::Bps(rate.bps));
+            columns.push(FlowColumn::Pps(rate.pps));
+        }
+        columns
+    }
+}
+
+// The format of conntrack-tools.
+// tcp      6 431999 ESTABLISHED src=10.0.0.1 dst=10.0.0.2 sport=12345 dport=80 src=10.0.0.2 dst=10.0.0.1 sport=80 dport=12345 [ASSURED] mark=0 use=1
+// ICMP type, code and id are not shown because they are not parsed.
+fn classic_line(flow: &Flow, event: bool) -> String {
+    let mut fields = Vec::new();
+    if event {
+        let event_type = format!("[{}]", String::from(flow.event_type).to_uppercase());
+        fields.push(format!("{:>9}", event_type));
+    }
+    let (name, has_ports) = match flow.protocol {
+        Protocol::Tcp => ("tcp", true),
+        Protocol::Udp => ("udp", true),
+        Protocol::Other(1) => ("icmp", false),
+        Protocol::Other(33) => ("dccp", true),
+        Protocol::Other(47) => ("gre", false),
+        Protocol::Other(58) => ("icmpv6", false),
+        Protocol::Other(132) => ("sctp", true),
+        Protocol::Other(136) => ("udplite", true),
+        Protocol::Other(_) => ("unknown", false),
+    };
+    fields.push(format!("{:<8} {}", name, u8::from(flow.protocol)));
+    fields.push(flow.timeout.to_string());
+    if let Some(s) = flow.tcp_state {
+        fields.push(String::from(s));
+    }
+    if let Some(s) = flow.dccp_state() {
+        fields.push(String::from(s));
+    }
+    let tuple = |fields: &mut Vec<String>, t: &Tuple, counter: Option<Counter>| {
+        fields.push(format!("src={}", t.src_addr));
+        fields.push(format!("dst={}", t.dst_addr));
+        if has_ports {
+            fields.push(format!("sport={}", t.src_port));
+            fields.push(format!("dport={}", t.dst_port));
+        }
+        if let Some(c) = counter {
+            fields.push(format!("packets={}", c.packets));
+            fields.push(format!("bytes={}", c.bytes));
+        }
+    };
+    tuple(&mut fields, &flow.original, flow.orig_counter);
+    let status = u16::from(&flow.status);
+    if status & u16::from(&Status::seen_reply()) == 0 {
+        fields.push(String::from("[UNREPLIED]"));
+    }
+    tuple(&mut fields, &flow.reply, flow.reply_counter);
+    if status & u16::from(&Status::assured()) != 0 {
+        fields.push(String::from("[ASSURED]"));
+    }
+    if let Some(m) = flow.mark {
+        fields.push(format!("mark={m}"));
+    }
+    if let Some(ctx) = &flow.secctx {
+        fields.push(format!("secctx={ctx}"));
+    }
+    if let Some(u) = flow.r#use {
+        fields.push(format!("use={u}"));
+    }
+    fields.join(" ")
+}
+
+// The reply tuple of a flow without NAT is the original tuple with the source and the destination swapped.
+fn is_mirror(original: &Tuple, reply: &Tuple) -> bool {
+    original.src_addr == reply.dst_addr
+        && original.dst_addr == reply.src_addr
+        && original.src_port == reply.dst_port
+        && original.dst_port == reply.src_port
+}
+
+// DCCP has no variant in Protocol, so it is matched by the protocol number.
+const IPPROTO_DCCP: u8 = 33;
+
+// UDP flows show a pseudo state in the column of the TCP state, and DCCP flows show their own state.
+// The column is shown when flows of any protocol can be listed because they may include ones with a state.
+fn has_state(protocol: Option<Protocol>) -> bool {
+    matches!(
+        protocol,
+        None | Some(Protocol::Tcp | Protocol::Udp | Protocol::Other(IPPROTO_DCCP))
+    )
+}
+
+// UDP has no state, but the kernel tells replied flows by SEEN_REPLY
+// and sets ASSURED when it sees a stream of packets in both directions to give it the longer stream timeout.
+// The timeout itself is not used because both timeouts are configurable by sysctl.
+fn udp_state(status: &Status) -> &'static str {
+    let status = u16::from(status);
+    if status & u16::from(&Status::seen_reply()) == 0 {
+        "UNREPLIED"
+    } else if status & u16::from(&Status::assured()) != 0 {
+        "STREAM"
+    } else {
+        "REPLIED"
+    }
+}
+
+const IPV4_ADDR_WIDTH: usize = 15;
+const IPV6_ADDR_WIDTH: usize = 39;
+
+// Shrink four address columns equally until the header fits in max_width.
+// Address columns are never narrower than ipv4 addresses.
+fn fit_addr_width(max_width: Option<usize>, full_header: &str) -> Option<usize> {
+    let max_width = max_width?;
+    let full = full_header.trim_end().chars().count();
+    if full <= max_width {
+        return None;
+    }
+    let shrink = (full - max_width).div_ceil(4);
+    Some(IPV6_ADDR_WIDTH.saturating_sub(shrink).max(IPV4_ADDR_WIDTH))
+}
+
+// Flags are shown in binary with detail, joined by name with names and the preferred one otherwise.
+fn ct_status_to_string(status: &Status, detail: bool, names: bool) -> String {
     if detail {
         let n = u16::from(status);
         format!("{:0>15b}", n)
+    } else if names {
+        status.to_string()
     } else {
         status.preferred_one()
     }
 }
 
+// A row in the format of `conntrack -L` and `conntrack -E`.
+// It has no header to keep the output compatible with scripts parsing conntrack-tools.
+#[derive(Debug, Default)]
+pub struct ClassicRow {
+    event: bool,
+}
+
+impl ClassicRow {
+    pub fn new(event: bool) -> ClassicRow {
+        ClassicRow { event }
+    }
+}
+
+impl Row for ClassicRow {
+    fn row<C: Column, E: Serialize + ToColumns<C> + Send + Sync>(&self, entry: &E) -> String {
+        let columns = entry.to_columns(ToColumnOptions {
+            event: self.event,
+            classic: true,
+            ..Default::default()
+        });
+        let mut row_str = columns
+            .iter()
+            .map(|c| c.column(false))
+            .collect::<Vec<String>>()
+            .join(" ");
+        row_str += "\n";
+
+        row_str
+    }
+
+    fn header(&self) -> String {
+        String::new()
+    }
+}
+
 pub struct EventFlowRow {
     detailed_status: bool,
+    status_names: bool,
     family: Family,
-    protocol: Protocol, // default is Tcp, Tcp shows TCP_STATE(when showing Udp flows, TCP_STATE is empty.).
+    protocol: Option<Protocol>, // None shows TCP_STATE for mixed protocols and leaves it empty for flows without a state.
+    secctx: bool,
+    show_id: bool,
+    timestamps: bool,
+    rates: bool,
+    max_width: Option<usize>,
+    addr_width: OnceLock<Option<usize>>,
 }
 
 impl EventFlowRow {
-    pub fn new(detailed_status: bool, family: Family, protocol: Protocol) -> EventFlowRow {
+    pub fn new(detailed_status: bool, family: Family, protocol: Option<Protocol>) -> EventFlowRow {
         EventFlowRow {
             detailed_status,
+            status_names: false,
             family,
             protocol,
+            secctx: false,
+            show_id: false,
+            timestamps: false,
+            rates: false,
+            max_width: None,
+            addr_width: OnceLock::new(),
         }
     }
+
+    // Show all status flags like "ASSURED,CONFIRMED,SNAT" instead of the preferred one.
+    pub fn status_names(mut self, show: bool) -> EventFlowRow {
+        self.status_names = show;
+        self
+    }
+
+    pub fn secctx(mut self, show: bool) -> EventFlowRow {
+        self.secctx = show;
+        self
+    }
+
+    pub fn show_id(mut self, show: bool) -> EventFlowRow {
+        self.show_id = show;
+        self
+    }
+
+    // Entries must be EventFlow with the timestamp when the TIME column is shown.
+    pub fn timestamps(mut self, show: bool) -> EventFlowRow {
+        self.timestamps = show;
+        self
+    }
+
+    // Entries must be EventFlow with the rate when BPS and PPS columns are shown.
+    pub fn rates(mut self, show: bool) -> EventFlowRow {
+        self.rates = show;
+        self
+    }
+
+    // Truncate address columns to fit rows in the width. None shows full addresses.
+    pub fn max_width(mut self, width: Option<usize>) -> EventFlowRow {
+        self.max_width = width;
+        self
+    }
 }
 
 impl Row for EventFlowRow {
@@ -409,8 +984,16 @@ impl Row for EventFlowRow {
         let columns = entry.to_columns(ToColumnOptions {
             event: true,
             detailed_status: self.detailed_status,
-            omit_tcp_state: self.protocol.eq(&Protocol::Udp),
+            status_names: self.status_names,
+            omit_tcp_state: !has_state(self.protocol),
             family: self.family,
+            secctx: self.secctx,
+            show_id: self.show_id,
+            classic: false,
+            addr_width: self.addr_width(),
+            compact: false,
+            expires: None,
+            delta: false,
         });
 
         for (i, c) in columns.iter().enumerate() {
@@ -425,6 +1008,18 @@ impl Row for EventFlowRow {
     }
 
     fn header(&self) -> String {
+        self.header_with(self.addr_width())
+    }
+}
+
+impl EventFlowRow {
+    fn addr_width(&self) -> Option<usize> {
+        *self
+            .addr_width
+            .get_or_init(|| fit_addr_width(self.max_width, &self.header_with(None)))
+    }
+
+    fn header_with(&self, addr_width: Option<usize>) -> String {
         let dummy_addr = match self.family {
             Family::Ipv4 => IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
             Family::Ipv6 | Family::Unspec => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
@@ -434,22 +1029,24 @@ impl Row for EventFlowRow {
         } else {
             (String::new(), false)
         };
-        let header_columns: Vec<FlowColumn> = if self.protocol.eq(&Protocol::Tcp) {
+        let mut header_columns: Vec<FlowColumn> = if has_state(self.protocol) {
             vec![
                 FlowColumn::Event(String::new()),
                 FlowColumn::Protocol(String::new()),
                 FlowColumn::ProtocolNumber(0),
                 FlowColumn::Timeout(0),
                 FlowColumn::TcpState(None),
-                FlowColumn::OrigSrcAddr((dummy_addr, self.family)),
-                FlowColumn::OrigDstAddr((dummy_addr, self.family)),
+                FlowColumn::OrigSrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::OrigDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::OrigSrcPort(0),
                 FlowColumn::OrigDstPort(0),
-                FlowColumn::ReplySrcAddr((dummy_addr, self.family)),
-                FlowColumn::ReplyDstAddr((dummy_addr, self.family)),
+                FlowColumn::ReplySrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::ReplyDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::ReplySrcPort(0),
                 FlowColumn::ReplyDstPort(0),
                 FlowColumn::Flags(dummy_flag),
+                FlowColumn::Mark(None),
+                FlowColumn::Use(None),
             ]
         } else {
             vec![
@@ -457,17 +1054,32 @@ impl Row for EventFlowRow {
                 FlowColumn::Protocol(String::new()),
                 FlowColumn::ProtocolNumber(0),
                 FlowColumn::Timeout(0),
-                FlowColumn::OrigSrcAddr((dummy_addr, self.family)),
-                FlowColumn::OrigDstAddr((dummy_addr, self.family)),
+                FlowColumn::OrigSrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::OrigDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::OrigSrcPort(0),
                 FlowColumn::OrigDstPort(0),
-                FlowColumn::ReplySrcAddr((dummy_addr, self.family)),
-                FlowColumn::ReplyDstAddr((dummy_addr, self.family)),
+                FlowColumn::ReplySrcAddr((dummy_addr, self.family, addr_width, None)),
+                FlowColumn::ReplyDstAddr((dummy_addr, self.family, addr_width, None)),
                 FlowColumn::ReplySrcPort(0),
                 FlowColumn::ReplyDstPort(0),
                 FlowColumn::Flags(dummy_flag),
+                FlowColumn::Mark(None),
+                FlowColumn::Use(None),
             ]
         };
+        if self.timestamps {
+            header_columns.insert(0, FlowColumn::Time(String::new()));
+        }
+        if self.show_id {
+            header_columns.push(FlowColumn::Id(None));
+        }
+        if self.secctx {
+            header_columns.push(FlowColumn::SecCtx(None));
+        }
+        if self.rates {
+            header_columns.push(FlowColumn::Bps(None));
+            header_columns.push(FlowColumn::Pps(None));
+        }
         let mut row_str = String::new();
 
         for (i, c) in header_columns.iter().enumerate() {
@@ -481,3 +1093,57 @@ impl Row for EventFlowRow {
         row_str
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use conntrack::{flow::Flow, Family};
+    use rstest::rstest;
+
+    use crate::{Column, Row};
+
+    use super::{EventFlow, EventFlowRow, FlowColumn, FlowRow};
+
+    // The TCP_STATE column is right aligned to the end of its header.
+    fn state_cell<'a>(header: &str, row: &'a str) -> &'a str {
+        let end = header.find("TCP_STATE").unwrap() + "TCP_STATE".len();
+        row[end - 11..end].trim()
+    }
+
+    #[rstest(
+        flow,
+        expected,
+        case("event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:80 reply=10.0.0.2:80->10.0.0.1:1234 state=ESTABLISHED status=SEEN_REPLY,ASSURED timeout=100", "ESTABLISHED"),
+        case("event=update proto=udp orig=10.0.0.1:1234->10.0.0.2:53 reply=10.0.0.2:53->10.0.0.1:1234 status=SEEN_REPLY timeout=30", "REPLIED"),
+        case("event=update proto=udp orig=10.0.0.1:1234->10.0.0.2:53 reply=10.0.0.2:53->10.0.0.1:1234 status=CONFIRMED timeout=30", "UNREPLIED"),
+        case("event=update proto=icmp orig=10.0.0.1:0->10.0.0.2:0 reply=10.0.0.2:0->10.0.0.1:0 status=SEEN_REPLY timeout=30", ""),
+    )]
+    fn test_flow_row_state(flow: &str, expected: &str) {
+        let flow: Flow = flow.parse().unwrap();
+
+        let row = FlowRow::new(false, Family::Ipv4, None);
+        assert_eq!(state_cell(&row.header(), &row.row(&flow)), expected);
+
+        let row = EventFlowRow::new(false, Family::Ipv4, None);
+        assert_eq!(
+            state_cell(&row.header(), &row.row(&EventFlow::new(&flow))),
+            expected
+        );
+    }
+
+    #[rstest(
+        name,
+        expected,
+        case(None, "       10.0.0.1"),
+        case(Some("host.example"), "   host.example"),
+        case(Some("very-long-host.example"), "very-long-host…"),
+        case(Some("ホスト.テスト.example.com"), "ホスト.テスト.exampl…")
+    )]
+    fn test_flow_column_addr_truncate(name: Option<&str>, expected: &str) {
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+        let column =
+            FlowColumn::OrigSrcAddr((addr, Family::Ipv4, Some(15), name.map(String::from)));
+        assert_eq!(column.column(false), expected);
+    }
+}
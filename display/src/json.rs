@@ -1,11 +1,14 @@
 use async_trait::async_trait;
 use serde::Serialize;
-use tokio::io::AsyncWriteExt;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncWriteExt, BufWriter};
 
 use crate::{error::Error, Column, Display};
 
 pub struct JsonDisplay<W: AsyncWriteExt + Unpin + Send + Sync> {
-    writer: W,
+    writer: BufWriter<W>,
+    // Top level keys to keep. All keys are kept when empty.
+    fields: Vec<String>,
 }
 
 unsafe impl<W> Send for JsonDisplay<W> where W: AsyncWriteExt + Unpin + Send + Sync {}
@@ -16,7 +19,15 @@ where
     W: AsyncWriteExt + Unpin + Send + Sync,
 {
     pub fn new(writer: W) -> JsonDisplay<W> {
-        JsonDisplay { writer }
+        JsonDisplay {
+            writer: BufWriter::new(writer),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn fields(mut self, fields: Vec<String>) -> Self {
+        self.fields = fields;
+        self
     }
 }
 
@@ -29,12 +40,79 @@ where
         &mut self,
         flow: &E,
     ) -> Result<(), Error> {
-        let str = serde_json::to_string(flow).map_err(Error::Json)?;
-        self.writer.write(str.as_bytes()).await.map_err(Error::IO)?;
+        // Each entry is a line to be read as JSON Lines.
+        let mut str = if self.fields.is_empty() {
+            serde_json::to_string(flow).map_err(Error::Json)?
+        } else {
+            serde_json::to_string(&project(flow, &self.fields)?).map_err(Error::Json)?
+        };
+        str.push('\n');
+        self.writer
+            .write_all(str.as_bytes())
+            .await
+            .map_err(Error::IO)?;
         Ok(())
     }
 
     async fn header(&mut self) -> Result<(), Error> {
         Ok(())
     }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush().await.map_err(Error::IO)
+    }
+}
+
+// Keys omitted from entries when they have no value.
+const OPTIONAL_FIELDS: [&str; 6] = ["proto_info", "master", "icmp", "raw", "timestamp", "rate"];
+
+// A key which doesn't exist in the entry is rejected unless it is omitted for having no value.
+fn project<E: Serialize>(entry: &E, fields: &[String]) -> Result<Value, Error> {
+    let value = serde_json::to_value(entry).map_err(Error::Json)?;
+    let Value::Object(mut m) = value else {
+        return Ok(value);
+    };
+    let mut projected = Map::new();
+    for f in fields {
+        match m.remove_entry(f) {
+            Some((k, v)) => {
+                projected.insert(k, v);
+            }
+            None if OPTIONAL_FIELDS.contains(&f.as_str()) => {}
+            None => return Err(Error::UnknownField(f.clone())),
+        }
+    }
+    Ok(Value::Object(projected))
+}
+
+#[cfg(test)]
+mod tests {
+    use conntrack::flow::Flow;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use crate::flow::FlowJson;
+
+    use super::project;
+
+    const FLOW: &str = "event=update proto=tcp orig=10.0.0.1:1234->10.0.0.2:80 reply=10.0.0.2:80->10.0.0.1:1234 state=ESTABLISHED status=SEEN_REPLY,ASSURED timeout=100";
+
+    #[rstest(
+        fields,
+        flat,
+        expected,
+        case(vec!["timeout", "protocol"], false, Some(json!({"timeout": 100, "protocol": "Tcp"}))),
+        case(vec!["orig_dst_port"], true, Some(json!({"orig_dst_port": 80}))),
+        // Omitted for having no value.
+        case(vec!["timeout", "master"], false, Some(json!({"timeout": 100}))),
+        case(vec!["timeout", "foo"], false, None),
+        // Only in the flat form.
+        case(vec!["orig_dst_port"], false, None),
+    )]
+    fn test_project(fields: Vec<&str>, flat: bool, expected: Option<serde_json::Value>) {
+        let flow: Flow = FLOW.parse().unwrap();
+        let fields: Vec<String> = fields.into_iter().map(String::from).collect();
+        let res = project(&FlowJson::new(&flow).flat(flat), &fields);
+        assert_eq!(res.ok(), expected);
+    }
 }
@@ -1,8 +1,11 @@
+use std::time::SystemTime;
+
 use async_trait::async_trait;
 use conntrack::Family;
 use error::Error;
 use serde::Serialize;
 
+pub mod count;
 pub mod error;
 pub mod flow;
 pub mod json;
@@ -16,11 +19,24 @@ pub trait Display {
         entry: &E,
     ) -> Result<(), Error>;
     async fn header(&mut self) -> Result<(), Error>;
+    // Output is buffered. So flush() must be called after all entries are consumed.
+    async fn flush(&mut self) -> Result<(), Error>;
 }
 
 pub trait Row {
     fn row<C: Column, E: Serialize + ToColumns<C> + Send + Sync>(&self, entry: &E) -> String;
     fn header(&self) -> String;
+    // Values of columns without padding for auto-width tables.
+    // Rows returning None are rendered with fixed widths even in auto-width tables.
+    fn cells<C: Column, E: Serialize + ToColumns<C> + Send + Sync>(
+        &self,
+        _entry: &E,
+    ) -> Option<Vec<String>> {
+        None
+    }
+    fn header_cells(&self) -> Option<Vec<String>> {
+        None
+    }
 }
 
 pub trait Column {
@@ -36,6 +52,20 @@ pub trait ToColumns<C: Column> {
 pub struct ToColumnOptions {
     pub event: bool,
     pub detailed_status: bool,
+    // Show all status flags by name instead of the preferred one.
+    pub status_names: bool,
     pub omit_tcp_state: bool,
     pub family: Family,
+    pub secctx: bool,
+    pub show_id: bool,
+    // Produce a single column in the format of `conntrack -L`.
+    pub classic: bool,
+    // Addresses longer than this are truncated. None shows full addresses.
+    pub addr_width: Option<usize>,
+    // Replace reply columns with a single column which is shown only for translated flows.
+    pub compact: bool,
+    // The time of the dump to show when flows expire. None hides the expires column.
+    pub expires: Option<SystemTime>,
+    // Show the change from the previous count.
+    pub delta: bool,
 }
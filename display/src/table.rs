@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use serde::Serialize;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncWriteExt, BufWriter};
 
 use crate::{error::Error, Column, Display, Row, ToColumns};
 
@@ -23,8 +23,13 @@ PROTOCOL PROTONUM    TIMEOUT   TCP_STATE   ORIG_SRC_ADDR   ORIG_DST_ADDR ORIG_SR
  */
 
 pub struct TableDisplay<W: AsyncWriteExt + Unpin + Send + Sync, R: Row> {
-    writer: W,
+    // Writing each row directly results in a syscall per row, which is slow for large tables.
+    writer: BufWriter<W>,
     row: R,
+    // Rows are buffered until flush() to size columns to the widest value.
+    auto_width: bool,
+    header: Option<Vec<String>>,
+    rows: Vec<Vec<String>>,
 }
 
 unsafe impl<W, R> Send for TableDisplay<W, R>
@@ -46,7 +51,48 @@ where
     R: Row,
 {
     pub fn new(writer: W, row: R) -> TableDisplay<W, R> {
-        TableDisplay { writer, row }
+        TableDisplay {
+            writer: BufWriter::new(writer),
+            row,
+            auto_width: false,
+            header: None,
+            rows: Vec::new(),
+        }
+    }
+
+    // Pad columns to the widest value like `column -t`. Nothing is written until flush().
+    pub fn auto_width(mut self, auto_width: bool) -> TableDisplay<W, R> {
+        self.auto_width = auto_width;
+        self
+    }
+
+    async fn write_buffered(&mut self) -> Result<(), Error> {
+        let mut widths: Vec<usize> = Vec::new();
+        for cells in self.header.iter().chain(self.rows.iter()) {
+            for (i, cell) in cells.iter().enumerate() {
+                let len = cell.chars().count();
+                match widths.get_mut(i) {
+                    Some(width) => *width = (*width).max(len),
+                    None => widths.push(len),
+                }
+            }
+        }
+        let header = self.header.take();
+        let rows = std::mem::take(&mut self.rows);
+        for cells in header.iter().chain(rows.iter()) {
+            let line = cells
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:>width$}", cell))
+                .collect::<Vec<_>>()
+                .join(" ")
+                + "\n";
+            self.writer
+                .write_all(line.as_bytes())
+                .await
+                .map_err(Error::IO)?;
+        }
+        Ok(())
     }
 }
 
@@ -60,16 +106,38 @@ where
         &mut self,
         entry: &E,
     ) -> Result<(), Error> {
+        if self.auto_width {
+            if let Some(cells) = self.row.cells(entry) {
+                self.rows.push(cells);
+                return Ok(());
+            }
+        }
         let r = self.row.row(entry);
-        self.writer.write(r.as_bytes()).await.map_err(Error::IO)?;
+        self.writer
+            .write_all(r.as_bytes())
+            .await
+            .map_err(Error::IO)?;
         Ok(())
     }
 
     async fn header(&mut self) -> Result<(), Error> {
+        if self.auto_width {
+            if let Some(cells) = self.row.header_cells() {
+                self.header = Some(cells);
+                return Ok(());
+            }
+        }
         self.writer
-            .write(self.row.header().as_bytes())
+            .write_all(self.row.header().as_bytes())
             .await
             .map_err(Error::IO)?;
         Ok(())
     }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if self.auto_width {
+            self.write_buffered().await?;
+        }
+        self.writer.flush().await.map_err(Error::IO)
+    }
 }
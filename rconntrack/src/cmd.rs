@@ -2,15 +2,44 @@ use async_trait::async_trait;
 use clap::{Parser, Subcommand};
 use conntrack::{socket::NfConntrackSocket, Conntrack};
 use display::Display;
+use terminal_size::Width;
 
 use crate::{
-    config::Output, count::CountCmd, error::Error, event::EventCmd, get::GetCmd, list::ListCmd,
-    stats::StatsCmd, version::VersionCmd,
+    config::{GlobalOption, Output},
+    count::CountCmd,
+    delete::DeleteCmd,
+    error::Error,
+    event::EventCmd,
+    get::GetCmd,
+    healthcheck::HealthcheckCmd,
+    list::ListCmd,
+    replay::ReplayCmd,
+    stats::StatsCmd,
+    update::UpdateCmd,
+    version::VersionCmd,
 };
 
 #[derive(Debug, Parser)]
 #[command(about = "Rconntrack is command line interface for the connection tracking in Linux.")]
 pub(super) struct Cmd {
+    #[arg(
+        long,
+        global = true,
+        help = "Network namespace to inspect. Accept a name in /var/run/netns or a path to the namespace file"
+    )]
+    netns: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Netlink portid to bind the socket with instead of letting the kernel assign one"
+    )]
+    port_id: Option<u32>,
+    #[arg(
+        long,
+        global = true,
+        help = "Print the hex of each netlink datagram received from the kernel to stderr before parsing it"
+    )]
+    raw_dump: bool,
     #[clap(subcommand)]
     sub: SubCmd,
 }
@@ -18,7 +47,17 @@ pub(super) struct Cmd {
 // All subcommands(except version command) must satisfy Runner traits.
 #[async_trait]
 pub(super) trait Runner {
-    async fn run(&self) -> Result<(), Error>;
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error>;
+
+    // Whether the output is JSON. Errors are reported in JSON too in that case.
+    fn json_output(&self) -> bool {
+        false
+    }
+
+    // Whether the exit code tells if any entry matched like grep.
+    fn status_code(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -26,20 +65,61 @@ pub(super) enum SubCmd {
     Version(VersionCmd),
     List(ListCmd),
     Get(GetCmd),
+    Delete(DeleteCmd),
+    Update(UpdateCmd),
     Event(EventCmd),
     Count(CountCmd),
     Stats(StatsCmd),
+    Replay(ReplayCmd),
+    Healthcheck(HealthcheckCmd),
 }
 
 impl Cmd {
     pub(super) async fn run(&self) -> Result<(), Error> {
+        let opt = GlobalOption::new(self.netns.as_deref(), self.port_id, self.raw_dump);
         match &self.sub {
-            SubCmd::Version(version) => version.run().await,
-            SubCmd::List(list) => list.run().await,
-            SubCmd::Get(get) => get.run().await,
-            SubCmd::Event(event) => event.run().await,
-            SubCmd::Count(count) => count.run().await,
-            SubCmd::Stats(stat) => stat.run().await,
+            SubCmd::Version(version) => version.run(&opt).await,
+            SubCmd::List(list) => list.run(&opt).await,
+            SubCmd::Get(get) => get.run(&opt).await,
+            SubCmd::Delete(delete) => delete.run(&opt).await,
+            SubCmd::Update(update) => update.run(&opt).await,
+            SubCmd::Event(event) => event.run(&opt).await,
+            SubCmd::Count(count) => count.run(&opt).await,
+            SubCmd::Stats(stat) => stat.run(&opt).await,
+            SubCmd::Replay(replay) => replay.run(&opt).await,
+            SubCmd::Healthcheck(healthcheck) => healthcheck.run(&opt).await,
+        }
+    }
+}
+
+impl Cmd {
+    pub(super) fn json_output(&self) -> bool {
+        match &self.sub {
+            SubCmd::Version(version) => version.json_output(),
+            SubCmd::List(list) => list.json_output(),
+            SubCmd::Get(get) => get.json_output(),
+            SubCmd::Delete(delete) => delete.json_output(),
+            SubCmd::Update(update) => update.json_output(),
+            SubCmd::Event(event) => event.json_output(),
+            SubCmd::Count(count) => count.json_output(),
+            SubCmd::Stats(stat) => stat.json_output(),
+            SubCmd::Replay(replay) => replay.json_output(),
+            SubCmd::Healthcheck(healthcheck) => healthcheck.json_output(),
+        }
+    }
+
+    pub(super) fn status_code(&self) -> bool {
+        match &self.sub {
+            SubCmd::Version(version) => version.status_code(),
+            SubCmd::List(list) => list.status_code(),
+            SubCmd::Get(get) => get.status_code(),
+            SubCmd::Delete(delete) => delete.status_code(),
+            SubCmd::Update(update) => update.status_code(),
+            SubCmd::Event(event) => event.status_code(),
+            SubCmd::Count(count) => count.status_code(),
+            SubCmd::Stats(stat) => stat.status_code(),
+            SubCmd::Replay(replay) => replay.status_code(),
+            SubCmd::Healthcheck(healthcheck) => healthcheck.status_code(),
         }
     }
 }
@@ -48,9 +128,19 @@ impl Cmd {
 pub(super) trait DisplayRunner {
     fn output(&self) -> Output;
     fn no_header(&self) -> bool;
+    // Return the number of displayed entries.
     async fn process<D: Display + Send + Sync>(
         &self,
         mut ct: Conntrack<NfConntrackSocket>,
         mut display: D,
-    ) -> Result<(), Error>;
+    ) -> Result<usize, Error>;
+}
+
+// Width of the terminal connected to stdout to fit table rows in.
+// None when the output is not a terminal or the full width is requested.
+pub(super) fn terminal_width(wide: bool) -> Option<usize> {
+    if wide {
+        return None;
+    }
+    terminal_size::terminal_size().map(|(Width(w), _)| w as usize)
 }
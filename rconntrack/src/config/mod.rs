@@ -1,14 +1,44 @@
-use std::{net::IpAddr, str::FromStr};
+use std::{net::IpAddr, path::PathBuf, str::FromStr};
 
 use error::Error;
 
 pub(crate) mod error;
 
+const NETNS_DIR: &str = "/var/run/netns";
+
+// Options shared by all subcommands.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct GlobalOption {
+    pub(crate) netns: Option<PathBuf>,
+    pub(crate) port_id: Option<u32>,
+    // Dump received netlink datagrams as hex to stderr.
+    pub(crate) raw_dump: bool,
+}
+
+impl GlobalOption {
+    pub(crate) fn new(netns: Option<&str>, port_id: Option<u32>, raw_dump: bool) -> GlobalOption {
+        GlobalOption {
+            // A bare name is resolved under /var/run/netns like `ip netns` does.
+            netns: netns.map(|ns| {
+                if ns.contains('/') {
+                    PathBuf::from(ns)
+                } else {
+                    PathBuf::from(NETNS_DIR).join(ns)
+                }
+            }),
+            port_id,
+            raw_dump,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Output {
     #[default]
     Table,
     Json,
+    // The format of conntrack-tools.
+    Classic,
 }
 
 impl FromStr for Output {
@@ -18,6 +48,7 @@ impl FromStr for Output {
         match s.to_lowercase().as_str() {
             "json" => Ok(Output::Json),
             "table" => Ok(Output::Table),
+            "classic" => Ok(Output::Classic),
             _ => Err(Error::InvalidValue(s.to_string())),
         }
     }
@@ -55,12 +86,94 @@ impl From<Table> for conntrack::Table {
     }
 }
 
+// Where entries are read from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    #[default]
+    Netlink,
+    // /proc/net/nf_conntrack. It is read-only and has only the conntrack table.
+    Proc,
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "netlink" => Ok(Source::Netlink),
+            "proc" => Ok(Source::Proc),
+            _ => Err(Error::InvalidValue(s.to_string())),
+        }
+    }
+}
+
+// A key to order flows for --sort-by.
+// Counters are ordered from the largest to find top talkers.
+// The timeout is ordered from the smallest to find flows about to expire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SortKey {
+    Tuple,
+    Timeout,
+    Bytes,
+    Packets,
+}
+
+// A flow with a smaller rank comes first.
+pub(crate) type Rank = (u64, (conntrack::flow::Protocol, IpAddr, u16, IpAddr, u16));
+
+impl SortKey {
+    // Ties are broken by the tuple to keep the order deterministic.
+    pub(crate) fn rank(self, flow: &conntrack::flow::Flow) -> Rank {
+        let total = |f: fn(&conntrack::flow::Counter) -> u64| {
+            [&flow.orig_counter, &flow.reply_counter]
+                .into_iter()
+                .flatten()
+                .map(f)
+                .sum::<u64>()
+        };
+        let primary = match self {
+            SortKey::Tuple => 0,
+            SortKey::Timeout => flow.timeout as u64,
+            SortKey::Bytes => u64::MAX - total(|c| c.bytes),
+            SortKey::Packets => u64::MAX - total(|c| c.packets),
+        };
+        (primary, flow.sort_key())
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tuple" => Ok(SortKey::Tuple),
+            "timeout" => Ok(SortKey::Timeout),
+            "bytes" => Ok(SortKey::Bytes),
+            "packets" => Ok(SortKey::Packets),
+            _ => Err(Error::InvalidValue(s.to_string())),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub(crate) enum Family {
     #[default]
     Ipv4,
     Ipv6,
+    // A single AF_UNSPEC dump. The kernel returns both families interleaved.
     Any,
+    // Query ipv4 and ipv6 with separate requests and merge them.
+    Both,
+}
+
+impl Family {
+    // Families to be requested one by one.
+    pub(crate) fn expand(self) -> Vec<Family> {
+        match self {
+            Family::Both => vec![Family::Ipv4, Family::Ipv6],
+            f => vec![f],
+        }
+    }
 }
 
 impl FromStr for Family {
@@ -71,17 +184,39 @@ impl FromStr for Family {
             "ipv4" => Ok(Family::Ipv4),
             "ipv6" => Ok(Family::Ipv6),
             "any" => Ok(Family::Any),
+            "both" => Ok(Family::Both),
             _ => Err(Error::InvalidValue(s.to_string())),
         }
     }
 }
 
+// Parse a family of a command sending a single request. "both" is rejected since only list requests families one by one.
+pub(crate) fn parse_single_family(s: &str) -> Result<Family, String> {
+    match Family::from_str(s).map_err(|e| e.to_string())? {
+        Family::Both => Err(String::from(
+            "\"both\" is available only for list. Use \"any\" to request both families at once",
+        )),
+        f => Ok(f),
+    }
+}
+
 impl From<Family> for conntrack::Family {
     fn from(f: Family) -> Self {
         match f {
             Family::Ipv4 => conntrack::Family::Ipv4,
             Family::Ipv6 => conntrack::Family::Ipv6,
-            Family::Any => conntrack::Family::Unspec,
+            Family::Any | Family::Both => conntrack::Family::Unspec,
+        }
+    }
+}
+
+// Unspec is converted into Any because Both is only a way to request families one by one.
+impl From<conntrack::Family> for Family {
+    fn from(f: conntrack::Family) -> Self {
+        match f {
+            conntrack::Family::Ipv4 => Family::Ipv4,
+            conntrack::Family::Ipv6 => Family::Ipv6,
+            conntrack::Family::Unspec => Family::Any,
         }
     }
 }
@@ -90,8 +225,12 @@ impl From<Family> for conntrack::Family {
 pub(crate) enum Protocol {
     #[default]
     Any,
+    // Same as Any, but also overrides the protocol in a filter file.
+    All,
     Tcp,
     Udp,
+    // Protocol number such as 47(GRE) or 50(ESP).
+    Number(u8),
 }
 
 impl FromStr for Protocol {
@@ -100,29 +239,169 @@ impl FromStr for Protocol {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "any" => Ok(Protocol::Any),
+            "all" => Ok(Protocol::All),
             "tcp" => Ok(Protocol::Tcp),
             "udp" => Ok(Protocol::Udp),
+            _ => s
+                .parse::<u8>()
+                .map(Protocol::Number)
+                .map_err(|_| Error::InvalidValue(s.to_string())),
+        }
+    }
+}
+
+impl Protocol {
+    // None means flows of every protocol.
+    pub(crate) fn flow_protocol(self) -> Option<conntrack::flow::Protocol> {
+        match self {
+            Protocol::Any | Protocol::All => None,
+            Protocol::Tcp => Some(conntrack::flow::Protocol::Tcp),
+            Protocol::Udp => Some(conntrack::flow::Protocol::Udp),
+            // 6 and 17 are converted into Tcp and Udp.
+            Protocol::Number(v) => Some(conntrack::flow::Protocol::from(v)),
+        }
+    }
+
+    // A tuple always needs a protocol, so TCP is used when it is not given.
+    pub(crate) fn tuple_protocol(self) -> conntrack::flow::Protocol {
+        self.flow_protocol()
+            .unwrap_or(conntrack::flow::Protocol::Tcp)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EventType {
+    New,
+    Update,
+    Destroy,
+}
+
+impl FromStr for EventType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "new" => Ok(EventType::New),
+            "update" => Ok(EventType::Update),
+            "destroy" => Ok(EventType::Destroy),
             _ => Err(Error::InvalidValue(s.to_string())),
         }
     }
 }
 
-impl From<Protocol> for conntrack::flow::Protocol {
-    fn from(p: Protocol) -> Self {
-        match p {
-            Protocol::Any | Protocol::Tcp => conntrack::flow::Protocol::Tcp,
-            Protocol::Udp => conntrack::flow::Protocol::Udp,
+impl From<EventType> for conntrack::message::MessageType {
+    fn from(t: EventType) -> Self {
+        match t {
+            EventType::New => conntrack::message::MessageType::New,
+            EventType::Update => conntrack::message::MessageType::Update,
+            EventType::Destroy => conntrack::message::MessageType::Destroy,
+        }
+    }
+}
+
+// A duration given like "30s", "500ms", "5m" or "1h".
+// A bare number is treated as seconds. Zero is rejected as it is neither a valid deadline nor a valid interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Duration(pub(crate) std::time::Duration);
+
+impl FromStr for Duration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let idx = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(idx);
+        let value = value
+            .parse::<u64>()
+            .map_err(|_| Error::InvalidValue(s.to_string()))?;
+        if value == 0 {
+            return Err(Error::InvalidValue(s.to_string()));
+        }
+        match unit {
+            "ms" => Ok(Duration(std::time::Duration::from_millis(value))),
+            "" | "s" => Ok(Duration(std::time::Duration::from_secs(value))),
+            "m" => value
+                .checked_mul(60)
+                .map(|secs| Duration(std::time::Duration::from_secs(secs)))
+                .ok_or(Error::InvalidValue(s.to_string())),
+            "h" => value
+                .checked_mul(60 * 60)
+                .map(|secs| Duration(std::time::Duration::from_secs(secs)))
+                .ok_or(Error::InvalidValue(s.to_string())),
+            _ => Err(Error::InvalidValue(s.to_string())),
+        }
+    }
+}
+
+// An endpoint to forward the output to, given like "tcp://collector:9000" or "unix:///run/collector.sock".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            // The host is resolved when connecting.
+            if addr
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+            {
+                return Ok(Endpoint::Tcp(addr.to_string()));
+            }
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            if !path.is_empty() {
+                return Ok(Endpoint::Unix(PathBuf::from(path)));
+            }
+        }
+        Err(Error::InvalidValue(s.to_string()))
+    }
+}
+
+// A sampling rate given like "1/10" to take every 10th event.
+// A bare number is treated as "1/N".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Sample(pub(crate) u64);
+
+impl FromStr for Sample {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = match s.split_once('/') {
+            Some(("1", n)) => n,
+            Some(_) => return Err(Error::InvalidValue(s.to_string())),
+            None => s,
+        };
+        match n.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Sample(n)),
+            _ => Err(Error::InvalidValue(s.to_string())),
         }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Status {
-    Assured,
+    Expected,
     SeenReply,
+    Assured,
+    Confirmed,
+    SrcNat,
+    DstNat,
+    SeqAdjust,
+    SrcNatDone,
+    DstNatDone,
+    Dying,
     FixedTimeout,
-    Expected,
+    Template,
+    Untracked,
+    Helper,
+    Offload,
+    // Flags given as an integer. A flow must have all of them like comma separated flags.
     Detailed(u16),
+    // Comma separated flags.
+    All(u16),
 }
 
 impl FromStr for Status {
@@ -130,10 +409,24 @@ impl FromStr for Status {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "assured" => Ok(Status::Assured),
+            "expected" => Ok(Status::Expected),
             "seen_reply" => Ok(Status::SeenReply),
+            "assured" => Ok(Status::Assured),
+            "confirmed" => Ok(Status::Confirmed),
+            "snat" => Ok(Status::SrcNat),
+            "dnat" => Ok(Status::DstNat),
+            "seq_adj" => Ok(Status::SeqAdjust),
+            "snat_done" => Ok(Status::SrcNatDone),
+            "dnat_done" => Ok(Status::DstNatDone),
+            "dying" => Ok(Status::Dying),
             "fixed_timeout" => Ok(Status::FixedTimeout),
-            "expected" => Ok(Status::Expected),
+            "template" => Ok(Status::Template),
+            "untracked" => Ok(Status::Untracked),
+            "helper" => Ok(Status::Helper),
+            "offload" => Ok(Status::Offload),
+            _ if s.contains(',') => conntrack::flow::Status::try_from(s)
+                .map(|status| Status::All(u16::from(&status)))
+                .map_err(|_| Error::InvalidValue(s.to_string())),
             _ => s
                 .parse::<u16>()
                 .map(Status::Detailed)
@@ -145,11 +438,22 @@ impl FromStr for Status {
 impl From<Status> for conntrack::flow::Status {
     fn from(s: Status) -> Self {
         match s {
-            Status::Assured => conntrack::flow::Status::assured(),
+            Status::Expected => conntrack::flow::Status::expected(),
             Status::SeenReply => conntrack::flow::Status::seen_reply(),
+            Status::Assured => conntrack::flow::Status::assured(),
+            Status::Confirmed => conntrack::flow::Status::confirmed(),
+            Status::SrcNat => conntrack::flow::Status::src_nat(),
+            Status::DstNat => conntrack::flow::Status::dst_nat(),
+            Status::SeqAdjust => conntrack::flow::Status::seq_adjust(),
+            Status::SrcNatDone => conntrack::flow::Status::src_nat_done(),
+            Status::DstNatDone => conntrack::flow::Status::dst_nat_done(),
+            Status::Dying => conntrack::flow::Status::dying(),
             Status::FixedTimeout => conntrack::flow::Status::fixed_timeout(),
-            Status::Expected => conntrack::flow::Status::expected(),
-            Status::Detailed(v) => conntrack::flow::Status::from(v),
+            Status::Template => conntrack::flow::Status::template(),
+            Status::Untracked => conntrack::flow::Status::untracked(),
+            Status::Helper => conntrack::flow::Status::helper(),
+            Status::Offload => conntrack::flow::Status::offload(),
+            Status::Detailed(v) | Status::All(v) => conntrack::flow::Status::from(v),
         }
     }
 }
@@ -172,3 +476,62 @@ impl From<&Tuple> for conntrack::flow::Tuple {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::{parse_single_family, Duration, Family, Protocol};
+
+    #[rstest(
+        protocol,
+        expected,
+        case("any", None),
+        case("all", None),
+        case("tcp", Some(conntrack::flow::Protocol::Tcp)),
+        case("udp", Some(conntrack::flow::Protocol::Udp)),
+        case("17", Some(conntrack::flow::Protocol::Udp)),
+        case("47", Some(conntrack::flow::Protocol::Other(47)))
+    )]
+    fn test_protocol_flow_protocol(protocol: &str, expected: Option<conntrack::flow::Protocol>) {
+        let protocol = Protocol::from_str(protocol).unwrap();
+        assert_eq!(protocol.flow_protocol(), expected);
+    }
+
+    #[rstest(
+        s,
+        expected,
+        case("500ms", Some(std::time::Duration::from_millis(500))),
+        case("30", Some(std::time::Duration::from_secs(30))),
+        case("5m", Some(std::time::Duration::from_secs(300))),
+        case("1h", Some(std::time::Duration::from_secs(3600))),
+        case("0s", None),
+        case("10d", None),
+        // The number of seconds overflows.
+        case("307445734561825861m", None),
+        case("5124095576030432h", None),
+    )]
+    fn test_duration_from_str(s: &str, expected: Option<std::time::Duration>) {
+        assert_eq!(Duration::from_str(s).ok().map(|d| d.0), expected);
+    }
+
+    #[rstest(
+        s,
+        expected,
+        case("ipv4", Some(conntrack::Family::Ipv4)),
+        case("ipv6", Some(conntrack::Family::Ipv6)),
+        case("any", Some(conntrack::Family::Unspec)),
+        case("both", None),
+        case("ipx", None)
+    )]
+    fn test_parse_single_family(s: &str, expected: Option<conntrack::Family>) {
+        assert_eq!(
+            parse_single_family(s).ok().map(conntrack::Family::from),
+            expected
+        );
+        // Both is still accepted for list.
+        assert!(Family::from_str("both").is_ok());
+    }
+}
@@ -1,13 +1,21 @@
 use async_trait::async_trait;
 use clap::Parser;
 use conntrack::{
+    count::Count,
     event::Event,
     request::{Request, RequestMeta, RequestOperation},
 };
+use display::{
+    count::{CountColumn, CountRow},
+    json::JsonDisplay,
+    table::TableDisplay,
+    Display,
+};
+use futures::TryStreamExt;
 
 use crate::{
     cmd::Runner,
-    config::{Family, Table},
+    config::{Duration, Family, GlobalOption, Output, Table},
     error::Error,
     executor::{Executor, Operation, OperationType},
 };
@@ -15,32 +23,176 @@ use crate::{
 #[derive(Debug, Parser)]
 #[command(about = "Show active tracked entries")]
 pub struct CountCmd {
+    #[arg(
+        short,
+        long,
+        default_value = "table",
+        help = "Output format (\"table\", \"json\")"
+    )]
+    output: Output,
     #[arg(
         short,
         long,
         default_value = "conntrack",
-        help = "Tables (\"conntrack\")"
+        help = "Tables (\"conntrack\", \"dying\", \"unconfirmed\")"
     )]
     table: Table,
+    #[arg(
+        short,
+        long,
+        help = "Show counts for all tables (\"conntrack\", \"dying\", \"unconfirmed\")"
+    )]
+    all: bool,
+    #[arg(
+        long,
+        conflicts_with = "all",
+        help = "Print the count every interval with the change from the previous count until Ctrl-C. e.g. \"1s\", \"500ms\". It must not be zero"
+    )]
+    interval: Option<Duration>,
+    #[arg(long, help = "Don't print the header")]
+    no_header: bool,
 }
 
 #[async_trait]
 impl Runner for CountCmd {
-    async fn run(&self) -> Result<(), Error> {
-        let op = CountOperation::new(self.table);
-        let executor = Executor::new(op);
-        let mut ct = executor.exec().await?;
-
-        for event in ct.recv_once().await.map_err(Error::Conntrack)?.iter() {
-            if let Event::Count(c) = event {
-                println!("{c}")
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        if let Some(interval) = self.interval {
+            return self.watch(interval.0, opt).await;
+        }
+        if !self.all {
+            let c = Count::new(count(self.table, opt).await?);
+            return match self.output {
+                // The classic format is only for flows.
+                Output::Table | Output::Classic => {
+                    println!("{}", c.count);
+                    Ok(())
+                }
+                Output::Json => {
+                    let json_display = JsonDisplay::new(tokio::io::stdout());
+                    self.process(&[c], json_display).await
+                }
+            };
+        }
+
+        let mut counts = Vec::new();
+        for table in [Table::Conntrack, Table::Dying, Table::Unconfirmed] {
+            let c = count(table, opt).await?;
+            counts.push(Count::with_table(table.into(), c));
+        }
+        match self.output {
+            // The classic format is only for flows.
+            Output::Table | Output::Classic => {
+                let table_display = TableDisplay::new(tokio::io::stdout(), CountRow::new());
+                self.process(&counts, table_display).await
+            }
+            Output::Json => {
+                let json_display = JsonDisplay::new(tokio::io::stdout());
+                self.process(&counts, json_display).await
+            }
+        }
+    }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
+    }
+}
+
+impl CountCmd {
+    // Each count is a fresh request, so the socket is recreated every tick.
+    async fn watch(&self, interval: std::time::Duration, opt: &GlobalOption) -> Result<(), Error> {
+        match self.output {
+            // The classic format is only for flows.
+            Output::Table | Output::Classic => {
+                let row = CountRow::new().delta(true);
+                self.watch_with(interval, opt, TableDisplay::new(tokio::io::stdout(), row))
+                    .await
+            }
+            Output::Json => {
+                self.watch_with(interval, opt, JsonDisplay::new(tokio::io::stdout()))
+                    .await
+            }
+        }
+    }
+
+    async fn watch_with<D: Display + Send + Sync>(
+        &self,
+        interval: std::time::Duration,
+        opt: &GlobalOption,
+        mut display: D,
+    ) -> Result<(), Error> {
+        if self.output.ne(&Output::Json) && !self.no_header {
+            display.header().await.map_err(Error::Display)?;
+        }
+        let mut ticker = tokio::time::interval(interval);
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+        let mut prev = None;
+        loop {
+            tokio::select! {
+                e = &mut ctrl_c => {
+                    if let Err(e) = e {
+                        eprintln!("failed to receive ctrl-c: {}", e);
+                    }
+                    return Ok(());
+                },
+                _ = ticker.tick() => {
+                    let c = count(self.table, opt).await?;
+                    display
+                        .consume::<CountColumn, Count>(&Count::with_table(self.table.into(), c).delta(prev))
+                        .await
+                        .map_err(Error::Display)?;
+                    display.flush().await.map_err(Error::Display)?;
+                    prev = Some(c);
+                }
             }
         }
+    }
 
+    async fn process<D: Display + Send + Sync>(
+        &self,
+        counts: &[Count],
+        mut display: D,
+    ) -> Result<(), Error> {
+        if self.output.ne(&Output::Json) && !self.no_header {
+            display.header().await.map_err(Error::Display)?;
+        }
+        for c in counts.iter() {
+            display
+                .consume::<CountColumn, Count>(c)
+                .await
+                .map_err(Error::Display)?;
+        }
+        display.flush().await.map_err(Error::Display)?;
         Ok(())
     }
 }
 
+pub(crate) async fn count(table: Table, opt: &GlobalOption) -> Result<u32, Error> {
+    match table {
+        Table::Conntrack => {
+            let executor = Executor::new(CountOperation::new(table), opt);
+            let mut ct = executor.conntrack()?;
+            ct.count(Family::Any.into(), table.into())
+                .await
+                .map_err(Error::Conntrack)
+        }
+        Table::Dying | Table::Unconfirmed => {
+            // The global counter message only supports the conntrack table.
+            // So, dump the table and count its entries.
+            let executor = Executor::new(DumpOperation::new(table), opt);
+            let mut ct = executor.exec().await?;
+            let mut c = 0;
+            while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
+                c += events
+                    .iter()
+                    .filter(|event| matches!(event, Event::Flow(_)))
+                    .count() as u32;
+            }
+            Ok(c)
+        }
+    }
+}
+
 #[derive(Debug)]
 struct CountOperation {
     table: Table,
@@ -64,3 +216,27 @@ impl CountOperation {
         CountOperation { table }
     }
 }
+
+#[derive(Debug)]
+pub(crate) struct DumpOperation {
+    table: Table,
+}
+
+impl Operation for DumpOperation {
+    fn request(&self) -> Result<Request, Error> {
+        let meta = RequestMeta::default()
+            .family(Family::Any.into())
+            .table(self.table.into());
+        Ok(Request::new(meta, RequestOperation::List(None)))
+    }
+
+    fn typ(&self) -> OperationType {
+        OperationType::List
+    }
+}
+
+impl DumpOperation {
+    pub(crate) fn new(table: Table) -> DumpOperation {
+        DumpOperation { table }
+    }
+}
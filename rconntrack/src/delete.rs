@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use clap::Parser;
+use conntrack::{
+    event::Event,
+    flow::Flow,
+    request::{DeleteParams, Direction, Request, RequestMeta, RequestOperation},
+};
+use futures::TryStreamExt;
+
+use crate::{
+    cmd::Runner,
+    config::{GlobalOption, Table},
+    count::DumpOperation,
+    error::Error,
+    executor::{Executor, Operation, OperationType},
+};
+
+#[derive(Debug, Parser)]
+#[command(about = "Delete a connection tracking entry")]
+pub struct DeleteCmd {
+    #[arg(
+        long,
+        help = "Id of the entry. It is shown by --show-id of list command"
+    )]
+    id: u32,
+}
+
+#[async_trait]
+impl Runner for DeleteCmd {
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        // The kernel ignores the id without the tuple and flushes the whole table.
+        // So, look up the entry first and send its tuple together with the id.
+        let flow = self.lookup(opt).await?;
+
+        let executor = Executor::new(DeleteOperation::new(flow, self.id), opt);
+        let mut ct = executor.exec().await?;
+        ct.recv_once().await.map_err(Error::Conntrack)?;
+
+        eprintln!("1 flow entries have been deleted.");
+        Ok(())
+    }
+}
+
+impl DeleteCmd {
+    async fn lookup(&self, opt: &GlobalOption) -> Result<Flow, Error> {
+        let executor = Executor::new(DumpOperation::new(Table::Conntrack), opt);
+        let mut ct = executor.exec().await?;
+        while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
+            for event in events.into_iter() {
+                if let Event::Flow(flow) = event {
+                    if flow.id == Some(self.id) {
+                        return Ok(flow);
+                    }
+                }
+            }
+        }
+        Err(Error::EntryNotFound(self.id))
+    }
+}
+
+#[derive(Debug)]
+struct DeleteOperation {
+    flow: Flow,
+    id: u32,
+}
+
+impl DeleteOperation {
+    fn new(flow: Flow, id: u32) -> DeleteOperation {
+        DeleteOperation { flow, id }
+    }
+}
+
+impl Operation for DeleteOperation {
+    fn request(&self) -> Result<Request, Error> {
+        let family = conntrack::Family::from_addr(self.flow.original.src_addr);
+        let meta = RequestMeta::default().family(family);
+        let params = DeleteParams::new(
+            self.flow.protocol,
+            Direction::Orig(self.flow.original.clone()),
+        )
+        .id(self.id);
+        Ok(Request::new(meta, RequestOperation::Delete(params)))
+    }
+
+    fn typ(&self) -> OperationType {
+        OperationType::Delete
+    }
+}
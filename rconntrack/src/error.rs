@@ -1,3 +1,7 @@
+use std::io::ErrorKind;
+
+use conntrack::error::NetlinkError;
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::get::ValidationError;
@@ -10,6 +14,71 @@ pub(super) enum Error {
     Display(display::error::Error),
     #[error("failed to parse IP address or CIDR: {0}")]
     FailedToParseAddrOrCIDR(String),
+    #[error("entry not found: id {0}")]
+    EntryNotFound(u32),
+    #[error("address family mismatch: {0}")]
+    FamilyMismatch(String),
+    #[error("not an exact address: {0}")]
+    NotExactAddr(String),
+    #[error("invalid filter file: {0}")]
+    InvalidFilterFile(String),
+    #[error("not supported with --source proc: {0}")]
+    ProcSource(String),
+    #[error("supported only with --output table: {0}")]
+    TableOutput(String),
+    #[error("failed to replay flows: {0}")]
+    Replay(String),
+    #[error("failed to connect to the forward endpoint: {0}")]
+    Forward(std::io::Error),
     #[error("validation error: {0}")]
     Validation(ValidationError),
+    #[error("failed to read sysctl: {0}")]
+    Sysctl(String),
+    // Returned by healthcheck to exit with 1 when the table is close to full.
+    #[error("unhealthy: {0}")]
+    Unhealthy(String),
+    // Returned only with --status-code to exit with 1 when nothing matched.
+    #[error("no flows matched")]
+    NoMatch,
+}
+
+impl Error {
+    // Guidance for errors which new users often hit.
+    pub(super) fn hint(&self) -> Option<&'static str> {
+        match self {
+            Error::Conntrack(conntrack::error::Error::NetlinkMessage(
+                NetlinkError::OperationNotPermitted,
+            )) => Some(PERMISSION_HINT),
+            Error::Conntrack(
+                conntrack::error::Error::Socket(e)
+                | conntrack::error::Error::Send(e)
+                | conntrack::error::Error::Netns(e),
+            ) if e.kind() == ErrorKind::PermissionDenied => Some(PERMISSION_HINT),
+            Error::Conntrack(conntrack::error::Error::CounterNotAvailable) => Some(COUNTER_HINT),
+            _ => None,
+        }
+    }
+}
+
+// Reported to stderr instead of the plain text when the output is JSON.
+#[derive(Debug, Serialize)]
+struct JsonError<'a> {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'a str>,
 }
+
+impl Error {
+    pub(super) fn to_json(&self) -> String {
+        let e = JsonError {
+            error: self.to_string(),
+            hint: self.hint(),
+        };
+        // Serializing a struct of strings never fails.
+        serde_json::to_string(&e).unwrap_or_default()
+    }
+}
+
+const PERMISSION_HINT: &str = "conntrack access requires root or CAP_NET_ADMIN; try sudo";
+
+const COUNTER_HINT: &str = "the kernel may be built without conntrack statistics";
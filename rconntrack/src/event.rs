@@ -1,28 +1,44 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
 use async_trait::async_trait;
+use chrono::{DateTime, Local, SecondsFormat};
 use clap::Parser;
 use conntrack::{
     event::Event,
-    flow::{Flow, TcpState},
-    request::{Request, RequestMeta, RequestOperation},
+    flow::{Flow, TcpState, Tuple},
+    message::{MessageGroup, MessageType},
+    request::{ProtoState, Request, RequestMeta, RequestOperation},
     socket::NfConntrackSocket,
     Conntrack,
 };
 use display::{
-    flow::{EventFlowRow, FlowColumn},
+    flow::{ClassicRow, EventFlow, EventFlowRow, FlowColumn, Rate},
     json::JsonDisplay,
     table::TableDisplay,
     Display,
 };
 use futures::TryStreamExt;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
 
 use crate::{
-    cmd::{DisplayRunner, Runner},
-    config::{Family, Output, Protocol, Status, Table},
+    cmd::{terminal_width, DisplayRunner, Runner},
+    config::{
+        parse_single_family, Duration, Endpoint, EventType, Family, GlobalOption, Output, Protocol,
+        Sample, Status, Table,
+    },
     error::Error,
     executor::{Executor, Operation, OperationType},
-    filter::Filter,
+    filter::{Filter, FilterFile},
+    forward::Forwarder,
 };
 
+const DROPPED_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
 #[derive(Debug, Parser)]
 #[command(about = "Poll and show connection tracking events")]
 pub struct EventCmd {
@@ -30,7 +46,7 @@ pub struct EventCmd {
         short,
         long,
         default_value = "table",
-        help = "Output format (\"table\", \"json\")"
+        help = "Output format (\"table\", \"json\", \"classic\"). \"classic\" is the format of conntrack-tools"
     )]
     output: Output,
     #[arg(
@@ -44,6 +60,7 @@ pub struct EventCmd {
         short,
         long,
         default_value = "ipv4",
+        value_parser = parse_single_family,
         help = "L3 layer protocol (\"ipv4\", \"ipv6\", \"any\")"
     )]
     family: Family,
@@ -51,7 +68,7 @@ pub struct EventCmd {
         short,
         long,
         default_value = "any",
-        help = "L4 layer protocol (\"any\", \"tcp\", \"udp\")"
+        help = "L4 layer protocol (\"any\", \"all\", \"tcp\", \"udp\" or protocol number e.g. 47). \"all\" also ignores the protocol in the filter file"
     )]
     protocol: Protocol,
     #[arg(
@@ -74,18 +91,50 @@ pub struct EventCmd {
         help = "Filter for destination address from reply direction. Accept IP address format or with prefix. e.g. \"192.168.0.1\" or \"192.168.0.0/24\""
     )]
     reply_dst_addr: Option<String>,
-    #[arg(long, help = "Filter for source port from original direction.")]
-    orig_src_port: Option<u16>,
-    #[arg(long, help = "Filter for destination port from original direction.")]
-    orig_dst_port: Option<u16>,
-    #[arg(long, help = "Filter for source port from reply direction.")]
-    reply_src_port: Option<u16>,
-    #[arg(long, help = "Filter for destination port from reply direction.")]
-    reply_dst_port: Option<u16>,
+    #[arg(
+        long,
+        help = "Address filters must be a single address. Networks like \"10.0.0.0/8\" are rejected to prevent accidental broad matches"
+    )]
+    exact_addr: bool,
+    #[arg(
+        long,
+        help = "Filter for source port from original direction. Repeat it to match any of the ports."
+    )]
+    orig_src_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for destination port from original direction. Repeat it to match any of the ports."
+    )]
+    orig_dst_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for source port from reply direction. Repeat it to match any of the ports."
+    )]
+    reply_src_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for destination port from reply direction. Repeat it to match any of the ports."
+    )]
+    reply_dst_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for source port from either direction. Repeat it to match any of the ports."
+    )]
+    src_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for destination port from either direction. Repeat it to match any of the ports."
+    )]
+    dst_port: Vec<u16>,
     #[arg(long, help = "Filter for mark")]
     mark: Option<u32>,
     #[arg(long, help = "Filter for use")]
     r#use: Option<u32>,
+    #[arg(
+        long,
+        help = "Filter for the id of ICMP and ICMPv6 flows. Flows of other protocols never match"
+    )]
+    icmp_id: Option<u16>,
     #[arg(
         long,
         help = "Filter for tcp state. (\"none\", \"syn_sent\", \"syn_recv\", \"established\", \"fin_wait\", \"close_wait\", \"last_ack\", \"time_wait\", \"close\", \"listen\")"
@@ -93,22 +142,207 @@ pub struct EventCmd {
     tcp_state: Option<TcpState>,
     #[arg(
         long,
-        help = "Filter for status flags. (\"assured\", \"seen_reply\", \"fixed_timeout\", \"expected\" or u16 integer (When specifying the integer value, you should use with --detailed-status flag.))"
+        help = "Filter for the state of the flow's own protocol. TCP flows are matched with tcp states and SCTP flows with sctp states (\"none\", \"closed\", \"cookie_wait\", \"cookie_echoed\", \"established\", \"shutdown_sent\", \"shutdown_recd\", \"shutdown_ack_sent\", \"heartbeat_sent\")"
+    )]
+    state: Option<ProtoState>,
+    #[arg(
+        long,
+        help = "Filter for status flags. (\"expected\", \"seen_reply\", \"assured\", \"confirmed\", \"snat\", \"dnat\", \"seq_adj\", \"snat_done\", \"dnat_done\", \"dying\", \"fixed_timeout\", \"template\", \"untracked\", \"helper\", \"offload\" or u16 integer (When specifying the integer value, you should use with --detailed-status flag.)). Flows must have all the given flags, either comma separated like \"assured,seen_reply\" or set in the integer"
     )]
     status: Option<Status>,
+    #[arg(
+        long,
+        help = "Filter for flows which have seen traffic in both directions (SEEN_REPLY). Unlike \"assured\", a single reply packet is enough. Combined with --status, both must match"
+    )]
+    bidirectional: bool,
+    #[arg(
+        long,
+        help = "Filter for related flows created by a helper such as FTP data or SIP RTP (EXPECTED)"
+    )]
+    related_only: bool,
+    #[arg(
+        long,
+        help = "Exclude IPv6 flows with link-local (fe80::/10) or multicast (ff00::/8) addresses"
+    )]
+    no_link_local: bool,
+    #[arg(
+        long = "self",
+        help = "Filter for flows whose original source and destination addresses are the same"
+    )]
+    self_connection: bool,
+    #[arg(
+        long,
+        help = "Exclude half-created flows whose reply tuple has zeroed addresses or ports"
+    )]
+    no_orphan_reply: bool,
+    #[arg(
+        long,
+        conflicts_with = "reply_dst_private",
+        help = "Filter for flows whose reply destination is not in the private ranges (10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16 and fc00::/7)"
+    )]
+    reply_dst_public: bool,
+    #[arg(
+        long,
+        help = "Filter for flows whose reply destination is in the private ranges (10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16 and fc00::/7)"
+    )]
+    reply_dst_private: bool,
+    #[arg(
+        long,
+        help = "Filter for flows whose remaining timeout is less than the given value in seconds. The value itself is excluded"
+    )]
+    timeout_lt: Option<u32>,
+    #[arg(
+        long,
+        help = "Filter for flows whose remaining timeout is greater than the given value in seconds. The value itself is excluded"
+    )]
+    timeout_gt: Option<u32>,
+    #[arg(
+        long,
+        help = "Load filters from a TOML file. Flags given on the command line override values in the file."
+    )]
+    filter_file: Option<PathBuf>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Show only given event types. Accept comma separated values. (\"new\", \"update\", \"destroy\")"
+    )]
+    only: Vec<EventType>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Subscribe only given event types in the kernel. Accept comma separated values. (\"new\", \"update\", \"destroy\")"
+    )]
+    events: Vec<EventType>,
+    #[arg(
+        long,
+        help = "Stop capturing events after the given duration. e.g. \"30s\", \"5m\""
+    )]
+    duration: Option<Duration>,
+    #[arg(
+        long,
+        help = "Send the output to a collector instead of stdout. Reconnect when the connection is lost. e.g. \"tcp://collector:9000\", \"unix:///run/collector.sock\""
+    )]
+    forward: Option<Endpoint>,
+    #[arg(
+        long,
+        default_value = "1024",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of events buffered while the output is slow. Events are dropped when the buffer is full"
+    )]
+    event_buffer: u32,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Stop capturing events after the given number of events are shown"
+    )]
+    max_events: Option<u64>,
+    #[arg(long, help = "Show only every Nth matching event. e.g. \"1/10\"")]
+    sample: Option<Sample>,
+    #[arg(
+        long,
+        help = "Show the time when each event is received in RFC3339 format"
+    )]
+    timestamps: bool,
+    #[arg(
+        long,
+        help = "Show bytes and packets per second of each flow computed from counters between events. Accounting must be enabled (net.netfilter.nf_conntrack_acct=1)"
+    )]
+    rates: bool,
     #[arg(
         long,
         help = "Show detailed status flags. Flags are shown binary format."
     )]
     detailed_status: bool,
+    #[arg(
+        long,
+        conflicts_with = "detailed_status",
+        help = "Show all status flags by name like \"ASSURED,CONFIRMED,SNAT\" instead of the preferred one"
+    )]
+    status_names: bool,
+    #[arg(long, help = "Show the security context column")]
+    secctx: bool,
+    #[arg(
+        long,
+        help = "Show the id column which the kernel assigns to each entry"
+    )]
+    show_id: bool,
+    #[arg(
+        long,
+        help = "Keep unrecognized netlink attributes and dump them as hex in JSON output"
+    )]
+    raw: bool,
+    #[arg(
+        long,
+        help = "Use flat keys like \"orig_src_addr\" which line up with the table columns in JSON output"
+    )]
+    json_flat: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Show only given keys in JSON output. Accept comma separated values. e.g. \"orig_src_addr,orig_dst_port\". Unknown keys are rejected"
+    )]
+    fields: Vec<String>,
+    #[arg(
+        long,
+        help = "Show full addresses. By default, addresses are truncated to fit the terminal width"
+    )]
+    wide: bool,
     #[arg(long, help = "Don't print the header")]
     no_header: bool,
 }
 
+impl EventCmd {
+    async fn run_with<W: AsyncWriteExt + Unpin + Send + Sync>(
+        &self,
+        ct: Conntrack<NfConntrackSocket>,
+        writer: W,
+    ) -> Result<(), Error> {
+        match self.output() {
+            Output::Table => {
+                let event_flow_row = EventFlowRow::new(
+                    self.detailed_status,
+                    self.family.into(),
+                    self.protocol.flow_protocol(),
+                )
+                .status_names(self.status_names)
+                .secctx(self.secctx)
+                .show_id(self.show_id)
+                .max_width(if self.forward.is_some() {
+                    None
+                } else {
+                    terminal_width(self.wide)
+                })
+                .timestamps(self.timestamps)
+                .rates(self.rates);
+                let table_display = TableDisplay::new(writer, event_flow_row);
+                self.process(ct, table_display).await?;
+            }
+            Output::Classic => {
+                let table_display = TableDisplay::new(writer, ClassicRow::new(true));
+                self.process(ct, table_display).await?;
+            }
+            Output::Json => {
+                let json_display = JsonDisplay::new(writer).fields(self.fields.clone());
+                self.process(ct, json_display).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_shown(&self, flow: &Flow) -> bool {
+        // When --only is not given, all event types are shown.
+        self.only.is_empty()
+            || self
+                .only
+                .iter()
+                .any(|t| flow.event_type.eq(&MessageType::from(*t)))
+    }
+}
+
 #[async_trait]
 impl Runner for EventCmd {
-    async fn run(&self) -> Result<(), Error> {
-        let filter = Filter::new(
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        let mut filter = Filter::new(
             self.table,
             self.family,
             false, // --zero flag is not allowed for Event command.
@@ -117,35 +351,62 @@ impl Runner for EventCmd {
             self.orig_dst_addr.clone(),
             self.reply_src_addr.clone(),
             self.reply_dst_addr.clone(),
-            self.orig_src_port,
-            self.orig_dst_port,
-            self.reply_src_port,
-            self.reply_dst_port,
+            self.orig_src_port.clone(),
+            self.orig_dst_port.clone(),
+            self.reply_src_port.clone(),
+            self.reply_dst_port.clone(),
+            self.src_port.clone(),
+            self.dst_port.clone(),
             self.mark,
             self.r#use,
+            self.icmp_id,
             self.tcp_state,
             self.status,
+            self.bidirectional,
+            self.timeout_lt,
+            self.timeout_gt,
+            None, // --src-nat flag is supported only for List command.
+            None, // --dst-nat flag is supported only for List command.
+            self.exact_addr,
+            self.related_only,
+            self.no_link_local,
+            self.self_connection,
+            self.no_orphan_reply,
+            self.reply_dst_public,
+            self.reply_dst_private,
+            self.state,
+            false, // --confirmed-only flag is supported only for List command.
+            false, // --unconfirmed-only flag is supported only for List command.
+            false, // --assured-only flag is supported only for List command.
+            false, // --unassured-only flag is supported only for List command.
         );
-        let op = EventOperation::new(filter);
-        let executor = Executor::new(op);
-        let ct = executor.exec().await?;
-
-        match self.output() {
-            Output::Table => {
-                let event_flow_row = EventFlowRow::new(
-                    self.detailed_status,
-                    self.family.into(),
-                    self.protocol.into(),
-                );
-                let table_display = TableDisplay::new(tokio::io::stdout(), event_flow_row);
-                self.process(ct, table_display).await
+        if let Some(path) = &self.filter_file {
+            filter = filter.merge(FilterFile::load(path)?)?;
+        }
+        // When --events is not given, all event types are subscribed.
+        let group = if self.events.is_empty() {
+            MessageGroup::all()
+        } else {
+            self.events.iter().map(|t| MessageType::from(*t)).collect()
+        };
+        let op = EventOperation::new(filter, self.raw, group);
+        let executor = Executor::new(op, opt);
+        match &self.forward {
+            Some(endpoint) => {
+                let forwarder = Forwarder::connect(endpoint.clone()).await?;
+                let ct = executor.exec().await?;
+                self.run_with(ct, forwarder).await
             }
-            Output::Json => {
-                let json_display = JsonDisplay::new(tokio::io::stdout());
-                self.process(ct, json_display).await
+            None => {
+                let ct = executor.exec().await?;
+                self.run_with(ct, tokio::io::stdout()).await
             }
         }
     }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
+    }
 }
 
 #[async_trait]
@@ -154,10 +415,19 @@ impl DisplayRunner for EventCmd {
         &self,
         mut ct: Conntrack<NfConntrackSocket>,
         mut display: D,
-    ) -> Result<(), Error> {
+    ) -> Result<usize, Error> {
         if self.output().ne(&Output::Json) && !self.no_header() {
             display.header().await.map_err(Error::Display)?;
         }
+        let mut count = 0;
+        // The number of events which pass the filters including ones skipped by --sample.
+        let mut matched = 0;
+        let mut rates = RateTracker::default();
+        // Events are dropped instead of blocking the socket when the display can't keep up.
+        // Otherwise the socket buffer overflows and the kernel drops events silently.
+        let dropped = AtomicU64::new(0);
+        let deadline = tokio::time::sleep(self.duration.map(|d| d.0).unwrap_or_default());
+        tokio::pin!(deadline);
         loop {
             tokio::select! {
                 e = tokio::signal::ctrl_c() => {
@@ -167,20 +437,98 @@ impl DisplayRunner for EventCmd {
                     break;
                 },
                 res = async {
-                    while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
-                        for event in events.iter() {
-                            if let Event::Flow(flow) = event {
-                                display.consume::<FlowColumn, Flow>(flow).await.map_err(Error::Display)?;
+                    let (tx, mut rx) = mpsc::channel::<(Flow, DateTime<Local>, Instant)>(self.event_buffer as usize);
+                    let receive = async {
+                        // The channel is closed when all events are received.
+                        let tx = tx;
+                        while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
+                            for event in events.into_iter() {
+                                if let Event::Flow(flow) = event {
+                                    if !self.is_shown(&flow) {
+                                        continue;
+                                    }
+                                    if tx.try_send((flow, Local::now(), Instant::now())).is_err() {
+                                        dropped.fetch_add(1, Ordering::Relaxed);
+                                    }
+                                }
                             }
                         }
+                        Ok::<(), Error>(())
+                    };
+                    let show = async {
+                        let mut interval = tokio::time::interval(DROPPED_REPORT_INTERVAL);
+                        let mut reported = 0;
+                        loop {
+                            tokio::select! {
+                                received = rx.recv() => {
+                                    let Some((flow, at, instant)) = received else {
+                                        break;
+                                    };
+                                    matched += 1;
+                                    if self.sample.is_some_and(|s| (matched - 1) % s.0 != 0) {
+                                        continue;
+                                    }
+                                    let mut entry = EventFlow::new(&flow).flat(self.json_flat);
+                                    if self.timestamps {
+                                        entry.timestamp = Some(at.to_rfc3339_opts(SecondsFormat::Millis, false));
+                                    }
+                                    if self.rates {
+                                        entry.rate = Some(rates.observe(&flow, instant));
+                                    }
+                                    display.consume::<FlowColumn, EventFlow>(&entry).await.map_err(Error::Display)?;
+                                    count += 1;
+                                    if self.max_events.is_some_and(|m| count >= m) {
+                                        break;
+                                    }
+                                    // Show events as soon as they arrive.
+                                    if rx.is_empty() {
+                                        display.flush().await.map_err(Error::Display)?;
+                                    }
+                                },
+                                _ = interval.tick() => {
+                                    let d = dropped.load(Ordering::Relaxed);
+                                    if d > reported {
+                                        eprintln!("dropped {} events", d - reported);
+                                        reported = d;
+                                    }
+                                },
+                            }
+                        }
+                        Ok::<(), Error>(())
+                    };
+                    tokio::pin!(show);
+                    tokio::select! {
+                        // Drain buffered events after the socket is closed.
+                        res = receive => {
+                            res?;
+                            show.await
+                        },
+                        // Stop receiving when --max-events is reached.
+                        res = &mut show => res,
+                        // Stop receiving at the deadline of --duration and show events already buffered.
+                        _ = &mut deadline, if self.duration.is_some() => show.await,
                     }
-                    Ok::<(), Error>(())
                 } => {
-                    res?
+                    res?;
+                    if self.max_events.is_some_and(|m| count >= m)
+                        || (self.duration.is_some() && deadline.is_elapsed())
+                    {
+                        break;
+                    }
                 },
             }
         }
-        Ok(())
+        display.flush().await.map_err(Error::Display)?;
+        // Print the summary to stderr not to mix it with the output.
+        // It tells why the capture stopped, so it is printed only when --duration is given.
+        if self.duration.is_some() {
+            eprintln!("{count} events captured");
+        }
+        let dropped = dropped.into_inner();
+        if dropped > 0 {
+            eprintln!("{dropped} events dropped. Increase --event-buffer to keep up");
+        }
+        Ok(count as usize)
     }
 
     fn output(&self) -> Output {
@@ -195,11 +543,13 @@ impl DisplayRunner for EventCmd {
 #[derive(Debug)]
 struct EventOperation {
     filter: Filter,
+    raw: bool,
+    group: MessageGroup,
 }
 
 impl EventOperation {
-    fn new(filter: Filter) -> EventOperation {
-        EventOperation { filter }
+    fn new(filter: Filter, raw: bool, group: MessageGroup) -> EventOperation {
+        EventOperation { filter, raw, group }
     }
 }
 
@@ -217,4 +567,50 @@ impl Operation for EventOperation {
     fn typ(&self) -> OperationType {
         OperationType::Event
     }
+
+    fn keep_raw(&self) -> bool {
+        self.raw
+    }
+
+    fn event_group(&self) -> MessageGroup {
+        self.group
+    }
+}
+
+// Keep the last observed counters of each flow keyed on its original tuple.
+#[derive(Debug, Default)]
+struct RateTracker {
+    last: HashMap<Tuple, (u64, u64, Instant)>,
+}
+
+impl RateTracker {
+    fn observe(&mut self, flow: &Flow, now: Instant) -> Rate {
+        let counters = [flow.orig_counter, flow.reply_counter];
+        if counters.iter().all(|c| c.is_none()) {
+            // Accounting is disabled.
+            return Rate::default();
+        }
+        let bytes = counters.iter().flatten().map(|c| c.bytes).sum();
+        let packets = counters.iter().flatten().map(|c| c.packets).sum();
+
+        let prev = if flow.event_type.eq(&MessageType::Destroy) {
+            self.last.remove(&flow.original)
+        } else {
+            self.last
+                .insert(flow.original.clone(), (bytes, packets, now))
+        };
+        match prev {
+            Some((prev_bytes, prev_packets, prev_time)) => {
+                let elapsed = now.duration_since(prev_time).as_secs_f64();
+                if elapsed == 0.0 {
+                    return Rate::default();
+                }
+                Rate {
+                    bps: Some((bytes.saturating_sub(prev_bytes) as f64 / elapsed) as u64),
+                    pps: Some((packets.saturating_sub(prev_packets) as f64 / elapsed) as u64),
+                }
+            }
+            None => Rate::default(),
+        }
+    }
 }
@@ -1,52 +1,155 @@
 use conntrack::{
-    message::{MessageGroup, MessageType},
+    error::NetlinkError,
+    event::Event,
+    message::MessageGroup,
     request::Request,
-    socket::NfConntrackSocket,
+    socket::{DatagramHook, NfConntrackSocket},
     Conntrack, ConntrackOption,
 };
 
-use crate::error::Error;
+use futures::TryStreamExt;
+
+use crate::{config::GlobalOption, error::Error};
 
 pub(crate) trait Operation {
     fn request(&self) -> Result<Request, Error>;
     fn typ(&self) -> OperationType;
+
+    // Requests tried in order by Executor::exec_first().
+    fn requests(&self) -> Result<Vec<Request>, Error> {
+        Ok(vec![self.request()?])
+    }
+
+    // Whether to keep unrecognized attributes of flows.
+    fn keep_raw(&self) -> bool {
+        false
+    }
+
+    // Whether to skip flows which can't be parsed instead of failing.
+    fn skip_incomplete(&self) -> bool {
+        false
+    }
+
+    // Event types to subscribe. This is used only for event operations.
+    fn event_group(&self) -> MessageGroup {
+        MessageGroup::all()
+    }
 }
 
 pub(super) struct Executor<O: Operation> {
     op: O,
+    opt: GlobalOption,
 }
 
 impl<O> Executor<O>
 where
     O: Operation,
 {
-    pub(super) fn new(op: O) -> Executor<O> {
-        Executor { op }
+    pub(super) fn new(op: O, opt: &GlobalOption) -> Executor<O> {
+        Executor {
+            op,
+            opt: opt.clone(),
+        }
     }
 
     pub(super) async fn exec(&self) -> Result<Conntrack<NfConntrackSocket>, Error> {
-        let mut ct = if self.op.typ().eq(&OperationType::Event) {
-            Conntrack::new(
-                ConntrackOption::default().set_flow_event_group(
-                    MessageGroup::default()
-                        .set(MessageType::Update)
-                        .set(MessageType::Destroy),
-                ),
-            )
-            .map_err(Error::Conntrack)?
-        } else {
-            Conntrack::new(ConntrackOption::default()).map_err(Error::Conntrack)?
-        };
+        let mut ct = self.conntrack()?;
         let req = self.op.request()?;
         ct.request(req).await.map_err(Error::Conntrack)?;
         Ok(ct)
     }
+
+    // Send requests one by one and return the first non-empty result.
+    // A request resulting in no entry is not an error as long as another request finds entries.
+    // A dump is read to the end and only the first flow is returned.
+    pub(super) async fn exec_first(&self) -> Result<Vec<Event>, Error> {
+        let mut res = Ok(Vec::new());
+        for req in self.op.requests()? {
+            let mut ct = self.conntrack()?;
+            let events = if req.is_dump() {
+                ct.list_vec(req)
+                    .await
+                    .map(|flows| flows.into_iter().take(1).map(Event::Flow).collect())
+            } else {
+                ct.request(req).await.map_err(Error::Conntrack)?;
+                ct.recv_once().await
+            };
+            match events {
+                Ok(events) if !events.is_empty() => return Ok(events),
+                Ok(events) => res = Ok(events),
+                Err(conntrack::error::Error::NetlinkMessage(NetlinkError::NoEntry)) => {
+                    res = Err(Error::Conntrack(conntrack::error::Error::NetlinkMessage(
+                        NetlinkError::NoEntry,
+                    )))
+                }
+                Err(e) => return Err(Error::Conntrack(e)),
+            }
+        }
+        res
+    }
+
+    // Send requests one by one and merge all results.
+    // Each request is a dump, so all events are read until the end of the dump.
+    pub(super) async fn exec_all(&self) -> Result<Vec<Event>, Error> {
+        let mut res = Vec::new();
+        let mut skipped = 0;
+        for req in self.op.requests()? {
+            let mut ct = self.conntrack()?;
+            ct.request(req).await.map_err(Error::Conntrack)?;
+            while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
+                res.extend(events);
+            }
+            skipped += ct.skipped();
+        }
+        report_skipped(skipped);
+        Ok(res)
+    }
+
+    pub(super) fn conntrack(&self) -> Result<Conntrack<NfConntrackSocket>, Error> {
+        let mut ct_opt = if self.op.typ().eq(&OperationType::Event) {
+            ConntrackOption::default().set_flow_event_group(self.op.event_group())
+        } else {
+            ConntrackOption::default()
+        };
+        if let Some(netns) = &self.opt.netns {
+            ct_opt = ct_opt.set_netns(netns);
+        }
+        if let Some(id) = self.opt.port_id {
+            ct_opt = ct_opt.set_port_id(id);
+        }
+        if self.opt.raw_dump {
+            ct_opt = ct_opt.set_datagram_hook(DatagramHook::new(dump_datagram));
+        }
+        ct_opt = ct_opt
+            .set_keep_raw(self.op.keep_raw())
+            .set_skip_incomplete(self.op.skip_incomplete());
+        Conntrack::new(ct_opt).map_err(Error::Conntrack)
+    }
+}
+
+// Flows skipped by Operation::skip_incomplete are reported to stderr not to mix the notice with the output.
+pub(super) fn report_skipped(skipped: usize) {
+    if skipped > 0 {
+        eprintln!("{skipped} incomplete flows skipped");
+    }
+}
+
+// Received datagrams are dumped to stderr as hex with offsets, 16 bytes per line, before they are parsed.
+fn dump_datagram(data: &[u8]) {
+    let mut dump = format!("# datagram {} bytes\n", data.len());
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        dump += &format!("{:08x}  {}\n", i * 16, hex.join(" "));
+    }
+    eprint!("{dump}");
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum OperationType {
     Get,
     List,
+    Delete,
+    Update,
     Event,
     Counter,
     Stats,
@@ -1,7 +1,15 @@
-use std::{net::IpAddr, str::FromStr};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+};
 
-use conntrack::flow::TcpState;
+use conntrack::{
+    flow::TcpState,
+    request::{FilterSet, ProtoState},
+};
 use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::Deserialize;
 
 use crate::{
     config::{Family, Protocol, Status, Table},
@@ -18,14 +26,34 @@ pub(super) struct Filter {
     pub(super) orig_dst_addr: Option<String>,
     pub(super) reply_src_addr: Option<String>,
     pub(super) reply_dst_addr: Option<String>,
-    pub(super) orig_src_port: Option<u16>,
-    pub(super) orig_dst_port: Option<u16>,
-    pub(super) reply_src_port: Option<u16>,
-    pub(super) reply_dst_port: Option<u16>,
+    pub(super) orig_src_port: Vec<u16>,
+    pub(super) orig_dst_port: Vec<u16>,
+    pub(super) reply_src_port: Vec<u16>,
+    pub(super) reply_dst_port: Vec<u16>,
+    pub(super) src_port: Vec<u16>,
+    pub(super) dst_port: Vec<u16>,
     pub(super) mark: Option<u32>,
     pub(super) r#use: Option<u32>,
+    pub(super) icmp_id: Option<u16>,
     pub(super) tcp_state: Option<TcpState>,
     pub(super) status: Option<Status>,
+    pub(super) bidirectional: bool,
+    pub(super) timeout_lt: Option<u32>,
+    pub(super) timeout_gt: Option<u32>,
+    pub(super) src_nat: Option<Option<String>>,
+    pub(super) dst_nat: Option<Option<String>>,
+    pub(super) exact_addr: bool,
+    pub(super) related_only: bool,
+    pub(super) no_link_local: bool,
+    pub(super) self_connection: bool,
+    pub(super) no_orphan_reply: bool,
+    pub(super) reply_dst_public: bool,
+    pub(super) reply_dst_private: bool,
+    pub(super) state: Option<ProtoState>,
+    pub(super) confirmed_only: bool,
+    pub(super) unconfirmed_only: bool,
+    pub(super) assured_only: bool,
+    pub(super) unassured_only: bool,
 }
 
 impl Filter {
@@ -39,14 +67,34 @@ impl Filter {
         orig_dst_addr: Option<String>,
         reply_src_addr: Option<String>,
         reply_dst_addr: Option<String>,
-        orig_src_port: Option<u16>,
-        orig_dst_port: Option<u16>,
-        reply_src_port: Option<u16>,
-        reply_dst_port: Option<u16>,
+        orig_src_port: Vec<u16>,
+        orig_dst_port: Vec<u16>,
+        reply_src_port: Vec<u16>,
+        reply_dst_port: Vec<u16>,
+        src_port: Vec<u16>,
+        dst_port: Vec<u16>,
         mark: Option<u32>,
         r#use: Option<u32>,
+        icmp_id: Option<u16>,
         tcp_state: Option<TcpState>,
         status: Option<Status>,
+        bidirectional: bool,
+        timeout_lt: Option<u32>,
+        timeout_gt: Option<u32>,
+        src_nat: Option<Option<String>>,
+        dst_nat: Option<Option<String>>,
+        exact_addr: bool,
+        related_only: bool,
+        no_link_local: bool,
+        self_connection: bool,
+        no_orphan_reply: bool,
+        reply_dst_public: bool,
+        reply_dst_private: bool,
+        state: Option<ProtoState>,
+        confirmed_only: bool,
+        unconfirmed_only: bool,
+        assured_only: bool,
+        unassured_only: bool,
     ) -> Filter {
         Filter {
             table,
@@ -61,67 +109,383 @@ impl Filter {
             orig_dst_port,
             reply_src_port,
             reply_dst_port,
+            src_port,
+            dst_port,
             mark,
             r#use,
+            icmp_id,
             tcp_state,
             status,
+            bidirectional,
+            timeout_lt,
+            timeout_gt,
+            src_nat,
+            dst_nat,
+            exact_addr,
+            related_only,
+            no_link_local,
+            self_connection,
+            no_orphan_reply,
+            reply_dst_public,
+            reply_dst_private,
+            state,
+            confirmed_only,
+            unconfirmed_only,
+            assured_only,
+            unassured_only,
         }
     }
 }
 
+// Filter values loaded from a TOML file given by --filter-file.
+// Keys are the same as the field names of Filter like `orig_src_addr = "10.0.0.0/24"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct FilterFile {
+    protocol: Option<String>,
+    orig_src_addr: Option<String>,
+    orig_dst_addr: Option<String>,
+    reply_src_addr: Option<String>,
+    reply_dst_addr: Option<String>,
+    orig_src_port: Option<u16>,
+    orig_dst_port: Option<u16>,
+    reply_src_port: Option<u16>,
+    reply_dst_port: Option<u16>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+    mark: Option<u32>,
+    #[serde(rename = "use")]
+    r#use: Option<u32>,
+    icmp_id: Option<u16>,
+    tcp_state: Option<String>,
+    state: Option<String>,
+    status: Option<String>,
+    bidirectional: Option<bool>,
+    exact_addr: Option<bool>,
+    related_only: Option<bool>,
+    no_link_local: Option<bool>,
+    self_connection: Option<bool>,
+    no_orphan_reply: Option<bool>,
+    reply_dst_public: Option<bool>,
+    reply_dst_private: Option<bool>,
+    confirmed_only: Option<bool>,
+    unconfirmed_only: Option<bool>,
+    assured_only: Option<bool>,
+    unassured_only: Option<bool>,
+    timeout_lt: Option<u32>,
+    timeout_gt: Option<u32>,
+}
+
+impl FilterFile {
+    pub(super) fn load(path: &Path) -> Result<FilterFile, Error> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| Error::InvalidFilterFile(format!("{}: {e}", path.display())))?;
+        toml::from_str(&s).map_err(|e| Error::InvalidFilterFile(format!("{}: {e}", path.display())))
+    }
+}
+
+impl Filter {
+    // Values given by command line flags take precedence over values in the file.
+    // The protocol flag is treated as unspecified when it is "any".
+    // "all" clears the protocol given by the file.
+    pub(super) fn merge(mut self, file: FilterFile) -> Result<Filter, Error> {
+        if self.protocol.eq(&Protocol::Any) {
+            if let Some(p) = file.protocol {
+                self.protocol = Protocol::from_str(&p)
+                    .map_err(|e| Error::InvalidFilterFile(format!("protocol: {e}")))?;
+            }
+        }
+        self.orig_src_addr = self.orig_src_addr.or(file.orig_src_addr);
+        self.orig_dst_addr = self.orig_dst_addr.or(file.orig_dst_addr);
+        self.reply_src_addr = self.reply_src_addr.or(file.reply_src_addr);
+        self.reply_dst_addr = self.reply_dst_addr.or(file.reply_dst_addr);
+        if self.orig_src_port.is_empty() {
+            self.orig_src_port.extend(file.orig_src_port);
+        }
+        if self.orig_dst_port.is_empty() {
+            self.orig_dst_port.extend(file.orig_dst_port);
+        }
+        if self.reply_src_port.is_empty() {
+            self.reply_src_port.extend(file.reply_src_port);
+        }
+        if self.reply_dst_port.is_empty() {
+            self.reply_dst_port.extend(file.reply_dst_port);
+        }
+        if self.src_port.is_empty() {
+            self.src_port.extend(file.src_port);
+        }
+        if self.dst_port.is_empty() {
+            self.dst_port.extend(file.dst_port);
+        }
+        self.mark = self.mark.or(file.mark);
+        self.r#use = self.r#use.or(file.r#use);
+        self.icmp_id = self.icmp_id.or(file.icmp_id);
+        if self.tcp_state.is_none() {
+            if let Some(s) = file.tcp_state {
+                self.tcp_state = Some(
+                    TcpState::from_str(&s)
+                        .map_err(|e| Error::InvalidFilterFile(format!("tcp_state: {e}")))?,
+                );
+            }
+        }
+        if self.state.is_none() {
+            if let Some(s) = file.state {
+                self.state = Some(
+                    ProtoState::from_str(&s)
+                        .map_err(|e| Error::InvalidFilterFile(format!("state: {e}")))?,
+                );
+            }
+        }
+        if self.status.is_none() {
+            if let Some(s) = file.status {
+                self.status = Some(
+                    Status::from_str(&s)
+                        .map_err(|e| Error::InvalidFilterFile(format!("status: {e}")))?,
+                );
+            }
+        }
+        self.bidirectional = self.bidirectional || file.bidirectional.unwrap_or(false);
+        self.exact_addr = self.exact_addr || file.exact_addr.unwrap_or(false);
+        self.related_only = self.related_only || file.related_only.unwrap_or(false);
+        self.no_link_local = self.no_link_local || file.no_link_local.unwrap_or(false);
+        self.self_connection = self.self_connection || file.self_connection.unwrap_or(false);
+        self.no_orphan_reply = self.no_orphan_reply || file.no_orphan_reply.unwrap_or(false);
+        self.reply_dst_public = self.reply_dst_public || file.reply_dst_public.unwrap_or(false);
+        self.reply_dst_private = self.reply_dst_private || file.reply_dst_private.unwrap_or(false);
+        self.confirmed_only = self.confirmed_only || file.confirmed_only.unwrap_or(false);
+        self.unconfirmed_only = self.unconfirmed_only || file.unconfirmed_only.unwrap_or(false);
+        self.assured_only = self.assured_only || file.assured_only.unwrap_or(false);
+        self.unassured_only = self.unassured_only || file.unassured_only.unwrap_or(false);
+        self.timeout_lt = self.timeout_lt.or(file.timeout_lt);
+        self.timeout_gt = self.timeout_gt.or(file.timeout_gt);
+        Ok(self)
+    }
+}
+
 impl TryFrom<&Filter> for conntrack::request::Filter {
     type Error = Error;
 
     fn try_from(f: &Filter) -> Result<Self, Self::Error> {
         let mut filter = conntrack::request::Filter::default().family(f.family.into());
-        if f.protocol.ne(&Protocol::Any) {
-            filter = filter.protocol(f.protocol.into());
+        if let Some(p) = f.protocol.flow_protocol() {
+            filter = filter.protocol(p);
         }
         if let Some(addr) = &f.orig_src_addr {
             let cidr = parse_addr_or_cidr(addr)?;
+            check_family(f.family, "orig-src-addr", &cidr)?;
+            check_exact(f.exact_addr, "orig-src-addr", &cidr)?;
             filter = filter.orig_src_addr(cidr);
         }
         if let Some(addr) = &f.orig_dst_addr {
             let cidr = parse_addr_or_cidr(addr)?;
+            check_family(f.family, "orig-dst-addr", &cidr)?;
+            check_exact(f.exact_addr, "orig-dst-addr", &cidr)?;
             filter = filter.orig_dst_addr(cidr);
         }
         if let Some(addr) = &f.reply_src_addr {
             let cidr = parse_addr_or_cidr(addr)?;
+            check_family(f.family, "reply-src-addr", &cidr)?;
+            check_exact(f.exact_addr, "reply-src-addr", &cidr)?;
             filter = filter.reply_src_addr(cidr);
         }
         if let Some(addr) = &f.reply_dst_addr {
             let cidr = parse_addr_or_cidr(addr)?;
+            check_family(f.family, "reply-dst-addr", &cidr)?;
+            check_exact(f.exact_addr, "reply-dst-addr", &cidr)?;
             filter = filter.reply_dst_addr(cidr);
         }
-        if let Some(port) = f.orig_src_port {
-            filter = filter.orig_src_port(port);
-        }
-        if let Some(port) = f.orig_dst_port {
-            filter = filter.orig_dst_port(port);
-        }
-        if let Some(port) = f.reply_src_port {
-            filter = filter.reply_src_port(port);
-        }
-        if let Some(port) = f.reply_dst_port {
-            filter = filter.reply_dst_port(port);
-        }
+        filter = ports(
+            filter,
+            &f.orig_src_port,
+            conntrack::request::Filter::orig_src_port,
+        );
+        filter = ports(
+            filter,
+            &f.orig_dst_port,
+            conntrack::request::Filter::orig_dst_port,
+        );
+        filter = ports(
+            filter,
+            &f.reply_src_port,
+            conntrack::request::Filter::reply_src_port,
+        );
+        filter = ports(
+            filter,
+            &f.reply_dst_port,
+            conntrack::request::Filter::reply_dst_port,
+        );
+        filter = ports(filter, &f.src_port, conntrack::request::Filter::src_port);
+        filter = ports(filter, &f.dst_port, conntrack::request::Filter::dst_port);
         if let Some(m) = f.mark {
             filter = filter.mark(m);
         }
         if let Some(u) = f.r#use {
             filter = filter.r#use(u);
         }
+        if let Some(id) = f.icmp_id {
+            filter = filter.icmp_id(id);
+        }
         if let Some(s) = f.tcp_state {
             filter = filter.tcp_state(s);
         }
+        if let Some(s) = f.state {
+            filter = filter.proto_state(s);
+        }
+        // A flow must have all flags given by --status, an integer or a comma separated list alike.
+        // Flags required by other options are accumulated into them.
         if let Some(status) = f.status {
-            filter = filter.status(status.into())
+            filter = filter.status_all(status.into());
+        }
+        if f.bidirectional {
+            filter = filter.status_all(conntrack::flow::Status::seen_reply());
+        }
+        if f.related_only {
+            filter = filter.status_all(conntrack::flow::Status::expected());
+        }
+        if f.no_link_local {
+            filter = filter.no_link_local();
+        }
+        if f.self_connection {
+            filter = filter.self_connection();
+        }
+        if f.no_orphan_reply {
+            filter = filter.no_orphan_reply();
+        }
+        match (f.reply_dst_public, f.reply_dst_private) {
+            (true, true) => {
+                return Err(Error::InvalidFilterFile(String::from(
+                    "reply_dst_public and reply_dst_private can't be set together",
+                )))
+            }
+            (true, false) => filter = filter.matches_none(private_ranges()),
+            (false, true) => filter = filter.matches_any(private_ranges()),
+            (false, false) => {}
+        }
+        match (f.confirmed_only, f.unconfirmed_only) {
+            (true, true) => {
+                return Err(Error::InvalidFilterFile(String::from(
+                    "confirmed_only and unconfirmed_only can't be set together",
+                )))
+            }
+            (true, false) => filter = filter.confirmed(true),
+            (false, true) => filter = filter.confirmed(false),
+            (false, false) => {}
+        }
+        match (f.assured_only, f.unassured_only) {
+            (true, true) => {
+                return Err(Error::InvalidFilterFile(String::from(
+                    "assured_only and unassured_only can't be set together",
+                )))
+            }
+            (true, false) => filter = filter.status_all(conntrack::flow::Status::assured()),
+            (false, true) => filter = filter.status_none(conntrack::flow::Status::assured()),
+            (false, false) => {}
+        }
+        // Both ends are exclusive, so a range without any integer between them matches nothing.
+        if let (Some(lt), Some(gt)) = (f.timeout_lt, f.timeout_gt) {
+            if lt <= gt.saturating_add(1) {
+                return Err(Error::InvalidFilterFile(format!(
+                    "no timeout is greater than {gt} and less than {lt}"
+                )));
+            }
+        }
+        if let Some(t) = f.timeout_lt {
+            filter = filter.timeout_lt(t);
+        }
+        if let Some(t) = f.timeout_gt {
+            filter = filter.timeout_gt(t);
+        }
+        // The translated-to address is checked with the reply tuple.
+        if let Some(addr) = &f.src_nat {
+            let cidr = match addr {
+                Some(addr) => {
+                    let cidr = parse_addr_or_cidr(addr)?;
+                    check_family(f.family, "src-nat", &cidr)?;
+                    check_exact(f.exact_addr, "src-nat", &cidr)?;
+                    Some(cidr)
+                }
+                None => None,
+            };
+            filter = filter.src_nat(cidr);
+        }
+        if let Some(addr) = &f.dst_nat {
+            let cidr = match addr {
+                Some(addr) => {
+                    let cidr = parse_addr_or_cidr(addr)?;
+                    check_family(f.family, "dst-nat", &cidr)?;
+                    check_exact(f.exact_addr, "dst-nat", &cidr)?;
+                    Some(cidr)
+                }
+                None => None,
+            };
+            filter = filter.dst_nat(cidr);
         }
 
         Ok(filter)
     }
 }
 
+// Private address ranges of RFC 1918 and unique local addresses of RFC 4193.
+// Addresses out of them are treated as public by --reply-dst-public.
+const PRIVATE_RANGES: [IpNet; 4] = [
+    IpNet::V4(Ipv4Net::new_assert(Ipv4Addr::new(10, 0, 0, 0), 8)),
+    IpNet::V4(Ipv4Net::new_assert(Ipv4Addr::new(172, 16, 0, 0), 12)),
+    IpNet::V4(Ipv4Net::new_assert(Ipv4Addr::new(192, 168, 0, 0), 16)),
+    IpNet::V6(Ipv6Net::new_assert(
+        Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0),
+        7,
+    )),
+];
+
+fn private_ranges() -> FilterSet {
+    FilterSet::new(
+        PRIVATE_RANGES
+            .iter()
+            .map(|cidr| conntrack::request::Filter::default().reply_dst_addr(*cidr))
+            .collect(),
+    )
+}
+
+// A single port is matched directly. Repeated ports are matched as any of them.
+fn ports(
+    filter: conntrack::request::Filter,
+    ports: &[u16],
+    f: fn(conntrack::request::Filter, u16) -> conntrack::request::Filter,
+) -> conntrack::request::Filter {
+    match ports {
+        [] => filter,
+        [port] => f(filter, *port),
+        ports => filter.matches_any(FilterSet::new(
+            ports
+                .iter()
+                .map(|port| f(conntrack::request::Filter::default(), *port))
+                .collect(),
+        )),
+    }
+}
+
+// An address which never matches the selected family is rejected instead of resulting in empty output.
+fn check_family(family: Family, flag: &str, cidr: &IpNet) -> Result<(), Error> {
+    let (expected, other) = match (family, cidr) {
+        (Family::Ipv4, IpNet::V6(_)) => ("ipv4", "ipv6"),
+        (Family::Ipv6, IpNet::V4(_)) => ("ipv6", "ipv4"),
+        _ => return Ok(()),
+    };
+    Err(Error::FamilyMismatch(format!(
+        "--{flag} {cidr} is an {other} address but the family is {expected}. Use --family {other} or --family any"
+    )))
+}
+
+// With --exact-addr, a network is rejected and only a single address is accepted.
+fn check_exact(exact: bool, flag: &str, cidr: &IpNet) -> Result<(), Error> {
+    if exact && cidr.prefix_len() != cidr.max_prefix_len() {
+        return Err(Error::NotExactAddr(format!(
+            "--{flag} {cidr} is a network but --exact-addr is set. Give a single address"
+        )));
+    }
+    Ok(())
+}
+
 fn parse_addr_or_cidr(s: &str) -> Result<IpNet, Error> {
     match IpNet::from_str(s) {
         Ok(cidr) => Ok(cidr),
@@ -145,3 +509,72 @@ fn parse_addr_or_cidr(s: &str) -> Result<IpNet, Error> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use conntrack::{flow::Flow, request::FilterSet};
+    use rstest::rstest;
+
+    use crate::config::{Protocol, Status};
+
+    use super::{Filter, FilterFile};
+
+    const UDP_FLOW: &str = "event=update proto=udp orig=10.0.0.1:1234->10.0.0.2:53 reply=10.0.0.2:53->10.0.0.1:1234 status=SEEN_REPLY timeout=30";
+
+    #[rstest(
+        protocol,
+        expected,
+        case(Protocol::Any, true),
+        case(Protocol::All, true),
+        case(Protocol::Udp, true),
+        case(Protocol::Tcp, false)
+    )]
+    fn test_filter_try_from_protocol(protocol: Protocol, expected: bool) {
+        let flow: Flow = UDP_FLOW.parse().unwrap();
+        let filter = Filter {
+            protocol,
+            ..Default::default()
+        };
+        let filter = conntrack::request::Filter::try_from(&filter).unwrap();
+        assert_eq!(FilterSet::new(vec![filter]).apply(&flow), expected);
+    }
+
+    // "any" takes the protocol in the file while "all" overrides it.
+    #[rstest(
+        protocol,
+        expected,
+        case(Protocol::Any, Protocol::Tcp),
+        case(Protocol::All, Protocol::All),
+        case(Protocol::Udp, Protocol::Udp)
+    )]
+    fn test_filter_merge_protocol(protocol: Protocol, expected: Protocol) {
+        let file: FilterFile = toml::from_str(r#"protocol = "tcp""#).unwrap();
+        let filter = Filter {
+            protocol,
+            ..Default::default()
+        }
+        .merge(file)
+        .unwrap();
+        assert_eq!(filter.protocol, expected);
+    }
+
+    // SEEN_REPLY is 2 and ASSURED is 4.
+    #[rstest(
+        status,
+        expected,
+        case("seen_reply", true),
+        case("assured", false),
+        case("seen_reply,assured", false),
+        case("2", true),
+        case("6", false)
+    )]
+    fn test_filter_try_from_status(status: &str, expected: bool) {
+        let flow: Flow = UDP_FLOW.parse().unwrap();
+        let filter = Filter {
+            status: Some(status.parse::<Status>().unwrap()),
+            ..Default::default()
+        };
+        let filter = conntrack::request::Filter::try_from(&filter).unwrap();
+        assert_eq!(FilterSet::new(vec![filter]).apply(&flow), expected);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncWrite,
+    net::{TcpStream, UnixStream},
+    task::JoinHandle,
+};
+
+use crate::{config::Endpoint, error::Error};
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+// Writes are blocked while this many bytes are waiting to be sent.
+const MAX_PENDING: usize = 64 * 1024;
+
+// A writer sending the output to a collector.
+// Output is sent line by line so that the collector always receives whole records.
+// When the connection is lost, it reconnects in the background and the line being sent is resent from its head.
+pub(crate) struct Forwarder {
+    endpoint: Endpoint,
+    state: State,
+    // Output which is not sent yet. Only complete lines are sent.
+    pending: Vec<u8>,
+    // Bytes of the first line in pending which are already sent to the current connection.
+    sent: usize,
+}
+
+enum State {
+    Connected(Stream),
+    Reconnecting(JoinHandle<Stream>),
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Forwarder {
+    // The first connection must succeed to notice a wrong endpoint early.
+    pub(crate) async fn connect(endpoint: Endpoint) -> Result<Forwarder, Error> {
+        let stream = connect(&endpoint).await.map_err(Error::Forward)?;
+        Ok(Forwarder {
+            endpoint,
+            state: State::Connected(stream),
+            pending: Vec::new(),
+            sent: 0,
+        })
+    }
+
+    fn reconnect(&mut self, e: io::Error) {
+        eprintln!("lost the connection to the forward endpoint: {e}. reconnecting");
+        let endpoint = self.endpoint.clone();
+        self.sent = 0;
+        self.state = State::Reconnecting(tokio::spawn(async move {
+            loop {
+                match connect(&endpoint).await {
+                    Ok(stream) => return stream,
+                    Err(_) => tokio::time::sleep(RECONNECT_INTERVAL).await,
+                }
+            }
+        }));
+    }
+
+    // Send complete lines in pending. It is ready when no complete line is left.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let Some(end) = self.pending.iter().position(|b| *b == b'\n') else {
+                return Poll::Ready(Ok(()));
+            };
+            match &mut self.state {
+                State::Connected(stream) => {
+                    match Pin::new(stream).poll_write(cx, &self.pending[self.sent..=end]) {
+                        Poll::Ready(Ok(0)) => self.reconnect(io::ErrorKind::WriteZero.into()),
+                        Poll::Ready(Ok(n)) => {
+                            self.sent += n;
+                            if self.sent > end {
+                                self.pending.drain(..=end);
+                                self.sent = 0;
+                            }
+                        }
+                        Poll::Ready(Err(e)) => self.reconnect(e),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                State::Reconnecting(handle) => match Pin::new(handle).poll(cx) {
+                    Poll::Ready(Ok(stream)) => self.state = State::Connected(stream),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+    }
+
+    // Poll the connection and call f with it.
+    // A failed operation is retried with a new connection.
+    fn poll_with<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(Pin<&mut Stream>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            match &mut self.state {
+                State::Connected(stream) => match f(Pin::new(stream), cx) {
+                    Poll::Ready(Err(e)) => self.reconnect(e),
+                    res => return res,
+                },
+                State::Reconnecting(handle) => match Pin::new(handle).poll(cx) {
+                    Poll::Ready(Ok(stream)) => self.state = State::Connected(stream),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+    }
+}
+
+async fn connect(endpoint: &Endpoint) -> io::Result<Stream> {
+    match endpoint {
+        Endpoint::Tcp(addr) => TcpStream::connect(addr).await.map(Stream::Tcp),
+        Endpoint::Unix(path) => UnixStream::connect(path).await.map(Stream::Unix),
+    }
+}
+
+impl AsyncWrite for Forwarder {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.pending.len() >= MAX_PENDING {
+            ready!(this.poll_send(cx))?;
+        }
+        this.pending.extend_from_slice(buf);
+        // Lines are sent by later writes or flush when the connection is not writable now.
+        if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        this.poll_with(cx, |stream, cx| stream.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().state {
+            State::Connected(stream) => Pin::new(stream).poll_shutdown(cx),
+            State::Reconnecting(handle) => {
+                handle.abort();
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            Stream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::config::Endpoint;
+
+    use super::Forwarder;
+
+    #[tokio::test]
+    async fn test_forwarder_sends_complete_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+        let (forwarder, accepted) = tokio::join!(Forwarder::connect(endpoint), listener.accept());
+        let mut forwarder = forwarder.unwrap();
+        let (mut peer, _) = accepted.unwrap();
+
+        forwarder.write_all(b"first\nsec").await.unwrap();
+        forwarder.flush().await.unwrap();
+        forwarder.write_all(b"ond\n").await.unwrap();
+        forwarder.flush().await.unwrap();
+        drop(forwarder);
+
+        let mut received = String::new();
+        peer.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, "first\nsecond\n");
+    }
+}
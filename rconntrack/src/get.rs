@@ -1,16 +1,14 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use clap::Parser;
+use clap::{Args, Parser};
 use conntrack::{
     event::Event,
-    flow::{Flow, Tuple},
+    flow::Tuple,
     request::{Direction, GetParams, Request, RequestMeta, RequestOperation},
-    socket::NfConntrackSocket,
-    Conntrack,
 };
 use display::{
-    flow::{FlowColumn, FlowRow},
+    flow::{ClassicRow, FlowColumn, FlowJson, FlowRow},
     json::JsonDisplay,
     table::TableDisplay,
     Display,
@@ -18,10 +16,11 @@ use display::{
 use thiserror::Error;
 
 use crate::{
-    cmd::{DisplayRunner, Runner},
-    config::{Family, Output, Protocol, Table},
+    cmd::{terminal_width, Runner},
+    config::{parse_single_family, Family, GlobalOption, Output, Protocol, Table},
     error::Error,
     executor::{Executor, Operation, OperationType},
+    resolve::resolve_flows,
 };
 
 const PARAMS_FOR_BOTH_MSG: &str = r"When acceptable parameters for the either direction is given, parameters for the another directions are ignored.
@@ -34,7 +33,7 @@ pub struct GetCmd {
         short,
         long,
         default_value = "table",
-        help = "Output format (\"table\", \"json\")"
+        help = "Output format (\"table\", \"json\", \"classic\"). \"classic\" is the format of conntrack-tools"
     )]
     output: Output,
     #[arg(
@@ -44,10 +43,16 @@ pub struct GetCmd {
         help = "Tables (\"conntrack\", \"dying\", \"unconfirmed\")"
     )]
     table: Table,
+    #[arg(
+        long,
+        help = "Search the entry in all tables in order of \"conntrack\", \"dying\" and \"unconfirmed\". --table is ignored"
+    )]
+    all_tables: bool,
     #[arg(
         short,
         long,
         default_value = "ipv4",
+        value_parser = parse_single_family,
         help = "L3 layer protocol (\"ipv4\", \"ipv6\", \"any\")"
     )]
     family: Family,
@@ -55,9 +60,62 @@ pub struct GetCmd {
         short,
         long,
         default_value = "any",
-        help = "L4 layer protocol (\"any\", \"tcp\", \"udp\")"
+        help = "L4 layer protocol (\"any\", \"tcp\", \"udp\" or protocol number e.g. 47)"
     )]
     protocol: Protocol,
+    #[command(flatten)]
+    tuple: TupleArgs,
+    #[arg(
+        long,
+        help = "Show detailed status flags. Flags are shown binary format."
+    )]
+    detailed_status: bool,
+    #[arg(
+        long,
+        conflicts_with = "detailed_status",
+        help = "Show all status flags by name like \"ASSURED,CONFIRMED,SNAT\" instead of the preferred one"
+    )]
+    status_names: bool,
+    #[arg(long, help = "Show the security context column")]
+    secctx: bool,
+    #[arg(
+        long,
+        help = "Show the id column which the kernel assigns to each entry"
+    )]
+    show_id: bool,
+    #[arg(
+        long,
+        help = "Keep unrecognized netlink attributes and dump them as hex in JSON output"
+    )]
+    raw: bool,
+    #[arg(
+        long,
+        help = "Use flat keys like \"orig_src_addr\" which line up with the table columns in JSON output"
+    )]
+    json_flat: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Show only given keys in JSON output. Accept comma separated values. e.g. \"orig_src_addr,orig_dst_port\". Unknown keys are rejected"
+    )]
+    fields: Vec<String>,
+    #[arg(
+        long,
+        help = "Show full addresses. By default, addresses are truncated to fit the terminal width"
+    )]
+    wide: bool,
+    #[arg(
+        long,
+        help = "Show names of addresses looked up by reverse DNS in the table. JSON output keeps addresses"
+    )]
+    resolve: bool,
+    #[arg(long, help = "Don't print the header")]
+    no_header: bool,
+}
+
+// The tuple to find an entry. It is shared by commands which target a single entry.
+#[derive(Debug, Args)]
+pub(crate) struct TupleArgs {
     #[arg(long, help = "Source address from original direction")]
     orig_src_addr: Option<IpAddr>,
     #[arg(long, help = "Destination address from original direction")]
@@ -74,16 +132,9 @@ pub struct GetCmd {
     reply_src_port: Option<u16>,
     #[arg(long, help = "Destination port from reply direction.")]
     reply_dst_port: Option<u16>,
-    #[arg(
-        long,
-        help = "Show detailed status flags. Flags are shown binary format."
-    )]
-    detailed_status: bool,
-    #[arg(long, help = "Don't print the header")]
-    no_header: bool,
 }
 
-impl GetCmd {
+impl TupleArgs {
     fn validate(&self) -> Result<(bool, bool), Error> {
         // 1. When any of orig-xxx flags are specified, all of orig-xxx flags must be specified.
         // 2. When any of reply-xxx flags are specified, all of reply-xxx flags must be specified.
@@ -128,7 +179,7 @@ impl GetCmd {
         Ok((orig_accepted, reply_accepted))
     }
 
-    fn get_tuples(&self) -> Result<Direction, Error> {
+    pub(crate) fn get_tuples(&self) -> Result<Direction, Error> {
         let (orig_accepted, reply_accepted) = self.validate()?;
         // After a validation, all needed field are confirmed not to be None.
         // So, unwrappable.
@@ -154,100 +205,142 @@ impl GetCmd {
 
 #[async_trait]
 impl Runner for GetCmd {
-    async fn run(&self) -> Result<(), Error> {
-        let directed_tuple = self.get_tuples()?;
-        let op = GetOperation::new(self.table, self.family, self.protocol, directed_tuple);
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        let directed_tuple = self.tuple.get_tuples()?;
+        let tables = if self.all_tables {
+            vec![Table::Conntrack, Table::Dying, Table::Unconfirmed]
+        } else {
+            vec![self.table]
+        };
+        let op = GetOperation::new(tables, self.family, self.protocol, directed_tuple, self.raw);
 
-        let executor = Executor::new(op);
-        let ct = executor.exec().await?;
+        let executor = Executor::new(op, opt);
+        let events = executor.exec_first().await?;
         match self.output {
             Output::Table => {
                 let flow_row = FlowRow::new(
                     self.detailed_status,
                     self.family.into(),
-                    self.protocol.into(),
-                );
+                    Some(self.protocol.tuple_protocol()),
+                )
+                .status_names(self.status_names)
+                .secctx(self.secctx)
+                .show_id(self.show_id)
+                .max_width(terminal_width(self.wide));
                 let table_display = TableDisplay::new(tokio::io::stdout(), flow_row);
 
-                self.process(ct, table_display).await
+                self.process(events, table_display).await
+            }
+            Output::Classic => {
+                let table_display = TableDisplay::new(tokio::io::stdout(), ClassicRow::new(false));
+                self.process(events, table_display).await
             }
             Output::Json => {
-                let json_display = JsonDisplay::new(tokio::io::stdout());
-                self.process(ct, json_display).await
+                let json_display =
+                    JsonDisplay::new(tokio::io::stdout()).fields(self.fields.clone());
+                self.process(events, json_display).await
             }
         }
     }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
+    }
 }
 
-#[async_trait]
-impl DisplayRunner for GetCmd {
+impl GetCmd {
+    // The get command may send requests for some tables.
+    // So, it displays received events instead of receiving them from Conntrack.
     async fn process<D: Display + Send + Sync>(
         &self,
-        mut ct: Conntrack<NfConntrackSocket>,
+        events: Vec<Event>,
         mut display: D,
     ) -> Result<(), Error> {
-        if self.output.ne(&Output::Json) && !self.no_header() {
+        if self.output.ne(&Output::Json) && !self.no_header {
             display.header().await.map_err(Error::Display)?;
         }
-        for event in ct.recv_once().await.map_err(Error::Conntrack)?.iter() {
+        let names = if self.resolve && self.output.eq(&Output::Table) {
+            Some(resolve_flows(&events).await)
+        } else {
+            None
+        };
+        for event in events.iter() {
             if let Event::Flow(flow) = event {
                 display
-                    .consume::<FlowColumn, Flow>(flow)
+                    .consume::<FlowColumn, FlowJson>(
+                        &FlowJson::new(flow)
+                            .flat(self.json_flat)
+                            .names(names.as_ref()),
+                    )
                     .await
                     .map_err(Error::Display)?;
             }
         }
+        display.flush().await.map_err(Error::Display)?;
         Ok(())
     }
-
-    fn output(&self) -> Output {
-        self.output
-    }
-
-    fn no_header(&self) -> bool {
-        self.no_header
-    }
 }
 
 #[derive(Debug)]
 struct GetOperation {
-    table: Table,
+    tables: Vec<Table>,
     family: Family,
     protocol: Protocol,
     tuple: Direction,
+    raw: bool,
 }
 
 impl GetOperation {
     fn new(
-        table: Table,
+        tables: Vec<Table>,
         family: Family,
         protocol: Protocol,
         directed_tuple: Direction,
+        raw: bool,
     ) -> GetOperation {
         GetOperation {
-            table,
+            tables,
             family,
             protocol,
             tuple: directed_tuple,
+            raw,
         }
     }
 }
 
-impl Operation for GetOperation {
-    fn request(&self) -> Result<Request, Error> {
+impl GetOperation {
+    // Only the conntrack table accepts get requests. Other tables are dumped and searched by the tuple.
+    fn request_for(&self, table: Table) -> Request {
         let meta = RequestMeta::default()
-            .table(self.table.into())
+            .table(table.into())
             .family(self.family.into());
+        let params = GetParams::new(self.protocol.tuple_protocol(), self.tuple.clone());
+        let op = match table {
+            Table::Conntrack => RequestOperation::Get(params),
+            Table::Dying | Table::Unconfirmed => RequestOperation::List(Some(params.filter())),
+        };
+
+        Request::new(meta, op)
+    }
+}
+
+impl Operation for GetOperation {
+    fn request(&self) -> Result<Request, Error> {
+        let table = self.tables.first().copied().unwrap_or_default();
+        Ok(self.request_for(table))
+    }
 
-        Ok(Request::new(
-            meta,
-            RequestOperation::Get(GetParams::new(self.protocol.into(), self.tuple.clone())),
-        ))
+    fn requests(&self) -> Result<Vec<Request>, Error> {
+        Ok(self.tables.iter().map(|t| self.request_for(*t)).collect())
     }
 
     fn typ(&self) -> OperationType {
         OperationType::Get
     }
+
+    fn keep_raw(&self) -> bool {
+        self.raw
+    }
 }
 
 #[derive(Debug, Error)]
@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use clap::Parser;
+use serde::Serialize;
+
+use crate::{
+    cmd::Runner,
+    config::{GlobalOption, Output, Table},
+    count::count,
+    error::Error,
+    sysctl,
+};
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Check that conntrack is usable and the table is not close to full. Exit with 1 when the utilization is above the threshold"
+)]
+pub struct HealthcheckCmd {
+    #[arg(
+        short,
+        long,
+        default_value = "table",
+        help = "Output format (\"table\", \"json\")"
+    )]
+    output: Output,
+    #[arg(
+        long,
+        default_value_t = 90.0,
+        value_parser = parse_threshold,
+        help = "Utilization of the table in percentage to be unhealthy above. Accept 0 to 100"
+    )]
+    threshold: f64,
+}
+
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    let threshold: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=100.0).contains(&threshold) {
+        return Err(String::from("the threshold must be between 0 and 100"));
+    }
+    Ok(threshold)
+}
+
+#[derive(Debug, Serialize)]
+struct Health {
+    count: u32,
+    max: u32,
+    utilization: f64,
+    healthy: bool,
+}
+
+#[async_trait]
+impl Runner for HealthcheckCmd {
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        // The count is requested by netlink to check that the socket works.
+        let count = count(Table::Conntrack, opt).await?;
+        let max = sysctl::conntrack_max().await?;
+        let utilization = sysctl::utilization(count, max);
+        let health = Health {
+            count,
+            max,
+            utilization,
+            healthy: utilization <= self.threshold,
+        };
+        match self.output {
+            Output::Json => println!("{}", serde_json::to_string(&health).unwrap_or_default()),
+            // The classic format is only for flows.
+            Output::Table | Output::Classic => println!(
+                "{} {count}/{max} ({utilization:.1}%)",
+                if health.healthy { "ok" } else { "warning" }
+            ),
+        }
+        if !health.healthy {
+            return Err(Error::Unhealthy(format!(
+                "the table utilization {utilization:.1}% is above the threshold {}%",
+                self.threshold
+            )));
+        }
+        Ok(())
+    }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
+    }
+}
@@ -1,14 +1,23 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, HashSet},
+    fmt,
+    path::PathBuf,
+    time::SystemTime,
+};
+
 use async_trait::async_trait;
 use clap::Parser;
 use conntrack::{
     event::Event,
-    flow::{Flow, TcpState},
-    request::{Request, RequestMeta, RequestOperation},
+    flow::{Flow, TcpState, Tuple},
+    proc::ProcConntrackSource,
+    request::{ProtoState, Request, RequestMeta, RequestOperation},
     socket::NfConntrackSocket,
     Conntrack,
 };
 use display::{
-    flow::{FlowColumn, FlowRow},
+    flow::{ClassicRow, FlowColumn, FlowJson, FlowRow},
     json::JsonDisplay,
     table::TableDisplay,
     Display,
@@ -16,11 +25,12 @@ use display::{
 use futures::TryStreamExt;
 
 use crate::{
-    cmd::{DisplayRunner, Runner},
-    config::{Family, Output, Protocol, Status, Table},
+    cmd::{terminal_width, DisplayRunner, Runner},
+    config::{Family, GlobalOption, Output, Protocol, Rank, SortKey, Source, Status, Table},
     error::Error,
-    executor::{Executor, Operation, OperationType},
-    filter::Filter,
+    executor::{report_skipped, Executor, Operation, OperationType},
+    filter::{Filter, FilterFile},
+    resolve::resolve_flows,
 };
 
 #[derive(Debug, Parser)]
@@ -30,7 +40,7 @@ pub struct ListCmd {
         short,
         long,
         default_value = "table",
-        help = "Output format (\"table\", \"json\")"
+        help = "Output format (\"table\", \"json\", \"classic\"). \"classic\" is the format of conntrack-tools"
     )]
     output: Output,
     #[arg(
@@ -44,14 +54,14 @@ pub struct ListCmd {
         short,
         long,
         default_value = "ipv4",
-        help = "L3 layer protocol (\"ipv4\", \"ipv6\", \"any\")"
+        help = "L3 layer protocol (\"ipv4\", \"ipv6\", \"any\", \"both\"). \"both\" dumps ipv4 and ipv6 entries separately and merges them"
     )]
     family: Family,
     #[arg(
         short,
         long,
         default_value = "any",
-        help = "L4 layer protocol (\"any\", \"tcp\", \"udp\")"
+        help = "L4 layer protocol (\"any\", \"all\", \"tcp\", \"udp\" or protocol number e.g. 47). \"all\" also ignores the protocol in the filter file"
     )]
     protocol: Protocol,
     #[arg(
@@ -61,6 +71,12 @@ pub struct ListCmd {
         help = "Zero counters while listing"
     )]
     zero: bool,
+    #[arg(
+        long,
+        default_value = "netlink",
+        help = "Where entries are read from (\"netlink\", \"proc\"). \"proc\" reads /proc/net/nf_conntrack where netlink is not permitted"
+    )]
+    source: Source,
     #[arg(
         long,
         help = "Filter for source address from original direction. Accept IP address format or with prefix. e.g. \"192.168.0.1\" or \"192.168.0.0/24\""
@@ -81,18 +97,50 @@ pub struct ListCmd {
         help = "Filter for destination address from reply direction. Accept IP address format or with prefix. e.g. \"192.168.0.1\" or \"192.168.0.0/24\""
     )]
     reply_dst_addr: Option<String>,
-    #[arg(long, help = "Filter for source port from original direction.")]
-    orig_src_port: Option<u16>,
-    #[arg(long, help = "Filter for destination port from original direction.")]
-    orig_dst_port: Option<u16>,
-    #[arg(long, help = "Filter for source port from reply direction.")]
-    reply_src_port: Option<u16>,
-    #[arg(long, help = "Filter for destination port from reply direction.")]
-    reply_dst_port: Option<u16>,
+    #[arg(
+        long,
+        help = "Address filters must be a single address. Networks like \"10.0.0.0/8\" are rejected to prevent accidental broad matches"
+    )]
+    exact_addr: bool,
+    #[arg(
+        long,
+        help = "Filter for source port from original direction. Repeat it to match any of the ports."
+    )]
+    orig_src_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for destination port from original direction. Repeat it to match any of the ports."
+    )]
+    orig_dst_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for source port from reply direction. Repeat it to match any of the ports."
+    )]
+    reply_src_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for destination port from reply direction. Repeat it to match any of the ports."
+    )]
+    reply_dst_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for source port from either direction. Repeat it to match any of the ports."
+    )]
+    src_port: Vec<u16>,
+    #[arg(
+        long,
+        help = "Filter for destination port from either direction. Repeat it to match any of the ports."
+    )]
+    dst_port: Vec<u16>,
     #[arg(long, help = "Filter for mark")]
     mark: Option<u32>,
     #[arg(long, help = "Filter for use")]
     r#use: Option<u32>,
+    #[arg(
+        long,
+        help = "Filter for the id of ICMP and ICMPv6 flows. Flows of other protocols never match"
+    )]
+    icmp_id: Option<u16>,
     #[arg(
         long,
         help = "Filter for tcp state. (\"none\", \"syn_sent\", \"syn_recv\", \"established\", \"fin_wait\", \"close_wait\", \"last_ack\", \"time_wait\", \"close\", \"listen\")"
@@ -100,22 +148,228 @@ pub struct ListCmd {
     tcp_state: Option<TcpState>,
     #[arg(
         long,
-        help = "Filter for status flags. (\"assured\", \"seen_reply\", \"fixed_timeout\", \"expected\" or u16 integer (When specifying the integer value, you should use with --detailed-status flag.))"
+        help = "Filter for the state of the flow's own protocol. TCP flows are matched with tcp states and SCTP flows with sctp states (\"none\", \"closed\", \"cookie_wait\", \"cookie_echoed\", \"established\", \"shutdown_sent\", \"shutdown_recd\", \"shutdown_ack_sent\", \"heartbeat_sent\")"
+    )]
+    state: Option<ProtoState>,
+    #[arg(
+        long,
+        help = "Filter for status flags. (\"expected\", \"seen_reply\", \"assured\", \"confirmed\", \"snat\", \"dnat\", \"seq_adj\", \"snat_done\", \"dnat_done\", \"dying\", \"fixed_timeout\", \"template\", \"untracked\", \"helper\", \"offload\" or u16 integer (When specifying the integer value, you should use with --detailed-status flag.)). Flows must have all the given flags, either comma separated like \"assured,seen_reply\" or set in the integer"
     )]
     status: Option<Status>,
+    #[arg(
+        long,
+        help = "Filter for flows which have seen traffic in both directions (SEEN_REPLY). Unlike \"assured\", a single reply packet is enough. Combined with --status, both must match"
+    )]
+    bidirectional: bool,
+    #[arg(
+        long,
+        help = "Filter for related flows created by a helper such as FTP data or SIP RTP (EXPECTED)"
+    )]
+    related_only: bool,
+    #[arg(
+        long,
+        conflicts_with = "unconfirmed_only",
+        help = "Filter for flows which have the CONFIRMED flag"
+    )]
+    confirmed_only: bool,
+    #[arg(
+        long,
+        help = "Filter for flows which don't have the CONFIRMED flag yet. Unlike \"--table unconfirmed\", entries are checked in the selected table"
+    )]
+    unconfirmed_only: bool,
+    #[arg(
+        long,
+        conflicts_with = "unassured_only",
+        help = "Filter for flows which have the ASSURED flag"
+    )]
+    assured_only: bool,
+    #[arg(long, help = "Filter for flows which don't have the ASSURED flag")]
+    unassured_only: bool,
+    #[arg(
+        long,
+        help = "Exclude IPv6 flows with link-local (fe80::/10) or multicast (ff00::/8) addresses"
+    )]
+    no_link_local: bool,
+    #[arg(
+        long = "self",
+        help = "Filter for flows whose original source and destination addresses are the same"
+    )]
+    self_connection: bool,
+    #[arg(
+        long,
+        help = "Exclude half-created flows whose reply tuple has zeroed addresses or ports"
+    )]
+    no_orphan_reply: bool,
+    #[arg(
+        long,
+        conflicts_with = "reply_dst_private",
+        help = "Filter for flows whose reply destination is not in the private ranges (10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16 and fc00::/7)"
+    )]
+    reply_dst_public: bool,
+    #[arg(
+        long,
+        help = "Filter for flows whose reply destination is in the private ranges (10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16 and fc00::/7)"
+    )]
+    reply_dst_private: bool,
+    #[arg(
+        long,
+        help = "Filter for flows whose remaining timeout is less than the given value in seconds. The value itself is excluded"
+    )]
+    timeout_lt: Option<u32>,
+    #[arg(
+        long,
+        help = "Filter for flows whose remaining timeout is greater than the given value in seconds. The value itself is excluded"
+    )]
+    timeout_gt: Option<u32>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        help = "Filter for source NAT flows. When the address is given, match flows translated to it. Accept IP address format or with prefix."
+    )]
+    src_nat: Option<Option<String>>,
+    #[arg(
+        long,
+        num_args = 0..=1,
+        help = "Filter for destination NAT flows. When the address is given, match flows translated to it. Accept IP address format or with prefix."
+    )]
+    dst_nat: Option<Option<String>>,
+    #[arg(
+        long,
+        help = "Load filters from a TOML file. Flags given on the command line override values in the file."
+    )]
+    filter_file: Option<PathBuf>,
     #[arg(
         long,
         help = "Show detailed status flags. Flags are shown binary format."
     )]
     detailed_status: bool,
+    #[arg(
+        long,
+        conflicts_with = "detailed_status",
+        help = "Show all status flags by name like \"ASSURED,CONFIRMED,SNAT\" instead of the preferred one"
+    )]
+    status_names: bool,
+    #[arg(long, help = "Show the security context column")]
+    secctx: bool,
+    #[arg(
+        long,
+        help = "Show the id column which the kernel assigns to each entry"
+    )]
+    show_id: bool,
+    #[arg(
+        long,
+        help = "Keep unrecognized netlink attributes and dump them as hex in JSON output"
+    )]
+    raw: bool,
+    #[arg(
+        long,
+        visible_alias = "skip-errors",
+        help = "Skip flows which fail to be parsed or have a zeroed reply tuple instead of aborting the dump. The number of skipped flows is printed to stderr"
+    )]
+    skip_incomplete: bool,
+    #[arg(
+        long,
+        help = "Use flat keys like \"orig_src_addr\" which line up with the table columns in JSON output"
+    )]
+    json_flat: bool,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Show only given keys in JSON output. Accept comma separated values. e.g. \"orig_src_addr,orig_dst_port\". Unknown keys are rejected"
+    )]
+    fields: Vec<String>,
+    #[arg(
+        long,
+        help = "Show full addresses. By default, addresses are truncated to fit the terminal width"
+    )]
+    wide: bool,
+    #[arg(
+        long,
+        help = "Show the reply tuple only for translated flows in the last column instead of the reply columns"
+    )]
+    compact: bool,
+    #[arg(
+        long,
+        help = "Show when flows expire as an absolute time. Flows with FIXED_TIMEOUT are shown as \"never\""
+    )]
+    expires: bool,
+    #[arg(
+        long,
+        help = "Size table columns to the widest value instead of fixed widths. Flows are shown after the dump ends"
+    )]
+    auto_width: bool,
+    #[arg(
+        long,
+        help = "Print the number of flows by protocol and tcp state to stderr after the list"
+    )]
+    summary: bool,
+    #[arg(
+        long,
+        help = "Skip flows which have the same tuples and protocol as an already shown flow"
+    )]
+    dedup: bool,
+    #[arg(
+        long,
+        help = "Sort flows by the key (\"tuple\", \"timeout\", \"bytes\", \"packets\"). Counters are sorted in descending order"
+    )]
+    sort_by: Option<SortKey>,
+    #[arg(
+        long,
+        help = "Show at most the given number of flows. With --sort-by, the first flows in the sorted order are shown"
+    )]
+    limit: Option<usize>,
+    // Flows are not displayed, so options for displaying them are rejected instead of being ignored.
+    #[arg(
+        long,
+        conflicts_with_all = ["sort_by", "limit", "summary", "resolve", "json_flat", "fields", "compact", "expires", "auto_width"],
+        help = "Print the number of TCP flows in each state instead of the flows"
+    )]
+    count_by_state: bool,
+    #[arg(
+        long,
+        help = "Show names of addresses looked up by reverse DNS in the table. JSON output keeps addresses"
+    )]
+    resolve: bool,
     #[arg(long, help = "Don't print the header")]
     no_header: bool,
+    #[arg(
+        long,
+        help = "Exit with 1 when no flows are listed and with 2 on errors like grep"
+    )]
+    status_code: bool,
 }
 
 #[async_trait]
 impl Runner for ListCmd {
-    async fn run(&self) -> Result<(), Error> {
-        let filter = Filter::new(
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        let listed = self.list(opt).await?;
+        if self.status_code && listed == 0 {
+            return Err(Error::NoMatch);
+        }
+        Ok(())
+    }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
+    }
+
+    fn status_code(&self) -> bool {
+        self.status_code
+    }
+}
+
+impl ListCmd {
+    // Return the number of listed flows.
+    async fn list(&self, opt: &GlobalOption) -> Result<usize, Error> {
+        // They change only how the table is drawn, so they are rejected instead of being ignored.
+        if self.output.ne(&Output::Table)
+            && (self.compact || self.expires || self.auto_width || self.wide || self.status_names)
+        {
+            return Err(Error::TableOutput(String::from(
+                "--compact, --expires, --auto-width, --wide and --status-names",
+            )));
+        }
+        let mut filter = Filter::new(
             self.table,
             self.family,
             self.zero,
@@ -124,17 +378,50 @@ impl Runner for ListCmd {
             self.orig_dst_addr.clone(),
             self.reply_src_addr.clone(),
             self.reply_dst_addr.clone(),
-            self.orig_src_port,
-            self.orig_dst_port,
-            self.reply_src_port,
-            self.reply_dst_port,
+            self.orig_src_port.clone(),
+            self.orig_dst_port.clone(),
+            self.reply_src_port.clone(),
+            self.reply_dst_port.clone(),
+            self.src_port.clone(),
+            self.dst_port.clone(),
             self.mark,
             self.r#use,
+            self.icmp_id,
             self.tcp_state,
             self.status,
+            self.bidirectional,
+            self.timeout_lt,
+            self.timeout_gt,
+            self.src_nat.clone(),
+            self.dst_nat.clone(),
+            self.exact_addr,
+            self.related_only,
+            self.no_link_local,
+            self.self_connection,
+            self.no_orphan_reply,
+            self.reply_dst_public,
+            self.reply_dst_private,
+            self.state,
+            self.confirmed_only,
+            self.unconfirmed_only,
+            self.assured_only,
+            self.unassured_only,
         );
-        let op = ListOperation::new(filter);
-        let executor = Executor::new(op);
+        if let Some(path) = &self.filter_file {
+            filter = filter.merge(FilterFile::load(path)?)?;
+        }
+        if self.source.eq(&Source::Proc) {
+            return self.run_proc(&filter, opt).await;
+        }
+        let family = filter.family;
+        let op = ListOperation::new(filter, self.raw, self.skip_incomplete);
+        let executor = Executor::new(op, opt);
+        if self.count_by_state {
+            return self.run_count_by_state(executor, family).await;
+        }
+        if matches!(family, Family::Both) || self.resolve {
+            return self.run_collected(executor).await;
+        }
         let ct = executor.exec().await?;
 
         match self.output() {
@@ -142,13 +429,25 @@ impl Runner for ListCmd {
                 let flow_row = FlowRow::new(
                     self.detailed_status,
                     self.family.into(),
-                    self.protocol.into(),
-                );
-                let table_display = TableDisplay::new(tokio::io::stdout(), flow_row);
+                    self.protocol.flow_protocol(),
+                )
+                .status_names(self.status_names)
+                .secctx(self.secctx)
+                .show_id(self.show_id)
+                .max_width(terminal_width(self.wide))
+                .compact(self.compact)
+                .expires(self.expires.then(SystemTime::now));
+                let table_display =
+                    TableDisplay::new(tokio::io::stdout(), flow_row).auto_width(self.auto_width);
+                self.process(ct, table_display).await
+            }
+            Output::Classic => {
+                let table_display = TableDisplay::new(tokio::io::stdout(), ClassicRow::new(false));
                 self.process(ct, table_display).await
             }
             Output::Json => {
-                let json_display = JsonDisplay::new(tokio::io::stdout());
+                let json_display =
+                    JsonDisplay::new(tokio::io::stdout()).fields(self.fields.clone());
                 self.process(ct, json_display).await
             }
         }
@@ -169,50 +468,405 @@ impl DisplayRunner for ListCmd {
         &self,
         mut ct: Conntrack<NfConntrackSocket>,
         mut display: D,
-    ) -> Result<(), Error> {
+    ) -> Result<usize, Error> {
         if self.output().ne(&Output::Json) && !self.no_header() {
             display.header().await.map_err(Error::Display)?;
         }
-        while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
+        let mut summary = Summary::default();
+        let mut seen = HashSet::new();
+        // Sorted flows are displayed after the dump ends.
+        let mut sorted = self.sort_by.map(|key| SortedFlows::new(key, self.limit));
+        let mut shown = 0;
+        'dump: while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
             for event in events.iter() {
                 if let Event::Flow(flow) = event {
+                    if self.is_duplicate(&mut seen, flow) {
+                        continue;
+                    }
+                    if let Some(sorted) = sorted.as_mut() {
+                        sorted.push(flow.clone());
+                        continue;
+                    }
+                    // The rest of the dump is not drained since the connection is dropped when this returns.
+                    if self.limit.is_some_and(|limit| shown >= limit) {
+                        break 'dump;
+                    }
                     display
-                        .consume::<FlowColumn, Flow>(flow)
+                        .consume::<FlowColumn, FlowJson>(&FlowJson::new(flow).flat(self.json_flat))
                         .await
                         .map_err(Error::Display)?;
+                    summary.observe(flow);
+                    shown += 1;
+                }
+            }
+        }
+        report_skipped(ct.skipped());
+        for flow in sorted.map(SortedFlows::into_vec).unwrap_or_default() {
+            display
+                .consume::<FlowColumn, FlowJson>(&FlowJson::new(&flow).flat(self.json_flat))
+                .await
+                .map_err(Error::Display)?;
+            summary.observe(&flow);
+        }
+        display.flush().await.map_err(Error::Display)?;
+        if self.summary {
+            eprint!("{summary}");
+        }
+        Ok(summary.total)
+    }
+}
+
+impl ListCmd {
+    // Flows are counted while receiving them instead of collecting, so that a huge table can be counted.
+    // Flows without a TCP state are not counted.
+    async fn run_count_by_state(
+        &self,
+        executor: Executor<ListOperation>,
+        family: Family,
+    ) -> Result<usize, Error> {
+        let mut counts = BTreeMap::<TcpState, u64>::new();
+        let mut seen = HashSet::new();
+        // Flows without a tcp state are not shown but matched for --status-code.
+        let mut total = 0;
+        let mut observe = |event: &Event| {
+            if let Event::Flow(flow) = event {
+                if self.is_duplicate(&mut seen, flow) {
+                    return;
+                }
+                total += 1;
+                if let Some(state) = flow.tcp_state {
+                    *counts.entry(state).or_default() += 1;
+                }
+            }
+        };
+        if let Family::Both = family {
+            executor.exec_all().await?.iter().for_each(&mut observe);
+        } else {
+            let mut ct = executor.exec().await?;
+            while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
+                events.iter().for_each(&mut observe);
+            }
+            report_skipped(ct.skipped());
+        }
+
+        let counts = counts
+            .into_iter()
+            .map(|(state, c)| (String::from(state), c));
+        match self.output {
+            Output::Json => {
+                let m = counts
+                    .map(|(state, c)| (state, serde_json::Value::from(c)))
+                    .collect::<serde_json::Map<_, _>>();
+                println!("{}", serde_json::Value::Object(m));
+            }
+            Output::Table | Output::Classic => {
+                for (state, c) in counts {
+                    println!("{state:<12} {c}");
                 }
             }
         }
+        Ok(total)
+    }
+
+    // Order flows by --sort-by and keep up to --limit flows.
+    fn order(&self, flows: impl Iterator<Item = Flow>) -> Vec<Flow> {
+        match self.sort_by {
+            Some(key) => {
+                let mut sorted = SortedFlows::new(key, self.limit);
+                flows.for_each(|flow| sorted.push(flow));
+                sorted.into_vec()
+            }
+            None => flows.take(self.limit.unwrap_or(usize::MAX)).collect(),
+        }
+    }
+
+    // Some kernels report the same flow twice in a dump of both families.
+    fn is_duplicate(&self, seen: &mut HashSet<FlowKey>, flow: &Flow) -> bool {
+        self.dedup && !seen.insert((flow.original.clone(), flow.reply.clone(), flow.protocol))
+    }
+
+    // Entries of both families are collected before displaying
+    // because the width of address columns depends on whether ipv6 entries exist.
+    // With --resolve, entries are also collected to look up names before displaying them.
+    async fn run_collected(&self, executor: Executor<ListOperation>) -> Result<usize, Error> {
+        let events = executor.exec_all().await?;
+        self.display_collected(events).await
+    }
+
+    // procfs has only the conntrack table and can't be written to.
+    // Entries are read at once, so they are displayed in the same way as collected entries.
+    async fn run_proc(&self, filter: &Filter, opt: &GlobalOption) -> Result<usize, Error> {
+        if !matches!(filter.table, Table::Conntrack) {
+            return Err(Error::ProcSource(String::from(
+                "only the conntrack table is available",
+            )));
+        }
+        if self.zero || self.count_by_state || self.raw {
+            return Err(Error::ProcSource(String::from(
+                "--zero, --count-by-state and --raw require netlink",
+            )));
+        }
+        // procfs is read in the current network namespace without a netlink socket.
+        if opt.netns.is_some() || opt.port_id.is_some() || opt.raw_dump || self.skip_incomplete {
+            return Err(Error::ProcSource(String::from(
+                "--netns, --port-id, --raw-dump and --skip-incomplete require netlink",
+            )));
+        }
+        let f = conntrack::request::Filter::try_from(filter)?;
+        let events = ProcConntrackSource::default()
+            .list(Some(&f))
+            .await
+            .map_err(Error::Conntrack)?
+            .into_iter()
+            .map(Event::Flow)
+            .collect();
+        self.display_collected(events).await
+    }
+
+    async fn display_collected(&self, events: Vec<Event>) -> Result<usize, Error> {
+        match self.output() {
+            Output::Table => {
+                let has_ipv6 = events.iter().any(|event| match event {
+                    Event::Flow(flow) => flow.original.src_addr.is_ipv6(),
+                    _ => false,
+                });
+                let family = if has_ipv6 {
+                    conntrack::Family::Ipv6
+                } else {
+                    conntrack::Family::Ipv4
+                };
+                let flow_row =
+                    FlowRow::new(self.detailed_status, family, self.protocol.flow_protocol())
+                        .status_names(self.status_names)
+                        .secctx(self.secctx)
+                        .show_id(self.show_id)
+                        .max_width(terminal_width(self.wide))
+                        .compact(self.compact)
+                        .expires(self.expires.then(SystemTime::now));
+                let table_display =
+                    TableDisplay::new(tokio::io::stdout(), flow_row).auto_width(self.auto_width);
+                self.process_events(events, table_display).await
+            }
+            Output::Classic => {
+                let table_display = TableDisplay::new(tokio::io::stdout(), ClassicRow::new(false));
+                self.process_events(events, table_display).await
+            }
+            Output::Json => {
+                let json_display =
+                    JsonDisplay::new(tokio::io::stdout()).fields(self.fields.clone());
+                self.process_events(events, json_display).await
+            }
+        }
+    }
+
+    async fn process_events<D: Display + Send + Sync>(
+        &self,
+        events: Vec<Event>,
+        mut display: D,
+    ) -> Result<usize, Error> {
+        if self.output().ne(&Output::Json) && !self.no_header() {
+            display.header().await.map_err(Error::Display)?;
+        }
+        let mut summary = Summary::default();
+        let mut seen = HashSet::new();
+        let names = if self.resolve && self.output().eq(&Output::Table) {
+            Some(resolve_flows(&events).await)
+        } else {
+            None
+        };
+        let flows = events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Flow(flow) => Some(flow),
+                _ => None,
+            })
+            .filter(|flow| !self.is_duplicate(&mut seen, flow));
+        for flow in self.order(flows).iter() {
+            display
+                .consume::<FlowColumn, FlowJson>(
+                    &FlowJson::new(flow)
+                        .flat(self.json_flat)
+                        .names(names.as_ref()),
+                )
+                .await
+                .map_err(Error::Display)?;
+            summary.observe(flow);
+        }
+        display.flush().await.map_err(Error::Display)?;
+        if self.summary {
+            eprint!("{summary}");
+        }
+        Ok(summary.total)
+    }
+}
+
+type FlowKey = (Tuple, Tuple, conntrack::flow::Protocol);
+
+// Flows ordered by --sort-by.
+// With --limit, only the first `limit` flows are kept. The heap has the last kept flow on the top
+// to replace it with a flow which comes earlier, so memory stays O(limit) even for a huge table.
+#[derive(Debug)]
+struct SortedFlows {
+    key: SortKey,
+    limit: Option<usize>,
+    heap: BinaryHeap<RankedFlow>,
+}
+
+impl SortedFlows {
+    fn new(key: SortKey, limit: Option<usize>) -> SortedFlows {
+        SortedFlows {
+            key,
+            limit,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn push(&mut self, flow: Flow) {
+        let ranked = RankedFlow(self.key.rank(&flow), flow);
+        if self.limit.is_none_or(|limit| self.heap.len() < limit) {
+            self.heap.push(ranked);
+        } else if self.heap.peek().is_some_and(|last| ranked < *last) {
+            self.heap.pop();
+            self.heap.push(ranked);
+        }
+    }
+
+    fn into_vec(self) -> Vec<Flow> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|RankedFlow(_, flow)| flow)
+            .collect()
+    }
+}
+
+// Flows are compared only by the rank.
+#[derive(Debug)]
+struct RankedFlow(Rank, Flow);
+
+impl PartialEq for RankedFlow {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for RankedFlow {}
+
+impl PartialOrd for RankedFlow {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedFlow {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+// Counts of listed flows shown by --summary.
+// It is printed to stderr not to mix it with the output.
+#[derive(Debug, Default)]
+struct Summary {
+    total: usize,
+    protocols: BTreeMap<String, usize>,
+    tcp_states: BTreeMap<String, usize>,
+}
+
+impl Summary {
+    fn observe(&mut self, flow: &Flow) {
+        self.total += 1;
+        *self
+            .protocols
+            .entry(String::from(flow.protocol))
+            .or_default() += 1;
+        if let Some(state) = flow.tcp_state {
+            *self.tcp_states.entry(String::from(state)).or_default() += 1;
+        }
+    }
+}
+
+// e.g.
+// 3 flows
+//   protocol: tcp=2 udp=1
+//   tcp state: ESTABLISHED=1 TIME_WAIT=1
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} flows", self.total)?;
+        if !self.protocols.is_empty() {
+            writeln!(f, "  protocol: {}", join_counts(&self.protocols))?;
+        }
+        if !self.tcp_states.is_empty() {
+            writeln!(f, "  tcp state: {}", join_counts(&self.tcp_states))?;
+        }
         Ok(())
     }
 }
 
+fn join_counts(counts: &BTreeMap<String, usize>) -> String {
+    counts
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[derive(Debug)]
 struct ListOperation {
     filter: Filter,
+    raw: bool,
+    skip_incomplete: bool,
 }
 
 impl Operation for ListOperation {
     fn request(&self) -> Result<Request, Error> {
-        let mut meta = RequestMeta::default()
-            .table(self.filter.table.into())
-            .family(self.filter.family.into());
-        if self.filter.zero {
-            meta = meta.zero()
-        }
-        Ok(Request::new(
-            meta,
-            RequestOperation::List(Some(conntrack::request::Filter::try_from(&self.filter)?)),
-        ))
+        self.request_for(self.filter.family)
+    }
+
+    fn requests(&self) -> Result<Vec<Request>, Error> {
+        self.filter
+            .family
+            .expand()
+            .into_iter()
+            .map(|f| self.request_for(f))
+            .collect()
     }
 
     fn typ(&self) -> OperationType {
         OperationType::List
     }
+
+    fn keep_raw(&self) -> bool {
+        self.raw
+    }
+
+    fn skip_incomplete(&self) -> bool {
+        self.skip_incomplete
+    }
 }
 
 impl ListOperation {
-    fn new(filter: Filter) -> ListOperation {
-        ListOperation { filter }
+    fn new(filter: Filter, raw: bool, skip_incomplete: bool) -> ListOperation {
+        ListOperation {
+            filter,
+            raw,
+            skip_incomplete,
+        }
+    }
+
+    fn request_for(&self, family: Family) -> Result<Request, Error> {
+        let mut meta = RequestMeta::default()
+            .table(self.filter.table.into())
+            .family(family.into());
+        if self.filter.zero {
+            meta = meta.zero()
+        }
+        Ok(Request::new(
+            meta,
+            // The filter of each request has the family of the request, so CTA_FILTER is sent for --family both too.
+            RequestOperation::List(Some(
+                conntrack::request::Filter::try_from(&self.filter)?.family(family.into()),
+            )),
+        ))
     }
 }
@@ -2,17 +2,25 @@ use std::process::exit;
 
 use clap::Parser;
 use cmd::Cmd;
+use error::Error;
 
 mod cmd;
 mod config;
 mod count;
+mod delete;
 mod error;
 mod event;
 mod executor;
 mod filter;
+mod forward;
 mod get;
+mod healthcheck;
 mod list;
+mod replay;
+mod resolve;
 mod stats;
+mod sysctl;
+mod update;
 mod version;
 
 #[tokio::main]
@@ -20,7 +28,28 @@ async fn main() {
     let cmd = Cmd::parse();
 
     if let Err(e) = cmd.run().await {
-        eprintln!("{e}");
-        exit(-1);
+        // Nothing is printed when no flows matched as grep does.
+        if matches!(e, Error::NoMatch) {
+            exit(1);
+        }
+        // The health is already printed, so only the reason is added.
+        if let Error::Unhealthy(reason) = &e {
+            if cmd.json_output() {
+                eprintln!("{}", e.to_json());
+            } else {
+                eprintln!("{reason}");
+            }
+            exit(1);
+        }
+        if cmd.json_output() {
+            eprintln!("{}", e.to_json());
+        } else {
+            eprintln!("{e}");
+            if let Some(hint) = e.hint() {
+                eprintln!("hint: {hint}");
+            }
+        }
+        // Errors are told apart from no matches with --status-code.
+        exit(if cmd.status_code() { 2 } else { -1 });
     }
 }
@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::Parser;
+use conntrack::flow::Flow;
+use display::{
+    flow::{ClassicRow, FlowColumn, FlowJson, FlowRow},
+    json::JsonDisplay,
+    table::TableDisplay,
+    Display,
+};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+use crate::{
+    cmd::{terminal_width, Runner},
+    config::{GlobalOption, Output},
+    error::Error,
+};
+
+#[derive(Debug, Parser)]
+#[command(about = "Show flows captured with \"-o json\" again in the given format")]
+pub struct ReplayCmd {
+    #[arg(
+        short,
+        long,
+        help = "JSON Lines file written by \"list -o json\" or \"event -o json\". \"-\" reads stdin"
+    )]
+    input: PathBuf,
+    #[arg(
+        short,
+        long,
+        default_value = "table",
+        help = "Output format (\"table\", \"json\", \"classic\"). \"classic\" is the format of conntrack-tools"
+    )]
+    output: Output,
+    #[arg(
+        long,
+        help = "Show detailed status flags. Flags are shown binary format."
+    )]
+    detailed_status: bool,
+    #[arg(
+        long,
+        help = "Show full addresses. By default, addresses are truncated to fit the terminal width"
+    )]
+    wide: bool,
+    #[arg(long, help = "Don't print the header")]
+    no_header: bool,
+}
+
+#[async_trait]
+impl Runner for ReplayCmd {
+    async fn run(&self, _opt: &GlobalOption) -> Result<(), Error> {
+        let flows = if self.input.as_os_str() == "-" {
+            read_flows(BufReader::new(tokio::io::stdin())).await?
+        } else {
+            let file = tokio::fs::File::open(&self.input)
+                .await
+                .map_err(|e| Error::Replay(format!("{}: {e}", self.input.display())))?;
+            read_flows(BufReader::new(file)).await?
+        };
+
+        match self.output {
+            Output::Table => {
+                // The width of address columns depends on whether ipv6 entries exist.
+                let family = if flows.iter().any(|flow| flow.original.src_addr.is_ipv6()) {
+                    conntrack::Family::Ipv6
+                } else {
+                    conntrack::Family::Ipv4
+                };
+                let flow_row = FlowRow::new(self.detailed_status, family, None)
+                    .max_width(terminal_width(self.wide));
+                let table_display = TableDisplay::new(tokio::io::stdout(), flow_row);
+                self.process(&flows, table_display).await
+            }
+            Output::Classic => {
+                let table_display = TableDisplay::new(tokio::io::stdout(), ClassicRow::new(false));
+                self.process(&flows, table_display).await
+            }
+            Output::Json => {
+                let json_display = JsonDisplay::new(tokio::io::stdout());
+                self.process(&flows, json_display).await
+            }
+        }
+    }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
+    }
+}
+
+impl ReplayCmd {
+    async fn process<D: Display + Send + Sync>(
+        &self,
+        flows: &[Flow],
+        mut display: D,
+    ) -> Result<(), Error> {
+        if self.output.ne(&Output::Json) && !self.no_header {
+            display.header().await.map_err(Error::Display)?;
+        }
+        for flow in flows.iter() {
+            display
+                .consume::<FlowColumn, FlowJson>(&FlowJson::new(flow))
+                .await
+                .map_err(Error::Display)?;
+        }
+        display.flush().await.map_err(Error::Display)?;
+        Ok(())
+    }
+}
+
+// Each line has a flow in the nested form. Blank lines are skipped.
+// Extra keys of events such as the timestamp are ignored.
+async fn read_flows<R: AsyncBufRead + Unpin>(reader: R) -> Result<Vec<Flow>, Error> {
+    let mut flows = Vec::new();
+    let mut lines = reader.lines();
+    let mut n = 0;
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| Error::Replay(e.to_string()))?
+    {
+        n += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let flow =
+            serde_json::from_str(&line).map_err(|e| Error::Replay(format!("line {n}: {e}")))?;
+        flows.push(flow);
+    }
+    Ok(flows)
+}
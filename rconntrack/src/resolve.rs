@@ -0,0 +1,96 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    mem,
+    net::IpAddr,
+};
+
+use conntrack::event::Event;
+
+// NI_MAXHOST in netdb.h
+const MAX_HOST_LEN: usize = 1025;
+
+// Look up names of all addresses in the flows.
+pub(crate) async fn resolve_flows(events: &[Event]) -> HashMap<IpAddr, String> {
+    let addrs = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Flow(flow) => Some([
+                flow.original.src_addr,
+                flow.original.dst_addr,
+                flow.reply.src_addr,
+                flow.reply.dst_addr,
+            ]),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    resolve(addrs).await
+}
+
+// Look up names of addresses in parallel.
+// An address without a name is not included in the result and shown as it is.
+async fn resolve(addrs: HashSet<IpAddr>) -> HashMap<IpAddr, String> {
+    let handles = addrs
+        .into_iter()
+        .map(|addr| tokio::task::spawn_blocking(move || lookup_addr(addr).map(|name| (addr, name))))
+        .collect::<Vec<_>>();
+    let mut names = HashMap::new();
+    for handle in handles {
+        if let Ok(Some((addr, name))) = handle.await {
+            names.insert(addr, name);
+        }
+    }
+    names
+}
+
+// getnameinfo(3) blocks while querying DNS, so it must run on a blocking thread.
+fn lookup_addr(addr: IpAddr) -> Option<String> {
+    let mut host = [0 as libc::c_char; MAX_HOST_LEN];
+    let res = match addr {
+        IpAddr::V4(v4) => {
+            // SAFETY: sockaddr_in is a plain C struct and all zero bytes are a valid value.
+            let mut sa: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sa.sin_family = libc::AF_INET as libc::sa_family_t;
+            sa.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+            // SAFETY: sa is an initialized sockaddr_in of the given length,
+            // and host is a writable buffer of MAX_HOST_LEN bytes. No service is requested.
+            unsafe {
+                libc::getnameinfo(
+                    &sa as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    host.as_mut_ptr(),
+                    MAX_HOST_LEN as libc::socklen_t,
+                    std::ptr::null_mut(),
+                    0,
+                    libc::NI_NAMEREQD,
+                )
+            }
+        }
+        IpAddr::V6(v6) => {
+            // SAFETY: sockaddr_in6 is a plain C struct and all zero bytes are a valid value.
+            let mut sa: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sa.sin6_addr.s6_addr = v6.octets();
+            // SAFETY: sa is an initialized sockaddr_in6 of the given length,
+            // and host is a writable buffer of MAX_HOST_LEN bytes. No service is requested.
+            unsafe {
+                libc::getnameinfo(
+                    &sa as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                    host.as_mut_ptr(),
+                    MAX_HOST_LEN as libc::socklen_t,
+                    std::ptr::null_mut(),
+                    0,
+                    libc::NI_NAMEREQD,
+                )
+            }
+        }
+    };
+    if res != 0 {
+        return None;
+    }
+    // SAFETY: getnameinfo succeeded, so host holds a NUL terminated string within the buffer.
+    let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
@@ -8,12 +8,15 @@ use conntrack::{
 };
 use display::{json::JsonDisplay, stats::StatsRow, table::TableDisplay, Display};
 use futures::TryStreamExt;
+use serde::Serialize;
 
 use crate::{
     cmd::{DisplayRunner, Runner},
-    config::{Family, Output},
+    config::{Family, GlobalOption, Output, Table},
+    count::count,
     error::Error,
     executor::{Executor, Operation, OperationType},
+    sysctl,
 };
 
 #[derive(Debug, Parser)]
@@ -26,28 +29,48 @@ pub struct StatsCmd {
         help = "Output format (\"table\", \"json\")"
     )]
     output: Output,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Show statistics only for the given CPUs. e.g. \"0,2,4\""
+    )]
+    cpu: Vec<u16>,
+    #[arg(
+        long,
+        help = "Show the utilization of the table from the number of entries and nf_conntrack_max after the statistics"
+    )]
+    utilization: bool,
     #[arg(long, help = "Don't print the header")]
     no_header: bool,
 }
 
 #[async_trait]
 impl Runner for StatsCmd {
-    async fn run(&self) -> Result<(), Error> {
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
         let op = StatsOperation::new();
-        let executor = Executor::new(op);
+        let executor = Executor::new(op, opt);
         let ct = executor.exec().await?;
 
         match self.output() {
-            Output::Table => {
+            // The classic format is only for flows.
+            Output::Table | Output::Classic => {
                 let stats_row = StatsRow::new();
                 let table_display = TableDisplay::new(tokio::io::stdout(), stats_row);
-                self.process(ct, table_display).await
+                self.process(ct, table_display).await?;
             }
             Output::Json => {
                 let json_display = JsonDisplay::new(tokio::io::stdout());
-                self.process(ct, json_display).await
+                self.process(ct, json_display).await?;
             }
         }
+        if self.utilization {
+            self.show_utilization(opt).await?;
+        }
+        Ok(())
+    }
+
+    fn json_output(&self) -> bool {
+        self.output.eq(&Output::Json)
     }
 }
 
@@ -65,17 +88,52 @@ impl DisplayRunner for StatsCmd {
         &self,
         mut ct: Conntrack<NfConntrackSocket>,
         mut display: D,
-    ) -> Result<(), Error> {
+    ) -> Result<usize, Error> {
         if self.output().ne(&Output::Json) && !self.no_header() {
             display.header().await.map_err(Error::Display)?;
         }
+        let mut count = 0;
         while let Some(events) = ct.try_next().await.map_err(Error::Conntrack)? {
             for event in events.iter() {
                 if let Event::Stats(stats) = event {
+                    // The kernel dumps statistics for all CPUs. So, filter them here.
+                    if !self.cpu.is_empty() && !self.cpu.contains(&stats.cpu) {
+                        continue;
+                    }
                     display.consume(stats).await.map_err(Error::Display)?;
+                    count += 1;
                 }
             }
         }
+        display.flush().await.map_err(Error::Display)?;
+        Ok(count)
+    }
+}
+
+// The utilization is not a per-CPU counter, so it is shown separately from the table.
+#[derive(Debug, Serialize)]
+struct Utilization {
+    count: u32,
+    max: u32,
+    utilization: f64,
+}
+
+impl StatsCmd {
+    // The count is requested by netlink to follow --netns like healthcheck.
+    async fn show_utilization(&self, opt: &GlobalOption) -> Result<(), Error> {
+        let count = count(Table::Conntrack, opt).await?;
+        let max = sysctl::conntrack_max().await?;
+        let u = Utilization {
+            count,
+            max,
+            utilization: sysctl::utilization(count, max),
+        };
+        match self.output {
+            Output::Json => println!("{}", serde_json::to_string(&u).unwrap_or_default()),
+            Output::Table | Output::Classic => {
+                println!("UTILIZATION {:.1}% ({}/{})", u.utilization, u.count, u.max)
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,28 @@
+use std::path::Path;
+
+use crate::error::Error;
+
+// Sysctls are read in the network namespace of this process even when --netns is given.
+const CONNTRACK_MAX: &str = "/proc/sys/net/netfilter/nf_conntrack_max";
+
+// The maximum number of entries in the conntrack table.
+pub(crate) async fn conntrack_max() -> Result<u32, Error> {
+    read(Path::new(CONNTRACK_MAX)).await
+}
+
+// The percentage of the table in use. A zero max is treated as an empty table.
+pub(crate) fn utilization(count: u32, max: u32) -> f64 {
+    if max == 0 {
+        return 0.0;
+    }
+    count as f64 / max as f64 * 100.0
+}
+
+async fn read(path: &Path) -> Result<u32, Error> {
+    let s = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| Error::Sysctl(format!("{}: {e}", path.display())))?;
+    s.trim()
+        .parse()
+        .map_err(|e| Error::Sysctl(format!("{}: {e}", path.display())))
+}
@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use clap::{ArgGroup, Parser};
+use conntrack::request::{Direction, Request, RequestMeta, RequestOperation, UpdateParams};
+
+use crate::{
+    cmd::Runner,
+    config::{parse_single_family, Family, GlobalOption, Protocol},
+    error::Error,
+    executor::{Executor, Operation, OperationType},
+    get::TupleArgs,
+};
+
+#[derive(Debug, Parser)]
+#[command(about = "Update the mark or the timeout of a connection tracking entry")]
+#[command(group(ArgGroup::new("values").required(true).multiple(true).args(["mark", "timeout"])))]
+pub struct UpdateCmd {
+    #[arg(
+        short,
+        long,
+        default_value = "ipv4",
+        value_parser = parse_single_family,
+        help = "L3 layer protocol (\"ipv4\", \"ipv6\")"
+    )]
+    family: Family,
+    #[arg(
+        short,
+        long,
+        default_value = "any",
+        help = "L4 layer protocol (\"any\", \"tcp\", \"udp\" or protocol number e.g. 47)"
+    )]
+    protocol: Protocol,
+    #[command(flatten)]
+    tuple: TupleArgs,
+    #[arg(long, help = "New mark of the entry")]
+    mark: Option<u32>,
+    #[arg(long, help = "New timeout of the entry in seconds")]
+    timeout: Option<u32>,
+}
+
+#[async_trait]
+impl Runner for UpdateCmd {
+    async fn run(&self, opt: &GlobalOption) -> Result<(), Error> {
+        let directed_tuple = self.tuple.get_tuples()?;
+        let op = UpdateOperation::new(
+            self.family,
+            self.protocol,
+            directed_tuple,
+            self.mark,
+            self.timeout,
+        );
+
+        let executor = Executor::new(op, opt);
+        let mut ct = executor.exec().await?;
+        // The kernel replies only an acknowledgement.
+        ct.recv_once().await.map_err(Error::Conntrack)?;
+
+        eprintln!("1 flow entries have been updated.");
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct UpdateOperation {
+    family: Family,
+    protocol: Protocol,
+    tuple: Direction,
+    mark: Option<u32>,
+    timeout: Option<u32>,
+}
+
+impl UpdateOperation {
+    fn new(
+        family: Family,
+        protocol: Protocol,
+        directed_tuple: Direction,
+        mark: Option<u32>,
+        timeout: Option<u32>,
+    ) -> UpdateOperation {
+        UpdateOperation {
+            family,
+            protocol,
+            tuple: directed_tuple,
+            mark,
+            timeout,
+        }
+    }
+}
+
+impl Operation for UpdateOperation {
+    fn request(&self) -> Result<Request, Error> {
+        let meta = RequestMeta::default().family(self.family.into());
+        let mut params = UpdateParams::new(self.protocol.tuple_protocol(), self.tuple.clone());
+        if let Some(mark) = self.mark {
+            params = params.mark(mark);
+        }
+        if let Some(timeout) = self.timeout {
+            params = params.timeout(timeout);
+        }
+        Ok(Request::new(meta, RequestOperation::Update(params)))
+    }
+
+    fn typ(&self) -> OperationType {
+        OperationType::Update
+    }
+}
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use clap::Parser;
 
-use crate::{cmd::Runner, error::Error};
+use crate::{cmd::Runner, config::GlobalOption, error::Error};
 
 build_info::build_info!(fn build_info);
 
@@ -19,7 +19,7 @@ pub(super) struct VersionCmd {
 
 #[async_trait]
 impl Runner for VersionCmd {
-    async fn run(&self) -> Result<(), Error> {
+    async fn run(&self, _opt: &GlobalOption) -> Result<(), Error> {
         if self.detail {
             println!("{:#?}", build_info());
         } else {